@@ -1,13 +1,13 @@
-use criterion::{black_box, criterion_group, criterion_main, Criterion, SamplingMode};
+use criterion::{criterion_group, criterion_main, Criterion};
 use itertools::Itertools;
 use ply_rs::parser;
 use ply_rs::ply;
+use rand::seq::SliceRandom;
 use rand::Rng;
-use std::{fs::create_dir_all, io::BufWriter, path::Path, time::SystemTime};
 use uniform_grid::point_object::PointObject;
-use uniform_grid::{spiral_cells, UniformGrid};
+use uniform_grid::{spiral_cells, UniformGrid, UniformGridBuilder};
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 struct Vertex {
     x: f32,
     y: f32,
@@ -74,16 +74,10 @@ fn remove_multiple_random<T>(vec: &mut Vec<T>, count: usize) -> Vec<T> {
     removed
 }
 
-pub fn bench_dragon(c: &mut Criterion) {
+pub fn bench_dragon(_c: &mut Criterion) {
     use std::time::Instant;
 
     let spiral = spiral_cells::spiral_cells(100);
-    let (idx, cell) = spiral
-        .iter()
-        .enumerate()
-        .max_by_key(|(i, sc)| sc.stop_cell_index1 - i)
-        .unwrap();
-
     let diffs = spiral
         .iter()
         .enumerate()
@@ -91,11 +85,6 @@ pub fn bench_dragon(c: &mut Criterion) {
         .take(100)
         .collect_vec();
     println!("Diffs: {:#?}", diffs);
-    // println!("Stop-cell max diff: {}", (cell.stop_cell_index1 - idx));
-    // println!(
-    //     "Stop-cell mean diff: {}",
-    //     (spiral_sum as f32 / spiral.len() as f32)
-    // );
 
     let mut vertices = ply_vertices("./benches/data/dragon_vrip.ply");
     let queries = remove_multiple_random(&mut vertices, 10000);
@@ -125,5 +114,178 @@ pub fn bench_dragon(c: &mut Criterion) {
     println!("Query time offset from surface: {:.2?}", elapsed);
 }
 
-criterion_group!(benches, bench_dragon);
+/// Returns `count` points scattered uniformly within `cluster_count` small,
+/// dense clusters whose centers are scattered uniformly within a unit cube.
+fn clustered_vertices(cluster_count: usize, count: usize, cluster_width: f32) -> Vec<Vertex> {
+    let mut rng = rand::thread_rng();
+    let centers = (0..cluster_count)
+        .map(|_| Vertex::new(rng.gen(), rng.gen(), rng.gen()))
+        .collect_vec();
+    (0..count)
+        .map(|i| {
+            let c = &centers[i % cluster_count];
+            let half = cluster_width / 2.0;
+            Vertex::new(
+                c.x + rng.gen_range(-half..half),
+                c.y + rng.gen_range(-half..half),
+                c.z + rng.gen_range(-half..half),
+            )
+        })
+        .collect_vec()
+}
+
+pub fn bench_clustered(_c: &mut Criterion) {
+    use std::time::Instant;
+
+    let mut vertices = clustered_vertices(20, 500000, 0.02);
+    let queries = remove_multiple_random(&mut vertices, 10000);
+    let spiral = spiral_cells::read("./resources/spiral_100");
+    let uniform_grid = UniformGrid::new(vertices, 1.0, spiral);
+
+    let now = Instant::now();
+    queries.iter().for_each(|q| {
+        let _ = uniform_grid.nearest_neighbor(q.position());
+    });
+    let elapsed = now.elapsed();
+    println!("Query time in dense clusters: {:.2?}", elapsed);
+}
+
+pub fn bench_near_boundary(_c: &mut Criterion) {
+    use std::time::Instant;
+
+    let mut rng = rand::thread_rng();
+    let vertices = (0..100000)
+        .map(|_| Vertex::new(rng.gen(), rng.gen(), rng.gen()))
+        .collect_vec();
+
+    // Queries that lie just inside one of the faces of the unit cube.
+    let queries = (0..10000)
+        .map(|i| {
+            let mut position = [rng.gen(), rng.gen(), rng.gen()];
+            position[i % 3] = if i % 2 == 0 { 0.001 } else { 0.999 };
+            Vertex::new(position[0], position[1], position[2])
+        })
+        .collect_vec();
+
+    // A large scale leaves most cells empty, so most queries need a spiral search.
+    let spiral = spiral_cells::read("./resources/spiral_100");
+    let uniform_grid = UniformGrid::new(vertices, 2.0, spiral);
+
+    let now = Instant::now();
+    queries.iter().for_each(|q| {
+        let _ = uniform_grid.nearest_neighbor(q.position());
+    });
+    let elapsed = now.elapsed();
+    println!("Query time near boundary: {:.2?}", elapsed);
+
+    // Count the cells that the searches examine, against the cells in the rings
+    // that they reach. Cells in those rings that are outside of the grid are
+    // skipped without being checked, which is most of them near a face.
+    let (cells_examined, cells_in_rings) = queries
+        .iter()
+        .map(|q| {
+            let (_, cells_examined) = uniform_grid.nearest_neighbor_counted(q.position());
+            let rings = uniform_grid
+                .nearest_neighbor_traced(q.position())
+                .map_or(0, |traced| traced.rings_searched);
+            (cells_examined, (2 * rings + 1).pow(3))
+        })
+        .fold((0, 0), |(examined, in_rings), (e, r)| {
+            (examined + e, in_rings + r)
+        });
+    println!(
+        "Cells examined near boundary: {} of {} in the rings searched",
+        cells_examined, cells_in_rings
+    );
+}
+
+pub fn bench_no_table(_c: &mut Criterion) {
+    use std::time::Instant;
+
+    let mut rng = rand::thread_rng();
+    let mut vertices = (0..200000)
+        .map(|_| Vertex::new(rng.gen(), rng.gen(), rng.gen()))
+        .collect_vec();
+    let queries = remove_multiple_random(&mut vertices, 10000);
+    let spiral = spiral_cells::read("./resources/spiral_100");
+    let uniform_grid = UniformGrid::new(vertices, 1.0, spiral);
+
+    let now = Instant::now();
+    queries.iter().for_each(|q| {
+        let _ = uniform_grid.nearest_neighbor(q.position());
+    });
+    let elapsed = now.elapsed();
+    println!("Query time with spiral table: {:.2?}", elapsed);
+
+    let now = Instant::now();
+    queries.iter().for_each(|q| {
+        let _ = uniform_grid.nearest_neighbor_no_table(q.position());
+    });
+    let elapsed = now.elapsed();
+    println!("Query time without spiral table: {:.2?}", elapsed);
+}
+
+pub fn bench_large_cells(_c: &mut Criterion) {
+    use std::time::Instant;
+
+    let mut rng = rand::thread_rng();
+    let mut vertices = (0..500000)
+        .map(|_| Vertex::new(rng.gen(), rng.gen(), rng.gen()))
+        .collect_vec();
+    let queries = remove_multiple_random(&mut vertices, 10000);
+
+    // A small scale puts about a hundred points in each cell, so most of the query
+    // time is spent computing distances to the points in a cell. Run with and
+    // without the `simd` feature to compare. The `simd` feature only pays off when
+    // 8-wide vectors are available, such as with `-C target-cpu=native` on x86-64
+    // CPUs that support AVX.
+    let spiral = spiral_cells::read("./resources/spiral_100");
+    let uniform_grid = UniformGrid::new(vertices, 0.2, spiral);
+
+    let now = Instant::now();
+    queries.iter().for_each(|q| {
+        let _ = uniform_grid.nearest_neighbor(q.position());
+    });
+    let elapsed = now.elapsed();
+    println!("Query time with large cells: {:.2?}", elapsed);
+}
+
+pub fn bench_sorted_fill(_c: &mut Criterion) {
+    use std::time::Instant;
+
+    // The vertices in the file are roughly in scan order, so neighboring vertices
+    // tend to land in the same cells. Shuffling them makes the cells fill in a
+    // random order, which is where sorting by cell should help.
+    let vertices = ply_vertices("./benches/data/dragon_vrip.ply");
+    let mut shuffled = vertices.clone();
+    shuffled.shuffle(&mut rand::thread_rng());
+    let spiral = spiral_cells::read("./resources/spiral_100");
+
+    for (name, vertices) in [("file order", vertices), ("shuffled", shuffled)] {
+        for sorted_fill in [false, true] {
+            let vertices = vertices.clone();
+            let now = Instant::now();
+            let _ = UniformGridBuilder::new()
+                .scale(1.19)
+                .spiral_cells(spiral.clone())
+                .sorted_fill(sorted_fill)
+                .build(vertices);
+            let elapsed = now.elapsed();
+            println!(
+                "Pre-Processing time ({}, sorted fill {}): {:.2?}",
+                name, sorted_fill, elapsed
+            );
+        }
+    }
+}
+
+criterion_group!(
+    benches,
+    bench_dragon,
+    bench_clustered,
+    bench_near_boundary,
+    bench_no_table,
+    bench_large_cells,
+    bench_sorted_fill
+);
 criterion_main!(benches);
@@ -3,7 +3,10 @@ use crate::{
     point_object::PointObject,
 };
 
+/// An axis-aligned box in 3-dimensional space.
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct BoundingBox {
+    /// The minimum corner of the box.
     pub min: [f32; 3],
     pub x_width: f32,
     pub y_width: f32,
@@ -11,9 +14,20 @@ pub struct BoundingBox {
 }
 
 impl BoundingBox {
+    /// Returns the smallest box that contains all of the points.
     pub fn new<T>(points: &[T]) -> Self
     where
         T: PointObject,
+    {
+        Self::from_positions(points.iter().map(PointObject::position))
+    }
+
+    /// Returns the smallest box that contains all of the positions.
+    ///
+    /// Positions with a NaN coordinate are left out.
+    pub(crate) fn from_positions<I>(positions: I) -> Self
+    where
+        I: IntoIterator<Item = [f32; 3]>,
     {
         let mut x_min = f32::INFINITY;
         let mut y_min = f32::INFINITY;
@@ -22,13 +36,16 @@ impl BoundingBox {
         let mut y_max = f32::NEG_INFINITY;
         let mut z_max = f32::NEG_INFINITY;
 
-        for p in points {
-            x_min = min_f32(p.position()[0], x_min);
-            y_min = min_f32(p.position()[1], y_min);
-            z_min = min_f32(p.position()[2], z_min);
-            x_max = max_f32(p.position()[0], x_max);
-            y_max = max_f32(p.position()[1], y_max);
-            z_max = max_f32(p.position()[2], z_max);
+        for p in positions
+            .into_iter()
+            .filter(|p| !p.iter().any(|c| c.is_nan()))
+        {
+            x_min = min_f32(p[0], x_min);
+            y_min = min_f32(p[1], y_min);
+            z_min = min_f32(p[2], z_min);
+            x_max = max_f32(p[0], x_max);
+            y_max = max_f32(p[1], y_max);
+            z_max = max_f32(p[2], z_max);
         }
 
         BoundingBox {
@@ -38,4 +55,59 @@ impl BoundingBox {
             z_width: z_max - z_min,
         }
     }
+
+    /// Returns the maximum corner of the box.
+    pub fn max(&self) -> [f32; 3] {
+        [
+            self.min[0] + self.x_width,
+            self.min[1] + self.y_width,
+            self.min[2] + self.z_width,
+        ]
+    }
+
+    /// Returns the point at the center of the box.
+    pub fn center(&self) -> [f32; 3] {
+        [
+            self.min[0] + self.x_width / 2.0,
+            self.min[1] + self.y_width / 2.0,
+            self.min[2] + self.z_width / 2.0,
+        ]
+    }
+
+    /// Returns the volume of the box.
+    pub fn volume(&self) -> f32 {
+        self.x_width * self.y_width * self.z_width
+    }
+
+    /// Returns true if the point is inside the box. Points on the faces of
+    /// the box are inside it.
+    pub fn contains(&self, point: [f32; 3]) -> bool {
+        let max = self.max();
+        (0..3).all(|axis| self.min[axis] <= point[axis] && point[axis] <= max[axis])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn contains_points_on_faces() {
+        let bb = BoundingBox::new(&[[-1.0, 0.0, 2.0], [3.0, 2.0, 2.5]]);
+        assert!(bb.contains([-1.0, 0.0, 2.0]));
+        assert!(bb.contains([3.0, 2.0, 2.5]));
+        assert!(bb.contains([3.0, 1.0, 2.25]));
+        assert!(bb.contains(bb.center()));
+        assert!(!bb.contains([3.001, 1.0, 2.25]));
+        assert!(!bb.contains([0.0, -0.001, 2.25]));
+        assert!(!bb.contains([0.0, 1.0, f32::NAN]));
+    }
+
+    #[test]
+    fn volume_of_known_box() {
+        let bb = BoundingBox::new(&[[-1.0, 0.0, 2.0], [3.0, 2.0, 2.5], [0.0, 1.0, 2.2]]);
+        assert_eq!(bb.max(), [3.0, 2.0, 2.5]);
+        assert_eq!(bb.center(), [1.0, 1.0, 2.25]);
+        assert_eq!(bb.volume(), 4.0);
+    }
 }
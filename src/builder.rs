@@ -0,0 +1,563 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    offset3::{Axis, BucketBoundary},
+    point_object::PointObject,
+    spiral_cells::{self, SpiralCell},
+    uniform_grid::{BuildPasses, SerialPasses},
+    GridTooLarge, UniformGrid,
+};
+
+#[cfg(feature = "rayon")]
+use crate::uniform_grid::ParallelPasses;
+
+/// Options that control how a uniform grid is constructed.
+///
+/// The options are kept by the grid so that it can be rebuilt the same way
+/// when points are added outside of its bounds or grids are merged.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub(crate) struct GridOptions {
+    /// The number of cells along each dimension of the grid is roughly
+    /// `scale` times the cube root of the number of points.
+    pub scale: f32,
+
+    /// Whether points are sorted into a canonical order before they're
+    /// bucketed.
+    pub canonical_order: bool,
+
+    /// The minimum and maximum corners of the box that's used for periodic
+    /// boundary conditions, if the grid is periodic.
+    pub periodic_box: Option<([f32; 3], [f32; 3])>,
+
+    /// The desired mean number of points in each non-empty cell, which
+    /// overrides `scale` if it's set.
+    pub target_occupancy: Option<f32>,
+
+    /// The minimum number of cells along each dimension of the grid.
+    pub min_dimensions: (usize, usize, usize),
+
+    /// The maximum number of cells along each dimension of the grid.
+    pub max_dimension: Option<usize>,
+
+    /// The axis of each point's position that becomes each axis of the grid.
+    pub axis_order: [Axis; 3],
+
+    /// Whether each cell keeps a copy of the positions of its points.
+    pub cache_positions: bool,
+
+    /// The rule that decides which cell a point on a cell boundary is
+    /// bucketed into.
+    pub bucket_boundary: BucketBoundary,
+
+    /// The factor by which the extents of the bounding box are enlarged to
+    /// give the extents that the grid's cells cover.
+    pub boundary_epsilon: f32,
+
+    /// Whether the points are sorted by cell before the cells are filled
+    /// during construction.
+    pub sorted_fill: bool,
+
+    /// The shape of the grid's cells.
+    pub cell_size: CellSize,
+
+    /// Whether the positions that are cached in each cell are stored at half
+    /// precision.
+    ///
+    /// This is kept without the `half` feature, and ignored there, so that
+    /// exported indices have the same shape with and without the feature.
+    pub half_precision_positions: bool,
+}
+
+impl Default for GridOptions {
+    fn default() -> Self {
+        Self {
+            scale: 1.0,
+            canonical_order: false,
+            periodic_box: None,
+            target_occupancy: None,
+            min_dimensions: (1, 1, 1),
+            max_dimension: None,
+            axis_order: [Axis::X, Axis::Y, Axis::Z],
+            cache_positions: true,
+            bucket_boundary: BucketBoundary::Upper,
+            boundary_epsilon: 1.01,
+            sorted_fill: false,
+            cell_size: CellSize::Cubic,
+            half_precision_positions: false,
+        }
+    }
+}
+
+/// The shape of the cells of a uniform grid.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize, Serialize)]
+pub enum CellSize {
+    /// The cells are cubes, whose width is chosen from the number of points
+    /// and the grid's scale or target occupancy.
+    #[default]
+    Cubic,
+
+    /// The cells are boxes with the given widths along the grid's axes.
+    PerAxis([f32; 3]),
+}
+
+impl GridOptions {
+    /// Returns a point's position with its axes in the grid's order.
+    pub fn grid_position(&self, position: [f32; 3]) -> [f32; 3] {
+        self.axis_order.map(|axis| position[axis.index()])
+    }
+}
+
+/// Builder for configuring and constructing a `UniformGrid`.
+#[derive(Debug, Clone, Default)]
+pub struct UniformGridBuilder {
+    options: GridOptions,
+    spiral_cells: Option<Vec<SpiralCell>>,
+    spiral_extent: Option<usize>,
+}
+
+impl UniformGridBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the scale that determines the number of cells in the grid.
+    ///
+    /// The number of cells in the grid is roughly `scale` cubed times the
+    /// number of points, so a scale below `1.0` gives fewer cells than points.
+    /// The grid always has at least one cell, so a scale that's so small that
+    /// it would leave no cells, including zero and negative scales, gives a
+    /// grid with a single cell, which makes every query a brute-force search.
+    /// Defaults to `1.0`.
+    pub fn scale(mut self, scale: f32) -> Self {
+        self.options.scale = scale;
+        self
+    }
+
+    /// Sets the spiral cells that are used to search outward from a query
+    /// cell.
+    ///
+    /// If spiral cells aren't provided, then spiral cells that cover the
+    /// constructed grid are generated.
+    pub fn spiral_cells(mut self, spiral_cells: Vec<SpiralCell>) -> Self {
+        self.spiral_cells = Some(spiral_cells);
+        self
+    }
+
+    /// Sets the number of cells, along each axis, that generated spiral cells
+    /// extend outward from the center cell, including the center cell itself.
+    ///
+    /// Spiral cells that cover the whole of a large grid can take a long time
+    /// to generate and a lot of memory to keep. With a smaller extent, the
+    /// nearest neighbor of a query point whose nearest points are further
+    /// away than the spiral cells reach may be missed, which
+    /// `UniformGrid::nearest_neighbor_traced` reports, while
+    /// `UniformGrid::nearest_neighbor_no_table` is exact regardless. Ignored
+    /// if spiral cells are provided with `spiral_cells`. Defaults to covering
+    /// the grid's widest dimension.
+    pub fn spiral_extent(mut self, spiral_extent: usize) -> Self {
+        self.spiral_extent = Some(spiral_extent);
+        self
+    }
+
+    /// Sets whether points are sorted into a canonical order before they're
+    /// bucketed into cells.
+    ///
+    /// When enabled, points are sorted by the Morton code of their position
+    /// within the bounding box, with ties broken by the bits of their
+    /// coordinates. The same set of points then produces the same grid, with
+    /// the same point indices, regardless of the order in which the points
+    /// are given. Points with identical positions keep their relative input
+    /// order. Points that are later added with `extend` are appended without
+    /// being sorted unless the grid needs to be rebuilt. Defaults to `false`.
+    pub fn canonical_order(mut self, canonical_order: bool) -> Self {
+        self.options.canonical_order = canonical_order;
+        self
+    }
+
+    /// Makes the grid periodic, so that space wraps around at the faces of the
+    /// box with the given minimum and maximum corners.
+    ///
+    /// A point near one face of the box is then considered close to points
+    /// near the opposite face, and nearest-neighbor queries measure the
+    /// distance from the query point to the nearest periodic image of each
+    /// point. Points and query points outside the box are wrapped into it.
+    /// The nearest-neighbor and radius queries, including the batch and metric
+    /// queries, `nearest_neighbor_within`, `two_nearest`, and `knn_graph`,
+    /// take periodicity into account. The queries that only look into the cells
+    /// around the query cell, `nearest_in_local_block` and
+    /// `nearest_per_neighbor_cell`, ignore it, as their documentation notes.
+    pub fn periodic(mut self, box_min: [f32; 3], box_max: [f32; 3]) -> Self {
+        self.options.periodic_box = Some((box_min, box_max));
+        self
+    }
+
+    /// Sets the desired mean number of points in each non-empty cell.
+    ///
+    /// A large `scale` can produce many more cells than points, which wastes
+    /// memory on empty cells without making queries any faster. When a target
+    /// occupancy is set, `scale` is ignored, and the cell width is instead
+    /// chosen so that the mean number of points in each non-empty cell, as
+    /// reported by `UniformGrid::mean_occupancy`, is close to the target.
+    /// The grid never gets more than about eight cells for each point, so
+    /// targets close to `1.0`, and point sets with many duplicate positions,
+    /// may end up with a higher occupancy than the target. Defaults to
+    /// `None`.
+    pub fn target_occupancy(mut self, target_occupancy: f32) -> Self {
+        self.options.target_occupancy = Some(target_occupancy);
+        self
+    }
+
+    /// Sets the desired mean number of points in each non-empty cell to a
+    /// whole number of points. See `target_occupancy`.
+    pub fn target_points_per_cell(self, target_points_per_cell: usize) -> Self {
+        self.target_occupancy(target_points_per_cell as f32)
+    }
+
+    /// Sets the minimum number of cells along each dimension of the grid,
+    /// regardless of the number of points.
+    ///
+    /// Small point sets otherwise produce grids with very few cells, which
+    /// makes queries little better than a brute-force search. When the
+    /// minimum is larger than the number of cells that `scale` calls for, the
+    /// cells are shrunk so that the bounding box spans at least the minimum
+    /// number of cells along each dimension. Defaults to `(1, 1, 1)`.
+    pub fn min_dimensions(mut self, min_dimensions: (usize, usize, usize)) -> Self {
+        self.options.min_dimensions = min_dimensions;
+        self
+    }
+
+    /// Sets the axis of each point's position that becomes each axis of the
+    /// grid.
+    ///
+    /// The grid's `i`th axis is the point positions' `axis_order[i]` axis. For
+    /// example, with an order of `[Axis::X, Axis::Z, Axis::Y]`, a grid of Z-up
+    /// points can be queried with Y-up query points. The positions of the
+    /// points are remapped as they're bucketed, and everything else that the
+    /// grid takes or returns, such as query points, the periodic box, the
+    /// bounding box, and cell offsets, uses the grid's axes. Squared distances
+    /// are the same in either order. Defaults to `[Axis::X, Axis::Y, Axis::Z]`.
+    ///
+    /// Panics if an axis appears more than once.
+    pub fn axis_order(mut self, axis_order: [Axis; 3]) -> Self {
+        assert!(
+            axis_order[0] != axis_order[1]
+                && axis_order[0] != axis_order[2]
+                && axis_order[1] != axis_order[2],
+            "axis order must contain each axis exactly once"
+        );
+        self.options.axis_order = axis_order;
+        self
+    }
+
+    /// Sets whether each cell keeps a copy of the positions of its points.
+    ///
+    /// Copies of the positions let queries scan a cell without reading the
+    /// grid's array of positions, which is in the order of the points rather
+    /// than of the cells. Without the copies, each cell only stores the
+    /// indices of its points, which saves 16 bytes for each point on 64-bit
+    /// targets, but queries are slower since they look up each point's
+    /// position in that array. If positions
+    /// aren't cached, then `half_precision_positions` has no effect. Defaults
+    /// to `true`.
+    pub fn cache_positions(mut self, cache_positions: bool) -> Self {
+        self.options.cache_positions = cache_positions;
+        self
+    }
+
+    /// Sets the rule that decides which cell a point is bucketed into when it
+    /// lies exactly on the boundary between two cells.
+    ///
+    /// By default, such a point is bucketed into the cell above the boundary,
+    /// so points on a regular lattice that lines up with the cells all land on
+    /// the same side, which skews per-cell counts such as those reported by
+    /// `occupancy_volume`. Query points are located with the same rule, and
+    /// query results don't depend on it. Defaults to `BucketBoundary::Upper`.
+    pub fn bucket_boundary(mut self, bucket_boundary: BucketBoundary) -> Self {
+        self.options.bucket_boundary = bucket_boundary;
+        self
+    }
+
+    /// Sets the factor by which the extents of the bounding box are enlarged
+    /// to give the extents that the grid's cells cover.
+    ///
+    /// Points on a maximum face of the bounding box are always bucketed into
+    /// the last cell along that axis, so the enlargement isn't needed to keep
+    /// them inside the grid. With a factor of `1.0`, the cells line up exactly
+    /// with the bounding box, which makes cell assignments match tools that
+    /// use the exact bounds. Defaults to `1.01`.
+    ///
+    /// Panics if the factor is less than `1.0` or NaN, since the cells
+    /// wouldn't cover the bounding box and some points would be left outside
+    /// of every cell.
+    pub fn boundary_epsilon(mut self, boundary_epsilon: f32) -> Self {
+        assert!(
+            boundary_epsilon >= 1.0,
+            "boundary epsilon must be at least 1.0"
+        );
+        self.options.boundary_epsilon = boundary_epsilon;
+        self
+    }
+
+    /// Sets the shape of the grid's cells.
+    ///
+    /// Cubic cells suit points that are spread about evenly along every axis.
+    /// Points that are much denser along some axes than others, such as
+    /// layers that are far apart, fill cubic cells unevenly, and
+    /// `CellSize::PerAxis` fits the cells to their spacing instead. The
+    /// widths are used as they're given, so `scale` and `target_occupancy`
+    /// are ignored, but the width along an axis still shrinks to respect
+    /// `min_dimensions`. Queries measure
+    /// Euclidean distances either way, but with cells that aren't cubes,
+    /// nearest-neighbor queries search outward from the query cell one ring
+    /// of cells at a time rather than by the spiral cells, whose distances
+    /// assume cubes. Defaults to `CellSize::Cubic`.
+    ///
+    /// Panics if a width along some axis isn't positive and finite.
+    pub fn cell_size(mut self, cell_size: CellSize) -> Self {
+        if let CellSize::PerAxis(widths) = cell_size {
+            assert!(
+                widths.iter().all(|&width| width > 0.0 && width.is_finite()),
+                "cell widths must be positive and finite"
+            );
+        }
+        self.options.cell_size = cell_size;
+        self
+    }
+
+    /// Sets whether the points are sorted by the cells that they're bucketed
+    /// into before the cells are filled during construction.
+    ///
+    /// Otherwise, the cells are filled in the order of the points, which jumps
+    /// between cells at random for unordered points and can thrash the cache
+    /// for large grids. Sorting takes a counting sort and an extra index for
+    /// each point while the grid is constructed. The constructed grid is the
+    /// same either way. Defaults to `false`.
+    pub fn sorted_fill(mut self, sorted_fill: bool) -> Self {
+        self.options.sorted_fill = sorted_fill;
+        self
+    }
+
+    /// Sets whether the positions that are cached in each cell are stored as
+    /// half-precision floats, which roughly halves the memory used by the
+    /// cache.
+    ///
+    /// Positions are stored relative to the minimum corner of their cell, so
+    /// each coordinate is accurate to within about `cell_width / 2048`. As a
+    /// result, a nearest-neighbor query may return a point whose distance to
+    /// the query is larger than the true nearest neighbor's by up to about
+    /// `cell_width / 1000`. The distance that's returned for the found point is
+    /// always computed from its exact position. Defaults to `false`.
+    #[cfg(feature = "half")]
+    pub fn half_precision_positions(mut self, half_precision_positions: bool) -> Self {
+        self.options.half_precision_positions = half_precision_positions;
+        self
+    }
+
+    /// Constructs a uniform grid that contains the given points.
+    ///
+    /// Panics if the grid would have too many cells. See `try_build`.
+    pub fn build<T>(self, points: Vec<T>) -> UniformGrid<T>
+    where
+        T: PointObject,
+    {
+        self.try_build(points)
+            .unwrap_or_else(|error| panic!("{}", error))
+    }
+
+    /// Constructs a uniform grid that contains the given points, or returns
+    /// an error if the grid would have too many cells.
+    ///
+    /// A very large scale or minimum number of cells can ask for more cells
+    /// than could ever be allocated, or for a number of cells that overflows
+    /// `usize`. The number of cells is checked before anything is allocated
+    /// for them, so such a grid fails cleanly instead of aborting. The points
+    /// are dropped when an error is returned.
+    pub fn try_build<T>(self, points: Vec<T>) -> Result<UniformGrid<T>, GridTooLarge>
+    where
+        T: PointObject,
+    {
+        self.try_build_with(points, &SerialPasses)
+    }
+
+    /// Constructs a uniform grid like `build`, but counts the points in each
+    /// cell and fills the cells in parallel.
+    ///
+    /// The grid is the same as the one that `build` constructs from the same
+    /// points, with the points of each cell in the same order. Panics if the
+    /// grid would have too many cells, like `build`.
+    #[cfg(feature = "rayon")]
+    pub fn build_parallel<T>(self, points: Vec<T>) -> UniformGrid<T>
+    where
+        T: PointObject + Sync,
+    {
+        self.try_build_with(points, &ParallelPasses)
+            .unwrap_or_else(|error| panic!("{}", error))
+    }
+
+    fn try_build_with<T, B>(
+        self,
+        points: Vec<T>,
+        passes: &B,
+    ) -> Result<UniformGrid<T>, GridTooLarge>
+    where
+        T: PointObject,
+        B: BuildPasses<T>,
+    {
+        match (self.spiral_cells, self.spiral_extent) {
+            (Some(spiral_cells), _) => {
+                UniformGrid::try_from_options_with(points, self.options, spiral_cells, passes)
+            }
+            (None, Some(spiral_extent)) => UniformGrid::try_from_options_with(
+                points,
+                self.options,
+                spiral_cells::spiral_cells(spiral_extent),
+                passes,
+            ),
+            (None, None) => UniformGrid::with_generated_spiral_cells(points, self.options, passes),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    #[test]
+    fn boundary_epsilon_of_one_keeps_every_point_in_the_grid() {
+        let points = vec![[0.0; 3], [1.0, 2.0, 3.0], [4.0; 3]];
+        let grid = UniformGridBuilder::new()
+            .boundary_epsilon(1.0)
+            .build(points);
+        assert!(grid.validate().is_ok());
+        assert_eq!(*grid.nearest_neighbor([4.0; 3]).unwrap().0, [4.0; 3]);
+    }
+
+    #[test]
+    #[should_panic(expected = "boundary epsilon must be at least 1.0")]
+    fn boundary_epsilon_below_one_panics() {
+        let _ = UniformGridBuilder::new().boundary_epsilon(0.5);
+    }
+
+    #[test]
+    fn min_dimensions_floors_grid_dimensions() {
+        let points = (0..8)
+            .map(|i| [(i & 1) as f32, ((i >> 1) & 1) as f32, ((i >> 2) & 1) as f32])
+            .collect::<Vec<_>>();
+        let grid = UniformGridBuilder::new()
+            .min_dimensions((4, 4, 4))
+            .build(points);
+        let (x, y, z) = grid.dimensions();
+        assert!(x >= 4 && y >= 4 && z >= 4);
+        assert!(grid.occupancy_volume().0.len() >= 64);
+        assert_eq!(grid.validate(), Ok(()));
+    }
+
+    #[test]
+    fn target_occupancy_picks_cells_for_mean_occupancy() {
+        let mut rng = StdRng::seed_from_u64(611);
+        let points = (0..5000).map(|_| rng.gen()).collect::<Vec<[f32; 3]>>();
+        let grid = UniformGridBuilder::new()
+            .target_occupancy(2.0)
+            .build(points);
+        let mean_occupancy = grid.mean_occupancy();
+        assert!(
+            (1.5..=2.5).contains(&mean_occupancy),
+            "mean occupancy {mean_occupancy}"
+        );
+    }
+
+    #[test]
+    fn swapped_axis_order_takes_query_points_in_grid_axes() {
+        let points = vec![[1.0, 2.0, 3.0], [4.0, 5.0, 6.0], [0.0, 0.0, 10.0]];
+        let grid = UniformGridBuilder::new()
+            .axis_order([Axis::X, Axis::Z, Axis::Y])
+            .build(points.clone());
+        let (found, distance) = grid.nearest_neighbor([0.0, 10.0, 0.0]).unwrap();
+        assert_eq!(*found, [0.0, 0.0, 10.0]);
+        assert_eq!(distance.squared(), 0.0);
+        let (found, _) = grid.nearest_neighbor([1.0, 3.0, 2.0]).unwrap();
+        assert_eq!(*found, [1.0, 2.0, 3.0]);
+        let (found, _) = grid.nearest_neighbor([4.0, 6.0, 5.0]).unwrap();
+        assert_eq!(*found, [4.0, 5.0, 6.0]);
+        assert_eq!(grid.points(), &points[..]);
+    }
+
+    #[test]
+    fn sorted_fill_builds_same_grid_as_naive_fill() {
+        let mut rng = StdRng::seed_from_u64(660);
+        let points = (0..5000).map(|_| rng.gen()).collect::<Vec<[f32; 3]>>();
+        let naive = UniformGridBuilder::new().build(points.clone());
+        let sorted = UniformGridBuilder::new()
+            .sorted_fill(true)
+            .build(points.clone());
+        assert_eq!(sorted.validate(), Ok(()));
+        assert_eq!(sorted.dimensions(), naive.dimensions());
+        assert_eq!(sorted.occupancy_volume(), naive.occupancy_volume());
+        for _ in 0..200 {
+            let query_point: [f32; 3] = rng.gen();
+            assert_eq!(
+                sorted.nearest_neighbor(query_point),
+                naive.nearest_neighbor(query_point)
+            );
+            assert_eq!(
+                sorted.neighbors_within_radius(query_point, 0.1),
+                naive.neighbors_within_radius(query_point, 0.1)
+            );
+        }
+    }
+
+    #[test]
+    fn huge_scale_is_a_clean_error() {
+        let mut rng = StdRng::seed_from_u64(667);
+        let points = (0..1000).map(|_| rng.gen()).collect::<Vec<[f32; 3]>>();
+        match UniformGridBuilder::new()
+            .scale(1.0e9)
+            .try_build(points.clone())
+        {
+            Err(GridTooLarge { dimensions }) => {
+                let (x_width, y_width, z_width) = dimensions;
+                assert!(
+                    (x_width as f64) * (y_width as f64) * (z_width as f64) > (1u64 << 31) as f64
+                );
+            }
+            Ok(_) => panic!("a grid with too many cells was built"),
+        }
+
+        let grid = UniformGridBuilder::new()
+            .scale(2.0)
+            .try_build(points)
+            .unwrap();
+        assert_eq!(grid.validate(), Ok(()));
+    }
+
+    #[test]
+    fn per_axis_cell_size_sets_each_width() {
+        let mut rng = StdRng::seed_from_u64(758);
+        let points = (0..2000)
+            .map(|_| [rng.gen(), rng.gen(), rng.gen::<f32>() * 100.0])
+            .collect::<Vec<[f32; 3]>>();
+        let grid = UniformGridBuilder::new()
+            .cell_size(CellSize::PerAxis([0.1, 0.1, 10.0]))
+            .build(points.clone());
+        assert_eq!(grid.cell_widths(), [0.1, 0.1, 10.0]);
+        assert_eq!(grid.dimensions(), (11, 11, 11));
+        assert_eq!(grid.validate(), Ok(()));
+
+        // A minimum number of cells only shrinks the axes that need it.
+        let grid = UniformGridBuilder::new()
+            .cell_size(CellSize::PerAxis([0.1, 0.1, 10.0]))
+            .min_dimensions((1, 1, 20))
+            .build(points);
+        let [x_width, y_width, z_width] = grid.cell_widths();
+        assert_eq!([x_width, y_width], [0.1, 0.1]);
+        assert!(z_width < 10.0);
+        assert_eq!(grid.dimensions().2, 20);
+    }
+
+    #[test]
+    #[should_panic(expected = "cell widths must be positive and finite")]
+    fn per_axis_cell_size_with_zero_width_panics() {
+        let _ = UniformGridBuilder::new().cell_size(CellSize::PerAxis([1.0, 0.0, 1.0]));
+    }
+}
@@ -0,0 +1,451 @@
+#[cfg(feature = "half")]
+use half::f16;
+use itertools::Either;
+
+use crate::builder::GridOptions;
+
+/// Storage for the positions of the points that are bucketed into each cell of
+/// a uniform grid.
+///
+/// Each point is represented by a tuple containing the point's position in
+/// 3-dimensional space and the point's index in the grid's point objects. The
+/// points of all cells are stored together in a `FlatCells`.
+#[derive(Clone)]
+pub(crate) enum CellPositions {
+    /// Positions are stored at full precision.
+    Full(FlatCells<([f32; 3], usize)>),
+
+    /// Only the point indices are stored, and positions are read from the
+    /// grid's array of positions.
+    Uncached(FlatCells<usize>),
+
+    /// Positions are stored at half precision, relative to the minimum corner
+    /// of the cell that contains them.
+    #[cfg(feature = "half")]
+    Half(FlatCells<([f16; 3], usize)>),
+}
+
+impl CellPositions {
+    /// Creates empty storage for cells that have room for the given number of
+    /// points.
+    pub fn with_capacities(cell_point_counts: &[usize], options: &GridOptions) -> Self {
+        #[cfg(feature = "half")]
+        if options.cache_positions && options.half_precision_positions {
+            return CellPositions::Half(FlatCells::with_capacities(cell_point_counts));
+        }
+        if !options.cache_positions {
+            return CellPositions::Uncached(FlatCells::with_capacities(cell_point_counts));
+        }
+
+        CellPositions::Full(FlatCells::with_capacities(cell_point_counts))
+    }
+
+    /// Creates storage for cells and fills it in parallel with the points in
+    /// `cell_points`, which holds the cell index and the point index of each
+    /// point, sorted by cell index.
+    ///
+    /// `position_of` returns the position of the point with the given index.
+    /// `cell_min` returns the minimum corner of the cell with the given index.
+    /// It's only called if positions are stored relative to their cell.
+    #[cfg(feature = "rayon")]
+    #[cfg_attr(not(feature = "half"), allow(unused_variables))]
+    pub fn par_from_cell_order<F, M>(
+        cell_point_counts: &[usize],
+        options: &GridOptions,
+        cell_points: &[(usize, usize)],
+        position_of: F,
+        cell_min: M,
+    ) -> Self
+    where
+        F: Fn(usize) -> [f32; 3] + Sync,
+        M: Fn(usize) -> [f32; 3] + Sync,
+    {
+        use rayon::prelude::*;
+
+        #[cfg(feature = "half")]
+        if options.cache_positions && options.half_precision_positions {
+            let entries = cell_points
+                .par_iter()
+                .map(|&(cell_index, point_index)| {
+                    let relative = to_half(position_of(point_index), cell_min(cell_index));
+                    (relative, point_index)
+                })
+                .collect();
+            return CellPositions::Half(FlatCells::from_cell_order(entries, cell_point_counts));
+        }
+        if !options.cache_positions {
+            let entries = cell_points
+                .par_iter()
+                .map(|&(_, point_index)| point_index)
+                .collect();
+            return CellPositions::Uncached(FlatCells::from_cell_order(entries, cell_point_counts));
+        }
+
+        let entries = cell_points
+            .par_iter()
+            .map(|&(_, point_index)| (position_of(point_index), point_index))
+            .collect();
+        CellPositions::Full(FlatCells::from_cell_order(entries, cell_point_counts))
+    }
+
+    /// Returns the number of cells.
+    pub fn cell_count(&self) -> usize {
+        match self {
+            CellPositions::Full(cells) => cells.cell_count(),
+            CellPositions::Uncached(cells) => cells.cell_count(),
+            #[cfg(feature = "half")]
+            CellPositions::Half(cells) => cells.cell_count(),
+        }
+    }
+
+    /// Returns an estimate of the number of bytes of heap memory that are used
+    /// to store the positions, including unused capacity.
+    pub fn memory_bytes(&self) -> usize {
+        match self {
+            CellPositions::Full(cells) => cells.memory_bytes(),
+            CellPositions::Uncached(cells) => cells.memory_bytes(),
+            #[cfg(feature = "half")]
+            CellPositions::Half(cells) => cells.memory_bytes(),
+        }
+    }
+
+    /// Returns true if positions lose precision when they're stored.
+    pub fn is_lossy(&self) -> bool {
+        match self {
+            CellPositions::Full(_) | CellPositions::Uncached(_) => false,
+            #[cfg(feature = "half")]
+            CellPositions::Half(_) => true,
+        }
+    }
+
+    /// Adds a point to a cell.
+    ///
+    /// `cell_min` returns the minimum corner of the cell. It's only called if
+    /// positions are stored relative to their cell.
+    #[cfg_attr(not(feature = "half"), allow(unused_variables))]
+    pub fn push<F>(
+        &mut self,
+        cell_index: usize,
+        cell_min: F,
+        position: [f32; 3],
+        point_index: usize,
+    ) where
+        F: FnOnce() -> [f32; 3],
+    {
+        match self {
+            CellPositions::Full(cells) => cells.push(cell_index, (position, point_index)),
+            CellPositions::Uncached(cells) => cells.push(cell_index, point_index),
+            #[cfg(feature = "half")]
+            CellPositions::Half(cells) => {
+                cells.push(cell_index, (to_half(position, cell_min()), point_index))
+            }
+        }
+    }
+
+    /// Makes room for points that are about to be added to the cells with the
+    /// given indices, one point for each index, so that adding a batch of
+    /// points moves the points of other cells at most once.
+    pub fn reserve(&mut self, new_cell_indices: &[usize]) {
+        match self {
+            CellPositions::Full(cells) => cells.reserve(new_cell_indices),
+            CellPositions::Uncached(cells) => cells.reserve(new_cell_indices),
+            #[cfg(feature = "half")]
+            CellPositions::Half(cells) => cells.reserve(new_cell_indices),
+        }
+    }
+
+    /// Removes a point from a cell. The order of the cell's other points may
+    /// change.
+    pub fn remove(&mut self, cell_index: usize, point_index: usize) {
+        match self {
+            CellPositions::Full(cells) => cells.swap_remove(cell_index, |&(_, i)| i == point_index),
+            CellPositions::Uncached(cells) => cells.swap_remove(cell_index, |&i| i == point_index),
+            #[cfg(feature = "half")]
+            CellPositions::Half(cells) => cells.swap_remove(cell_index, |&(_, i)| i == point_index),
+        }
+    }
+
+    /// Moves the points of every cell next to each other, releasing the room
+    /// that cells have to grow into, including room left by removed points.
+    pub fn compact(&mut self) {
+        match self {
+            CellPositions::Full(cells) => cells.compact(),
+            CellPositions::Uncached(cells) => cells.compact(),
+            #[cfg(feature = "half")]
+            CellPositions::Half(cells) => cells.compact(),
+        }
+    }
+
+    /// Changes the index of a point in a cell from `old_index` to `new_index`.
+    pub fn reindex(&mut self, cell_index: usize, old_index: usize, new_index: usize) {
+        match self {
+            CellPositions::Full(cells) => {
+                reindex_point(cells.cell_mut(cell_index), |(_, i)| i, old_index, new_index)
+            }
+            CellPositions::Uncached(cells) => {
+                reindex_point(cells.cell_mut(cell_index), |i| i, old_index, new_index)
+            }
+            #[cfg(feature = "half")]
+            CellPositions::Half(cells) => {
+                reindex_point(cells.cell_mut(cell_index), |(_, i)| i, old_index, new_index)
+            }
+        }
+    }
+
+    /// Returns the positions and point indices of the points in a cell.
+    ///
+    /// `cell_min` returns the minimum corner of the cell. It's only called if
+    /// positions are stored relative to their cell. `position_of` returns the
+    /// position of the point with the given index. It's only called if
+    /// positions aren't stored.
+    #[cfg(not(feature = "half"))]
+    pub fn cell<'a, F, P>(
+        &'a self,
+        cell_index: usize,
+        _cell_min: F,
+        position_of: P,
+    ) -> impl Iterator<Item = ([f32; 3], usize)> + 'a
+    where
+        F: FnOnce() -> [f32; 3],
+        P: Fn(usize) -> [f32; 3] + 'a,
+    {
+        match self {
+            CellPositions::Full(cells) => Either::Left(cells.cell(cell_index).iter().copied()),
+            CellPositions::Uncached(cells) => Either::Right(
+                cells
+                    .cell(cell_index)
+                    .iter()
+                    .map(move |&point_index| (position_of(point_index), point_index)),
+            ),
+        }
+    }
+
+    /// Returns the positions and point indices of the points in a cell.
+    ///
+    /// `cell_min` returns the minimum corner of the cell. It's only called if
+    /// positions are stored relative to their cell. `position_of` returns the
+    /// position of the point with the given index. It's only called if
+    /// positions aren't stored.
+    #[cfg(feature = "half")]
+    pub fn cell<'a, F, P>(
+        &'a self,
+        cell_index: usize,
+        cell_min: F,
+        position_of: P,
+    ) -> impl Iterator<Item = ([f32; 3], usize)> + 'a
+    where
+        F: FnOnce() -> [f32; 3],
+        P: Fn(usize) -> [f32; 3] + 'a,
+    {
+        match self {
+            CellPositions::Full(cells) => {
+                Either::Left(Either::Left(cells.cell(cell_index).iter().copied()))
+            }
+            CellPositions::Uncached(cells) => Either::Left(Either::Right(
+                cells
+                    .cell(cell_index)
+                    .iter()
+                    .map(move |&point_index| (position_of(point_index), point_index)),
+            )),
+            CellPositions::Half(cells) => {
+                let min = cell_min();
+                Either::Right(
+                    cells
+                        .cell(cell_index)
+                        .iter()
+                        .map(move |(relative, point_index)| {
+                            let position =
+                                [0, 1, 2].map(|axis| min[axis] + relative[axis].to_f32());
+                            (position, *point_index)
+                        }),
+                )
+            }
+        }
+    }
+}
+
+/// The points of every cell of a uniform grid, stored in a single vector in
+/// the order of the cells.
+///
+/// Keeping the points of all cells in one allocation, rather than giving each
+/// cell a vector of its own, saves an allocation for each cell and a pointer
+/// to follow for each cell that a query looks into. Each cell can grow into
+/// the unused room that follows its points, up to the start of the next cell,
+/// so points can be added to and removed from a cell without moving the points
+/// of other cells until the cell runs out of room.
+#[derive(Clone)]
+pub(crate) struct FlatCells<P> {
+    /// The points of all cells, along with the room that's left for each cell
+    /// to grow into.
+    entries: Vec<P>,
+
+    /// The start and end in `entries` of the points of each cell.
+    ranges: Vec<(usize, usize)>,
+}
+
+impl<P> FlatCells<P>
+where
+    P: Copy + Default,
+{
+    /// Creates empty cells that have room for the given number of points.
+    fn with_capacities(cell_point_counts: &[usize]) -> Self {
+        // Allocate all of the necessary space up front so that nothing needs to be
+        // moved as the cells are filled.
+        let mut start = 0;
+        let ranges = cell_point_counts
+            .iter()
+            .map(|&count| {
+                let range = (start, start);
+                start += count;
+                range
+            })
+            .collect();
+        Self {
+            entries: vec![P::default(); start],
+            ranges,
+        }
+    }
+
+    /// Creates cells that hold the given points, which are in the order of
+    /// their cells, with the given number of points in each cell.
+    #[cfg(feature = "rayon")]
+    fn from_cell_order(entries: Vec<P>, cell_point_counts: &[usize]) -> Self {
+        let mut start = 0;
+        let ranges = cell_point_counts
+            .iter()
+            .map(|&count| {
+                let range = (start, start + count);
+                start += count;
+                range
+            })
+            .collect();
+        Self { entries, ranges }
+    }
+
+    fn cell_count(&self) -> usize {
+        self.ranges.len()
+    }
+
+    fn cell(&self, cell_index: usize) -> &[P] {
+        let (start, end) = self.ranges[cell_index];
+        &self.entries[start..end]
+    }
+
+    fn cell_mut(&mut self, cell_index: usize) -> &mut [P] {
+        let (start, end) = self.ranges[cell_index];
+        &mut self.entries[start..end]
+    }
+
+    /// Returns the index in `entries` that the cell can grow up to, which is
+    /// the start of the next cell.
+    fn room_end(&self, cell_index: usize) -> usize {
+        self.ranges
+            .get(cell_index + 1)
+            .map_or(self.entries.len(), |&(start, _)| start)
+    }
+
+    fn push(&mut self, cell_index: usize, entry: P) {
+        if self.ranges[cell_index].1 == self.room_end(cell_index) {
+            self.reserve(&[cell_index]);
+        }
+        let end = &mut self.ranges[cell_index].1;
+        self.entries[*end] = entry;
+        *end += 1;
+    }
+
+    /// Makes room for one more point in the cell with each of the given
+    /// indices, which may repeat.
+    ///
+    /// Every cell is moved when any cell runs out of room, so a cell that runs
+    /// out of room gets at least twice as much room as it had. Adding points
+    /// to a cell one at a time then only moves the cells a logarithmic number
+    /// of times.
+    fn reserve(&mut self, new_cell_indices: &[usize]) {
+        let mut new_counts = vec![0; self.ranges.len()];
+        for &cell_index in new_cell_indices {
+            new_counts[cell_index] += 1;
+        }
+        let is_out_of_room = new_counts
+            .iter()
+            .enumerate()
+            .any(|(cell_index, new_count)| {
+                self.ranges[cell_index].1 + new_count > self.room_end(cell_index)
+            });
+        if !is_out_of_room {
+            return;
+        }
+
+        let mut entries = Vec::with_capacity(self.entries.len() + new_cell_indices.len());
+        let mut ranges = Vec::with_capacity(self.ranges.len());
+        for (cell_index, new_count) in new_counts.into_iter().enumerate() {
+            let (start, end) = self.ranges[cell_index];
+            let len = end - start;
+            let room = self.room_end(cell_index) - start;
+            let room = if len + new_count > room {
+                (len + new_count).max(2 * room)
+            } else {
+                room
+            };
+            let new_start = entries.len();
+            entries.extend_from_slice(&self.entries[start..end]);
+            entries.resize(new_start + room, P::default());
+            ranges.push((new_start, new_start + len));
+        }
+        self.entries = entries;
+        self.ranges = ranges;
+    }
+
+    /// Moves the points of every cell next to each other, with no room left
+    /// between cells.
+    fn compact(&mut self) {
+        let len = self.ranges.iter().map(|(start, end)| end - start).sum();
+        let mut entries = Vec::with_capacity(len);
+        for range in &mut self.ranges {
+            let (start, end) = *range;
+            let new_start = entries.len();
+            entries.extend_from_slice(&self.entries[start..end]);
+            *range = (new_start, entries.len());
+        }
+        self.entries = entries;
+        self.ranges.shrink_to_fit();
+    }
+
+    /// Removes the first point in a cell that `is_removed` accepts, moving the
+    /// cell's last point into its place.
+    fn swap_remove<F>(&mut self, cell_index: usize, is_removed: F)
+    where
+        F: Fn(&P) -> bool,
+    {
+        let cell = self.cell_mut(cell_index);
+        if let Some(i) = cell.iter().position(is_removed) {
+            let last = cell.len() - 1;
+            cell.swap(i, last);
+            self.ranges[cell_index].1 -= 1;
+        }
+    }
+
+    /// Returns the number of bytes of heap memory that are used to store the
+    /// points, including unused capacity.
+    fn memory_bytes(&self) -> usize {
+        self.entries.capacity() * std::mem::size_of::<P>()
+            + self.ranges.capacity() * std::mem::size_of::<(usize, usize)>()
+    }
+}
+
+/// Returns a position relative to the minimum corner of its cell, at half
+/// precision.
+#[cfg(feature = "half")]
+fn to_half(position: [f32; 3], cell_min: [f32; 3]) -> [f16; 3] {
+    [0, 1, 2].map(|axis| f16::from_f32(position[axis] - cell_min[axis]))
+}
+
+/// Changes the index of the point with index `old_index` in a cell to
+/// `new_index`.
+fn reindex_point<P, F>(cell: &mut [P], index_of: F, old_index: usize, new_index: usize)
+where
+    F: Fn(&mut P) -> &mut usize,
+{
+    if let Some(point_index) = cell.iter_mut().map(index_of).find(|i| **i == old_index) {
+        *point_index = new_index;
+    }
+}
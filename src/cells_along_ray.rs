@@ -0,0 +1,158 @@
+use crate::offset3::Offset3;
+
+/// Iterator over the cells of a uniform grid that a ray passes through, in
+/// the order that the ray passes through them.
+///
+/// The cells are found with the 3-dimensional digital differential analyzer
+/// of Amanatides and Woo. After a cell is visited, the next cell is the
+/// neighbor across whichever of the cell's walls the ray crosses first.
+pub(crate) struct CellsAlongRay {
+    /// The cell that will be returned next, or `None` if the ray has left the
+    /// grid.
+    next_cell: Option<[i64; 3]>,
+
+    /// The direction in which the cell offset changes along each axis when the
+    /// ray crosses a wall perpendicular to that axis.
+    step: [i64; 3],
+
+    /// The value of the ray parameter at which the ray next crosses a wall
+    /// perpendicular to each axis.
+    t_max: [f32; 3],
+
+    /// The change in the ray parameter between crossings of walls that are
+    /// perpendicular to each axis.
+    t_delta: [f32; 3],
+
+    grid_dimensions: [i64; 3],
+}
+
+impl CellsAlongRay {
+    /// Creates an iterator over the cells that the ray passes through. The
+    /// ray's origin is relative to the minimum corner of the grid.
+    pub fn new(
+        origin: [f32; 3],
+        direction: [f32; 3],
+        cell_widths: [f32; 3],
+        grid_dimensions: (usize, usize, usize),
+    ) -> Self {
+        let grid_dimensions = [
+            grid_dimensions.0 as i64,
+            grid_dimensions.1 as i64,
+            grid_dimensions.2 as i64,
+        ];
+        let mut ray_cells = Self {
+            next_cell: None,
+            step: [0; 3],
+            t_max: [f32::INFINITY; 3],
+            t_delta: [f32::INFINITY; 3],
+            grid_dimensions,
+        };
+
+        if direction.iter().all(|&d| d == 0.0) {
+            return ray_cells;
+        }
+
+        // Find the range of the ray parameter over which the ray is inside the grid, so
+        // that rays which start outside the grid are advanced to where they enter it.
+        let mut t_enter: f32 = 0.0;
+        let mut t_exit = f32::INFINITY;
+        for axis in 0..3 {
+            let grid_max = grid_dimensions[axis] as f32 * cell_widths[axis];
+            if direction[axis] == 0.0 {
+                if origin[axis] < 0.0 || origin[axis] >= grid_max {
+                    return ray_cells;
+                }
+            } else {
+                let t1 = -origin[axis] / direction[axis];
+                let t2 = (grid_max - origin[axis]) / direction[axis];
+                t_enter = t_enter.max(t1.min(t2));
+                t_exit = t_exit.min(t1.max(t2));
+            }
+        }
+        if t_enter >= t_exit {
+            return ray_cells;
+        }
+
+        let mut cell = [0; 3];
+        for axis in 0..3 {
+            // Clamp the cell the ray enters, since rounding can put the entry point just
+            // outside the grid.
+            let entry = origin[axis] + direction[axis] * t_enter;
+            let relative_pos = entry / cell_widths[axis];
+            cell[axis] = (relative_pos.floor() as i64).clamp(0, grid_dimensions[axis] - 1);
+
+            if direction[axis] > 0.0 {
+                let wall = (cell[axis] + 1) as f32 * cell_widths[axis];
+                ray_cells.step[axis] = 1;
+                ray_cells.t_max[axis] = (wall - origin[axis]) / direction[axis];
+                ray_cells.t_delta[axis] = cell_widths[axis] / direction[axis];
+            } else if direction[axis] < 0.0 {
+                let wall = cell[axis] as f32 * cell_widths[axis];
+                ray_cells.step[axis] = -1;
+                ray_cells.t_max[axis] = (wall - origin[axis]) / direction[axis];
+                ray_cells.t_delta[axis] = -cell_widths[axis] / direction[axis];
+            }
+        }
+        ray_cells.next_cell = Some(cell);
+        ray_cells
+    }
+}
+
+impl Iterator for CellsAlongRay {
+    type Item = Offset3;
+
+    fn next(&mut self) -> Option<Offset3> {
+        let cell = self.next_cell?;
+
+        // Cross whichever wall the ray reaches first.
+        let axis = (0..3)
+            .min_by(|&a1, &a2| self.t_max[a1].total_cmp(&self.t_max[a2]))
+            .unwrap();
+        let mut next_cell = cell;
+        next_cell[axis] += self.step[axis];
+        self.t_max[axis] += self.t_delta[axis];
+        self.next_cell = if self.step[axis] != 0
+            && next_cell[axis] >= 0
+            && next_cell[axis] < self.grid_dimensions[axis]
+        {
+            Some(next_cell)
+        } else {
+            None
+        };
+
+        Some(Offset3::new(cell[0], cell[1], cell[2]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn axis_aligned_ray_visits_row_of_cells() {
+        let cells = CellsAlongRay::new([0.5, 1.5, 2.5], [1.0, 0.0, 0.0], [1.0; 3], (4, 3, 3))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            cells,
+            (0..4).map(|x| Offset3::new(x, 1, 2)).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn ray_starting_outside_grid_advances_to_entry() {
+        let cells = CellsAlongRay::new([0.5, 10.5, 0.5], [0.0, -2.0, 0.0], [1.0; 3], (2, 3, 2))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            cells,
+            vec![
+                Offset3::new(0, 2, 0),
+                Offset3::new(0, 1, 0),
+                Offset3::new(0, 0, 0),
+            ]
+        );
+        assert_eq!(
+            CellsAlongRay::new([0.5, 10.5, 0.5], [0.0, 1.0, 0.0], [1.0; 3], (2, 3, 2)).count(),
+            0
+        );
+    }
+}
@@ -0,0 +1,105 @@
+use std::cmp::Ordering;
+
+/// The Euclidean distance between two points, as returned by queries.
+///
+/// Searches compare squared distances, which don't need a square root, so a
+/// distance stores the squared distance. `squared` returns it as it is, and
+/// `linear` takes the square root when it's called, so the square root is
+/// only paid for when the linear distance is needed. Keeping the two apart
+/// prevents comparing a linear radius against a squared distance by mistake.
+///
+/// Distances are ordered by their squared values, which orders them the same
+/// way as their linear values. The order is total: a NaN distance, which
+/// comes from a position with a NaN coordinate, is greater than every other
+/// distance.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Distance {
+    squared: f32,
+}
+
+impl Distance {
+    /// Constructs a distance from the squared distance.
+    pub fn from_squared(squared: f32) -> Self {
+        Self { squared }
+    }
+
+    /// Constructs a distance from the linear distance, which can then be
+    /// compared against the distances that queries return.
+    pub fn from_linear(linear: f32) -> Self {
+        Self {
+            squared: linear * linear,
+        }
+    }
+
+    /// Returns the squared distance.
+    pub fn squared(self) -> f32 {
+        self.squared
+    }
+
+    /// Returns the linear distance, which is the square root of the squared
+    /// distance.
+    pub fn linear(self) -> f32 {
+        self.squared.sqrt()
+    }
+}
+
+impl PartialEq for Distance {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Distance {}
+
+impl PartialOrd for Distance {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Distance {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // `total_cmp` puts a NaN with its sign bit set below every number, so
+        // every NaN is made positive first.
+        let positive = |squared: f32| if squared.is_nan() { f32::NAN } else { squared };
+        positive(self.squared).total_cmp(&positive(other.squared))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    #[test]
+    fn distances_order_like_their_squares_and_linear_is_euclidean() {
+        let mut rng = StdRng::seed_from_u64(671);
+        let mut distances = (0..1000)
+            .map(|_| Distance::from_squared(rng.gen_range(0.0..100.0)))
+            .collect::<Vec<_>>();
+        distances.push(Distance::from_squared(f32::NAN));
+        distances.push(Distance::from_squared(0.0));
+        for a in &distances[..100] {
+            for b in &distances {
+                if !b.squared().is_nan() {
+                    assert_eq!(Some(a.cmp(b)), a.squared().partial_cmp(&b.squared()));
+                }
+            }
+        }
+        distances.push(Distance::from_squared(-f32::NAN));
+        distances.sort();
+        assert!(distances[distances.len() - 2..]
+            .iter()
+            .all(|distance| distance.squared().is_nan()));
+        assert_eq!(
+            Distance::from_squared(-f32::NAN),
+            Distance::from_squared(f32::NAN)
+        );
+        assert!(Distance::from_squared(-f32::NAN) > Distance::from_squared(f32::INFINITY));
+
+        let (p, q) = ([1.0f32, 2.0, 3.0], [4.0f32, 6.0, 3.0]);
+        let squared = (0..3).map(|axis| (q[axis] - p[axis]).powi(2)).sum();
+        assert_eq!(Distance::from_squared(squared).linear(), 5.0);
+        assert_eq!(Distance::from_linear(5.0), Distance::from_squared(25.0));
+    }
+}
@@ -0,0 +1,66 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{builder::GridOptions, spiral_cells::SpiralCell};
+
+/// The spatial index of a uniform grid, without the grid's points.
+///
+/// An index is exported from a grid with `UniformGrid::export_index`, and a
+/// grid is rebuilt from an index and the same points with
+/// `UniformGrid::from_index`. Points are only referred to by their indices,
+/// so the index can be serialized and stored separately from the points.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct GridIndex {
+    /// The indices of the points that are bucketed into each cell.
+    pub(crate) cell_point_indices: Vec<Vec<usize>>,
+    pub(crate) min_position: [f32; 3],
+    pub(crate) cell_widths: [f32; 3],
+    pub(crate) grid_dimensions: (usize, usize, usize),
+    pub(crate) options: GridOptions,
+    pub(crate) spiral_cells: Vec<SpiralCell>,
+}
+
+impl GridIndex {
+    /// Returns the number of points that the index refers to.
+    pub fn point_count(&self) -> usize {
+        self.cell_point_indices.iter().map(Vec::len).sum()
+    }
+}
+
+/// Error returned when a grid can't be rebuilt from an index and a vector of
+/// points because they don't match each other.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IndexMismatch {
+    /// The index refers to a different number of points than were given.
+    PointCount { expected: usize, found: usize },
+
+    /// The index refers to a point index that's out of range, or refers to
+    /// the same point index more than once.
+    InvalidPointIndex(usize),
+
+    /// The number of cells in the index doesn't match its dimensions.
+    CellCount,
+}
+
+impl fmt::Display for IndexMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IndexMismatch::PointCount { expected, found } => write!(
+                f,
+                "index refers to {} points but {} points were given",
+                expected, found
+            ),
+            IndexMismatch::InvalidPointIndex(i) => {
+                write!(
+                    f,
+                    "index refers to point {} out of range or more than once",
+                    i
+                )
+            }
+            IndexMismatch::CellCount => write!(f, "index cell count doesn't match its dimensions"),
+        }
+    }
+}
+
+impl std::error::Error for IndexMismatch {}
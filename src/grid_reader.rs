@@ -0,0 +1,77 @@
+use std::ops::Deref;
+
+use crate::{point_object::PointObject, uniform_grid::UniformGrid};
+
+/// A read-only view of a uniform grid, for a phase of queries.
+///
+/// A reader is obtained with `UniformGrid::reader`, and it dereferences to the
+/// grid, so every query method of the grid can be called on it. It borrows the
+/// grid immutably for as long as it lives, so the grid can't be mutated, for
+/// example with `extend`, until the reader and every query result that borrows
+/// from it have been dropped. Dropping the reader at the end of a phase of
+/// queries makes the boundary with the following phase of mutations explicit.
+pub struct GridReader<'a, T>
+where
+    T: PointObject,
+{
+    grid: &'a UniformGrid<T>,
+}
+
+impl<'a, T> GridReader<'a, T>
+where
+    T: PointObject,
+{
+    pub(crate) fn new(grid: &'a UniformGrid<T>) -> Self {
+        Self { grid }
+    }
+}
+
+impl<T> Clone for GridReader<'_, T>
+where
+    T: PointObject,
+{
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for GridReader<'_, T> where T: PointObject {}
+
+impl<T> Deref for GridReader<'_, T>
+where
+    T: PointObject,
+{
+    type Target = UniformGrid<T>;
+
+    fn deref(&self) -> &UniformGrid<T> {
+        self.grid
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::UniformGridBuilder;
+
+    #[test]
+    fn read_phase_then_mutation_phase() {
+        let mut grid = UniformGridBuilder::new().build(vec![[0.0; 3], [1.0; 3], [2.0; 3]]);
+
+        // The read phase ends when the reader goes out of scope.
+        let (nearest_before, neighbor_count) = {
+            let reader = grid.reader();
+            let nearest = reader.nearest_neighbor([2.9; 3]).map(|(point, _)| *point);
+            (nearest, reader.neighbors_within_radius([1.0; 3], 2.0).len())
+        };
+        assert_eq!(nearest_before, Some([2.0; 3]));
+        assert_eq!(neighbor_count, 3);
+
+        grid.insert([3.0; 3]);
+        grid.extend(vec![[4.0; 3], [5.0; 3]]);
+
+        let reader = grid.reader();
+        let (nearest_after, _) = reader.nearest_neighbor([2.9; 3]).unwrap();
+        assert_eq!(*nearest_after, [3.0; 3]);
+        assert_eq!(reader.len(), 6);
+        assert_eq!(reader.validate(), Ok(()));
+    }
+}
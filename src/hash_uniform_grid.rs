@@ -0,0 +1,333 @@
+use std::collections::HashMap;
+
+use crate::{
+    distance::Distance,
+    offset3::Offset3,
+    point_object::PointObject,
+    uniform_grid::{dedup_by_point_index, dist2},
+};
+
+/// A uniform grid of cube-shaped cells that covers all of 3-dimensional space.
+///
+/// Unlike `UniformGrid`, which stores every cell of a finite region in a flat
+/// vector, only the cells that contain points are stored, in a hash map that's
+/// keyed by the offset of each cell from the cell at the origin. Empty regions
+/// of space take no memory, and points can be inserted anywhere without
+/// rebuilding the grid. This suits point sets that are sparse or that have no
+/// bounds known in advance.
+///
+/// Queries search the cells around the query point in rings of growing size.
+/// Once a ring would have more cells than the grid has non-empty cells, the
+/// rest of the non-empty cells are searched directly instead, so points that
+/// are very far apart don't make queries walk through huge numbers of empty
+/// cells.
+pub struct HashUniformGrid<T>
+where
+    T: PointObject,
+{
+    point_objs: Vec<T>,
+
+    /// The non-empty cells, keyed by their offsets. Each point is represented
+    /// by its position and its index in `point_objs`.
+    cells: HashMap<Offset3, Vec<([f32; 3], usize)>>,
+
+    // The width in space that is covered by each cube-shaped cell.
+    cell_width: f32,
+}
+
+impl<T> HashUniformGrid<T>
+where
+    T: PointObject,
+{
+    /// Constructs an empty grid whose cells have the given width, which must
+    /// be positive.
+    pub fn new(cell_width: f32) -> Self {
+        assert!(cell_width > 0.0, "cell width must be positive");
+        Self {
+            point_objs: Vec::new(),
+            cells: HashMap::new(),
+            cell_width,
+        }
+    }
+
+    /// Inserts a point into the grid and returns the index that identifies it
+    /// in `points()`.
+    pub fn insert(&mut self, point: T) -> usize {
+        let point_index = self.point_objs.len();
+        let position = point.position();
+        self.cells
+            .entry(self.point_into_offset(position))
+            .or_default()
+            .push((position, point_index));
+        self.point_objs.push(point);
+        point_index
+    }
+
+    /// Returns the points in the grid, in the order that they were inserted.
+    pub fn points(&self) -> &[T] {
+        &self.point_objs
+    }
+
+    /// Returns the number of points in the grid.
+    pub fn len(&self) -> usize {
+        self.point_objs.len()
+    }
+
+    /// Returns true if the grid contains no points.
+    pub fn is_empty(&self) -> bool {
+        self.point_objs.is_empty()
+    }
+
+    /// Returns the width of each cell in the grid.
+    pub fn cell_width(&self) -> f32 {
+        self.cell_width
+    }
+
+    /// Returns the number of cells that contain at least one point.
+    pub fn occupied_cell_count(&self) -> usize {
+        self.cells.len()
+    }
+
+    /// Finds the point in the grid that is closest to the given query point.
+    ///
+    /// Returns the point along with its distance to the query point.
+    pub fn nearest_neighbor(&self, query_point: [f32; 3]) -> Option<(&T, Distance)> {
+        let query_cell_offset = self.point_into_offset(query_point);
+        let mut maybe_nearest_so_far: Option<(usize, f32)> = None;
+        let mut ring = 0;
+        loop {
+            if ring_cell_count(ring) > self.cells.len() as u128 {
+                // Searching every non-empty cell outside of the rings that have been
+                // searched is cheaper than searching the next ring.
+                for (&cell_offset, cell) in &self.cells {
+                    if ring_of(query_cell_offset, cell_offset) >= ring {
+                        nearest_in_cell(query_point, cell, &mut maybe_nearest_so_far);
+                    }
+                }
+                break;
+            }
+
+            for cell_offset in ring_offsets(query_cell_offset, ring) {
+                if let Some(cell) = self.cells.get(&cell_offset) {
+                    nearest_in_cell(query_point, cell, &mut maybe_nearest_so_far);
+                }
+            }
+
+            // Any point closer than the one found would have to be in one of the rings
+            // that have been searched.
+            if let Some((_, distance2)) = maybe_nearest_so_far {
+                if self.is_sphere_within_rings(query_point, query_cell_offset, ring, distance2) {
+                    break;
+                }
+            }
+            ring += 1;
+        }
+
+        maybe_nearest_so_far.map(|(point_index, distance2)| {
+            (
+                &self.point_objs[point_index],
+                Distance::from_squared(distance2),
+            )
+        })
+    }
+
+    /// Finds all points in the grid that are within the given radius of the
+    /// query point.
+    ///
+    /// Returns each point along with its distance to the query point. The
+    /// points are not returned in any particular order.
+    pub fn neighbors_within_radius(
+        &self,
+        query_point: [f32; 3],
+        radius: f32,
+    ) -> Vec<(&T, Distance)> {
+        let radius2 = radius * radius;
+        let min_offset = self.point_into_offset(query_point.map(|c| c - radius));
+        let max_offset = self.point_into_offset(query_point.map(|c| c + radius));
+        let block_cell_count = [
+            max_offset.x.saturating_sub(min_offset.x),
+            max_offset.y.saturating_sub(min_offset.y),
+            max_offset.z.saturating_sub(min_offset.z),
+        ]
+        .into_iter()
+        .map(|width| (width.max(0) as u128).saturating_add(1))
+        .fold(1u128, u128::saturating_mul);
+
+        let in_block = |offset: &Offset3| {
+            (min_offset.x..=max_offset.x).contains(&offset.x)
+                && (min_offset.y..=max_offset.y).contains(&offset.y)
+                && (min_offset.z..=max_offset.z).contains(&offset.z)
+        };
+        let cells: Vec<&Vec<([f32; 3], usize)>> = if block_cell_count > self.cells.len() as u128 {
+            self.cells
+                .iter()
+                .filter(|(offset, _)| in_block(offset))
+                .map(|(_, cell)| cell)
+                .collect()
+        } else {
+            block_offsets(min_offset, max_offset)
+                .filter_map(|offset| self.cells.get(&offset))
+                .collect()
+        };
+
+        let mut neighbors = cells
+            .into_iter()
+            .flatten()
+            .map(|&(position, point_index)| (point_index, dist2(query_point, position)))
+            .filter(|&(_, distance2)| distance2 <= radius2)
+            .collect();
+
+        // Each point is only stored in one cell, but don't rely on that.
+        dedup_by_point_index(&mut neighbors);
+        neighbors
+            .into_iter()
+            .map(|(point_index, distance2)| {
+                (
+                    &self.point_objs[point_index],
+                    Distance::from_squared(distance2),
+                )
+            })
+            .collect()
+    }
+
+    /// Returns true if the sphere around the query point with the given
+    /// squared radius lies entirely within the block of cells that are at most
+    /// `rings` cells away from the query cell along every axis.
+    fn is_sphere_within_rings(
+        &self,
+        query_point: [f32; 3],
+        query_cell_offset: Offset3,
+        rings: i64,
+        radius2: f32,
+    ) -> bool {
+        let cell_offset = [
+            query_cell_offset.x,
+            query_cell_offset.y,
+            query_cell_offset.z,
+        ];
+        (0..3).all(|axis| {
+            let block_min = cell_offset[axis].saturating_sub(rings) as f32 * self.cell_width;
+            let block_max = cell_offset[axis].saturating_add(rings + 1) as f32 * self.cell_width;
+            let dist_to_wall = (query_point[axis] - block_min).min(block_max - query_point[axis]);
+            dist_to_wall >= 0.0 && dist_to_wall * dist_to_wall > radius2
+        })
+    }
+
+    /// Returns the offset of the cell in which the point would be bucketed.
+    fn point_into_offset(&self, point: [f32; 3]) -> Offset3 {
+        let x = (point[0] / self.cell_width).floor() as i64;
+        let y = (point[1] / self.cell_width).floor() as i64;
+        let z = (point[2] / self.cell_width).floor() as i64;
+        Offset3::new(x, y, z)
+    }
+}
+
+/// Updates `maybe_nearest_so_far` with the point in the cell that is closest
+/// to the query point, if it's closer than the nearest point found so far.
+fn nearest_in_cell(
+    query_point: [f32; 3],
+    cell: &[([f32; 3], usize)],
+    maybe_nearest_so_far: &mut Option<(usize, f32)>,
+) {
+    for &(position, point_index) in cell {
+        let distance2 = dist2(query_point, position);
+        if distance2.is_nan() {
+            continue;
+        }
+        match maybe_nearest_so_far {
+            Some((_, nearest_distance2)) if *nearest_distance2 <= distance2 => {}
+            _ => *maybe_nearest_so_far = Some((point_index, distance2)),
+        }
+    }
+}
+
+/// Returns the number of cells that are exactly `ring` cells away from a
+/// center cell along at least one axis.
+fn ring_cell_count(ring: i64) -> u128 {
+    let ring = ring as u128;
+    if ring == 0 {
+        1
+    } else {
+        (2 * ring + 1).pow(3) - (2 * ring - 1).pow(3)
+    }
+}
+
+/// Returns the number of rings between the center cell and the cell.
+fn ring_of(center_cell_offset: Offset3, cell_offset: Offset3) -> i64 {
+    let axis_rings = |c: i64, center: i64| c.saturating_sub(center).saturating_abs();
+    axis_rings(cell_offset.x, center_cell_offset.x)
+        .max(axis_rings(cell_offset.y, center_cell_offset.y))
+        .max(axis_rings(cell_offset.z, center_cell_offset.z))
+}
+
+/// Returns the offsets of the cells that are exactly `ring` cells away from
+/// the center cell along at least one axis.
+fn ring_offsets(center_cell_offset: Offset3, ring: i64) -> impl Iterator<Item = Offset3> {
+    // Offsets saturate rather than overflow, so rings around a query point with huge
+    // coordinates are clipped at the edge of the range of offsets.
+    let Offset3 { x, y, z } = center_cell_offset;
+    let range = move |c: i64| c.saturating_sub(ring)..=c.saturating_add(ring);
+    range(z).flat_map(move |cz| {
+        range(y).flat_map(move |cy| {
+            // Rows on a face of the ring are full, but other rows only cross the ring at
+            // their two ends.
+            let is_face_row = ring_of(Offset3::new(x, cy, cz), center_cell_offset) == ring;
+            range(x)
+                .filter(move |&cx| is_face_row || cx.saturating_sub(x).saturating_abs() == ring)
+                .map(move |cx| Offset3::new(cx, cy, cz))
+        })
+    })
+}
+
+/// Returns the offsets of the cells in the block between the two corner
+/// offsets, inclusive.
+fn block_offsets(min_offset: Offset3, max_offset: Offset3) -> impl Iterator<Item = Offset3> {
+    (min_offset.z..=max_offset.z).flat_map(move |z| {
+        (min_offset.y..=max_offset.y)
+            .flat_map(move |y| (min_offset.x..=max_offset.x).map(move |x| Offset3::new(x, y, z)))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn queries_reach_across_wildly_separated_points() {
+        let points = [
+            [0.0, 0.0, 0.0],
+            [0.5, 0.0, 0.0],
+            [1.0e9, -1.0e9, 0.0],
+            [-3.0e12, 5.0, 7.0],
+            [2.0e6, 2.0e6, 2.0e6],
+        ];
+        let mut grid = HashUniformGrid::new(1.0);
+        for (point_index, &point) in points.iter().enumerate() {
+            assert_eq!(grid.insert(point), point_index);
+        }
+        assert_eq!(grid.len(), points.len());
+        assert_eq!(grid.occupied_cell_count(), 4);
+
+        for &point in &points {
+            let (found, distance) = grid.nearest_neighbor(point).unwrap();
+            assert_eq!(*found, point);
+            assert_eq!(distance.squared(), 0.0);
+        }
+        let (found, _) = grid.nearest_neighbor([0.9e9, -0.9e9, 1.0]).unwrap();
+        assert_eq!(*found, [1.0e9, -1.0e9, 0.0]);
+        let (found, _) = grid.nearest_neighbor([-2.0e12, 0.0, 0.0]).unwrap();
+        assert_eq!(*found, [-3.0e12, 5.0, 7.0]);
+        let (found, _) = grid.nearest_neighbor([0.3, 10.0, 0.0]).unwrap();
+        assert_eq!(*found, [0.5, 0.0, 0.0]);
+
+        let mut neighbors = grid
+            .neighbors_within_radius([0.2, 0.0, 0.0], 1.0)
+            .into_iter()
+            .map(|(point, _)| *point)
+            .collect::<Vec<_>>();
+        neighbors.sort_by(|p, q| p[0].total_cmp(&q[0]));
+        assert_eq!(neighbors, vec![[0.0, 0.0, 0.0], [0.5, 0.0, 0.0]]);
+        assert_eq!(grid.neighbors_within_radius([0.0; 3], 2.5e12).len(), 4);
+        assert!(grid.neighbors_within_radius([0.0; 3], -1.0).is_empty());
+    }
+}
@@ -1,8 +1,35 @@
 mod bounding_box;
+mod builder;
+mod cell_positions;
+mod cells_along_ray;
+mod distance;
 mod f32;
+mod grid_index;
+mod grid_reader;
+mod hash_uniform_grid;
+mod metric;
 mod offset3;
+#[cfg(feature = "ply")]
+pub mod ply;
 pub mod point_object;
+#[cfg(feature = "simd")]
+mod simd;
 pub mod spiral_cells;
 mod uniform_grid;
+mod validation;
 
-pub use crate::uniform_grid::UniformGrid;
+pub use crate::{
+    bounding_box::BoundingBox,
+    builder::{CellSize, UniformGridBuilder},
+    distance::Distance,
+    grid_index::{GridIndex, IndexMismatch},
+    grid_reader::GridReader,
+    hash_uniform_grid::HashUniformGrid,
+    metric::{Chebyshev, Euclidean, Manhattan, Metric, WeightedEuclidean},
+    offset3::{Axis, BucketBoundary, Offset3, OutOfBounds},
+    uniform_grid::{
+        BufferError, BuildPhase, CellLocation, GridParts, GridTooLarge, QueryHint, TracedNeighbor,
+        UniformGrid,
+    },
+    validation::ValidationError,
+};
@@ -0,0 +1,116 @@
+/// A measure of the distance between two positions, which the `_by_metric`
+/// queries of a uniform grid search by.
+///
+/// The grid's cells are laid out the same way for every metric, so a search
+/// prunes cells by bounding how close a point outside of the cells it has
+/// searched could be. That bound is only valid if the distance never
+/// decreases when the difference between the positions grows along any axis,
+/// which holds for the Euclidean, Manhattan, Chebyshev, and weighted Euclidean
+/// metrics and for every other norm that treats each axis symmetrically.
+///
+/// Positions are in the grid's axes, as set by
+/// `UniformGridBuilder::axis_order`.
+pub trait Metric {
+    /// Returns the distance between two positions.
+    fn distance(&self, p: [f32; 3], q: [f32; 3]) -> f32;
+
+    /// Returns the distance between two positions that differ by `delta`
+    /// along the given axis, and not at all along the other axes.
+    ///
+    /// This must equal `distance` for such positions.
+    fn axis_distance(&self, axis: usize, delta: f32) -> f32;
+}
+
+/// The straight-line distance, which is the distance that the other queries
+/// of a uniform grid search by.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Euclidean;
+
+impl Metric for Euclidean {
+    fn distance(&self, p: [f32; 3], q: [f32; 3]) -> f32 {
+        (0..3)
+            .map(|axis| (q[axis] - p[axis]) * (q[axis] - p[axis]))
+            .sum::<f32>()
+            .sqrt()
+    }
+
+    fn axis_distance(&self, _axis: usize, delta: f32) -> f32 {
+        delta.abs()
+    }
+}
+
+/// The sum of the differences between the positions along each axis.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Manhattan;
+
+impl Metric for Manhattan {
+    fn distance(&self, p: [f32; 3], q: [f32; 3]) -> f32 {
+        (0..3).map(|axis| (q[axis] - p[axis]).abs()).sum()
+    }
+
+    fn axis_distance(&self, _axis: usize, delta: f32) -> f32 {
+        delta.abs()
+    }
+}
+
+/// The largest of the differences between the positions along each axis.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Chebyshev;
+
+impl Metric for Chebyshev {
+    fn distance(&self, p: [f32; 3], q: [f32; 3]) -> f32 {
+        (0..3)
+            .map(|axis| (q[axis] - p[axis]).abs())
+            .fold(0.0, f32::max)
+    }
+
+    fn axis_distance(&self, _axis: usize, delta: f32) -> f32 {
+        delta.abs()
+    }
+}
+
+/// The straight-line distance after the difference between the positions
+/// along each axis is scaled by a weight for that axis.
+///
+/// This searches an anisotropic space, such as a feature space whose axes are
+/// measured in different units, without scaling the positions of the points.
+#[derive(Debug, Clone, Copy)]
+pub struct WeightedEuclidean {
+    weights: [f32; 3],
+}
+
+impl WeightedEuclidean {
+    /// Constructs a weighted Euclidean metric, in which the difference between
+    /// two positions along the grid's `i`th axis is multiplied by
+    /// `weights[i]`.
+    ///
+    /// Panics if a weight is negative or NaN.
+    pub fn new(weights: [f32; 3]) -> Self {
+        assert!(
+            weights.iter().all(|&weight| weight >= 0.0),
+            "weights must be non-negative"
+        );
+        Self { weights }
+    }
+
+    /// Returns the weight of each axis.
+    pub fn weights(&self) -> [f32; 3] {
+        self.weights
+    }
+}
+
+impl Metric for WeightedEuclidean {
+    fn distance(&self, p: [f32; 3], q: [f32; 3]) -> f32 {
+        (0..3)
+            .map(|axis| {
+                let delta = self.weights[axis] * (q[axis] - p[axis]);
+                delta * delta
+            })
+            .sum::<f32>()
+            .sqrt()
+    }
+
+    fn axis_distance(&self, axis: usize, delta: f32) -> f32 {
+        (self.weights[axis] * delta).abs()
+    }
+}
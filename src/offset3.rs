@@ -1,4 +1,4 @@
-use std::ops::Add;
+use std::{fmt, ops::Add};
 
 use serde::{Deserialize, Serialize};
 
@@ -26,21 +26,23 @@ impl Offset3 {
     /// If the offset references a cell that is outside the bounds of the grid,
     /// then this will return `None`.
     pub fn into_grid_index1(self, grid_size: (usize, usize, usize)) -> Option<usize> {
-        if self.x >= 0
-            && (self.x as usize) < grid_size.0
-            && self.y >= 0
-            && (self.y as usize) < grid_size.1
-            && self.z >= 0
-            && (self.z as usize) < grid_size.2
-        {
-            Some(
-                (self.x as usize)
-                    + (self.y as usize) * grid_size.0
-                    + (self.z as usize) * grid_size.0 * grid_size.1,
-            )
-        } else {
-            None
-        }
+        self.into_grid_index1_checked(grid_size).ok()
+    }
+
+    /// Converts the 3-dimensional offset into a 1-dimensional index.
+    ///
+    /// This behaves like `into_grid_index1`, except that when the offset
+    /// references a cell that is outside the bounds of the grid, the returned
+    /// error names the first axis, checked in x, y, z order, on which the
+    /// offset is out of bounds.
+    pub fn into_grid_index1_checked(
+        self,
+        grid_size: (usize, usize, usize),
+    ) -> Result<usize, OutOfBounds> {
+        let x = axis_in_bounds(self.x, grid_size.0, Axis::X)?;
+        let y = axis_in_bounds(self.y, grid_size.1, Axis::Y)?;
+        let z = axis_in_bounds(self.z, grid_size.2, Axis::Z)?;
+        Ok(x + y * grid_size.0 + z * grid_size.0 * grid_size.1)
     }
 
     /// Converts a 1-dimensional index into a 3-dimensional offset.
@@ -56,6 +58,112 @@ impl Offset3 {
     }
 }
 
+/// One of the three axes of 3-dimensional space.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub enum Axis {
+    X,
+    Y,
+    Z,
+}
+
+impl Axis {
+    /// Returns the index of the axis's component in a position.
+    pub(crate) fn index(self) -> usize {
+        match self {
+            Axis::X => 0,
+            Axis::Y => 1,
+            Axis::Z => 2,
+        }
+    }
+}
+
+impl fmt::Display for Axis {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Axis::X => write!(f, "x"),
+            Axis::Y => write!(f, "y"),
+            Axis::Z => write!(f, "z"),
+        }
+    }
+}
+
+/// The rule that decides which cell a point is bucketed into when it lies
+/// exactly on the boundary between two cells along an axis.
+///
+/// Points that aren't on a boundary are always bucketed into the cell that
+/// contains them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Deserialize, Serialize)]
+pub enum BucketBoundary {
+    /// Points on a boundary are bucketed into the cell above the boundary.
+    #[default]
+    Upper,
+
+    /// Points on a boundary are bucketed into the cell below the boundary,
+    /// except for points on the minimum face of the grid, which have no cell
+    /// below them.
+    Lower,
+
+    /// Points on a boundary are bucketed into whichever of the two cells has
+    /// an even offset along the axis, so that a regular lattice of points on
+    /// the boundaries isn't biased towards either side.
+    HalfToEven,
+}
+
+impl BucketBoundary {
+    /// Returns the offset along an axis of the cell into which a coordinate is
+    /// bucketed, given the coordinate in units of cell widths relative to the
+    /// minimum corner of the grid.
+    pub(crate) fn bucket(self, relative: f32) -> i64 {
+        let floor = relative.floor();
+        let offset = floor as i64;
+        if relative != floor {
+            return offset;
+        }
+        match self {
+            BucketBoundary::Upper => offset,
+            BucketBoundary::Lower if offset > 0 => offset - 1,
+            BucketBoundary::Lower => offset,
+            BucketBoundary::HalfToEven if offset % 2 != 0 => offset - 1,
+            BucketBoundary::HalfToEven => offset,
+        }
+    }
+}
+
+/// Error returned when an offset references a cell outside the bounds of a
+/// grid.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutOfBounds {
+    /// The offset's component on the axis is negative.
+    Under(Axis),
+
+    /// The offset's component on the axis is greater than or equal to the
+    /// grid's width on that axis.
+    Over(Axis),
+}
+
+impl fmt::Display for OutOfBounds {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OutOfBounds::Under(axis) => write!(f, "offset is below the grid on the {} axis", axis),
+            OutOfBounds::Over(axis) => write!(f, "offset is above the grid on the {} axis", axis),
+        }
+    }
+}
+
+impl std::error::Error for OutOfBounds {}
+
+/// Returns the offset's component on a single axis as an index if it lies
+/// within `[0, width)`.
+fn axis_in_bounds(component: i64, width: usize, axis: Axis) -> Result<usize, OutOfBounds> {
+    if component < 0 {
+        Err(OutOfBounds::Under(axis))
+    } else if (component as usize) >= width {
+        Err(OutOfBounds::Over(axis))
+    } else {
+        Ok(component as usize)
+    }
+}
+
 impl Add for Offset3 {
     type Output = Self;
 
@@ -79,3 +187,55 @@ impl Add<&Self> for Offset3 {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checked_index_reports_under_bounds_on_x() {
+        let offset = Offset3::new(-1, 2, 3);
+        assert_eq!(
+            offset.into_grid_index1_checked((4, 4, 4)),
+            Err(OutOfBounds::Under(Axis::X))
+        );
+    }
+
+    #[test]
+    fn checked_index_reports_over_bounds_on_z() {
+        let offset = Offset3::new(1, 2, 4);
+        assert_eq!(
+            offset.into_grid_index1_checked((4, 4, 4)),
+            Err(OutOfBounds::Over(Axis::Z))
+        );
+        assert_eq!(
+            Offset3::new(1, 2, 3).into_grid_index1_checked((4, 4, 4)),
+            Ok(57)
+        );
+    }
+
+    #[test]
+    fn boundary_rules_send_boundary_points_to_expected_cells() {
+        for relative in [0.0, 1.0, 2.0, 3.0] {
+            assert_eq!(BucketBoundary::Upper.bucket(relative), relative as i64);
+        }
+        assert_eq!(BucketBoundary::Lower.bucket(0.0), 0);
+        assert_eq!(BucketBoundary::Lower.bucket(1.0), 0);
+        assert_eq!(BucketBoundary::Lower.bucket(3.0), 2);
+        assert_eq!(BucketBoundary::HalfToEven.bucket(0.0), 0);
+        assert_eq!(BucketBoundary::HalfToEven.bucket(1.0), 0);
+        assert_eq!(BucketBoundary::HalfToEven.bucket(2.0), 2);
+        assert_eq!(BucketBoundary::HalfToEven.bucket(3.0), 2);
+
+        // Points that aren't on a boundary are bucketed the same way by every rule.
+        for boundary in [
+            BucketBoundary::Upper,
+            BucketBoundary::Lower,
+            BucketBoundary::HalfToEven,
+        ] {
+            assert_eq!(boundary.bucket(0.5), 0);
+            assert_eq!(boundary.bucket(2.999), 2);
+            assert_eq!(boundary.bucket(3.001), 3);
+        }
+    }
+}
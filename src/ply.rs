@@ -0,0 +1,136 @@
+use std::{fmt, io};
+
+use ply_rs::{parser, ply};
+
+use crate::{point_object::PointObject, UniformGrid, UniformGridBuilder};
+
+/// A vertex read from the "vertex" element of a PLY file.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PlyVertex {
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+}
+
+impl PointObject for PlyVertex {
+    fn position(&self) -> [f32; 3] {
+        [self.x, self.y, self.z]
+    }
+}
+
+impl ply::PropertyAccess for PlyVertex {
+    fn new() -> Self {
+        PlyVertex {
+            x: 0.0,
+            y: 0.0,
+            z: 0.0,
+        }
+    }
+
+    fn set_property(&mut self, key: String, property: ply::Property) {
+        // Properties other than the vertex coordinates, such as normals or colors, are
+        // ignored.
+        match (key.as_ref(), property) {
+            ("x", ply::Property::Float(v)) => self.x = v,
+            ("y", ply::Property::Float(v)) => self.y = v,
+            ("z", ply::Property::Float(v)) => self.z = v,
+            ("x", ply::Property::Double(v)) => self.x = v as f32,
+            ("y", ply::Property::Double(v)) => self.y = v as f32,
+            ("z", ply::Property::Double(v)) => self.z = v as f32,
+            _ => {}
+        }
+    }
+}
+
+/// Error returned when a uniform grid can't be constructed from a PLY file.
+#[derive(Debug)]
+pub enum PlyError {
+    /// The file couldn't be read or parsed.
+    Io(io::Error),
+
+    /// The file doesn't contain a "vertex" element.
+    MissingVertexElement,
+}
+
+impl fmt::Display for PlyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PlyError::Io(e) => write!(f, "failed to read PLY file: {}", e),
+            PlyError::MissingVertexElement => write!(f, "PLY file has no vertex element"),
+        }
+    }
+}
+
+impl std::error::Error for PlyError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            PlyError::Io(e) => Some(e),
+            PlyError::MissingVertexElement => None,
+        }
+    }
+}
+
+impl From<io::Error> for PlyError {
+    fn from(e: io::Error) -> Self {
+        PlyError::Io(e)
+    }
+}
+
+impl UniformGrid<PlyVertex> {
+    /// Constructs a uniform grid from the vertices in a PLY file.
+    ///
+    /// Spiral cells that cover the constructed grid are generated
+    /// automatically.
+    pub fn from_ply(path: &str, scale: f32) -> Result<UniformGrid<PlyVertex>, PlyError> {
+        let vertices = read_vertices(path)?;
+        Ok(UniformGridBuilder::new().scale(scale).build(vertices))
+    }
+}
+
+/// Reads the vertices from the "vertex" element of a PLY file.
+fn read_vertices(path: &str) -> Result<Vec<PlyVertex>, PlyError> {
+    let file = std::fs::File::open(path)?;
+    let mut reader = io::BufReader::new(file);
+    let vertex_parser = parser::Parser::<PlyVertex>::new();
+    let header = vertex_parser.read_header(&mut reader)?;
+
+    // Elements are stored in the order they're declared in the header, so every
+    // element before the "vertex" element must be read to reach the vertices.
+    for (_, element) in &header.elements {
+        let payload = vertex_parser.read_payload_for_element(&mut reader, element, &header)?;
+        if element.name == "vertex" {
+            return Ok(payload);
+        }
+    }
+
+    Err(PlyError::MissingVertexElement)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn grid_from_ply_finds_nearest_vertex() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/resources/tetrahedron.ply");
+        let grid = UniformGrid::from_ply(path, 1.0).unwrap();
+        assert_eq!(grid.len(), 4);
+
+        let (nearest, distance) = grid.nearest_neighbor([0.1, 0.9, 0.2]).unwrap();
+        assert_eq!(
+            *nearest,
+            PlyVertex {
+                x: 0.0,
+                y: 1.0,
+                z: 0.0
+            }
+        );
+        assert!((distance.squared() - 0.06).abs() < 1.0e-6);
+    }
+
+    #[test]
+    fn missing_ply_file_is_an_io_error() {
+        let result = UniformGrid::from_ply("no/such/file.ply", 1.0);
+        assert!(matches!(result, Err(PlyError::Io(_))));
+    }
+}
@@ -1,3 +1,23 @@
 pub trait PointObject {
     fn position(&self) -> [f32; 3];
+
+    /// Returns the category of the point, which can be used to restrict
+    /// queries such as `UniformGrid::nearest_neighbor_in_category` to points
+    /// of a single category. Defaults to `0`.
+    fn category(&self) -> u32 {
+        0
+    }
+
+    /// Returns the surface normal at the point, if the point has one, which is
+    /// returned alongside the point by queries such as
+    /// `UniformGrid::nearest_neighbor_with_normal`. Defaults to `None`.
+    fn normal(&self) -> Option<[f32; 3]> {
+        None
+    }
+}
+
+impl PointObject for [f32; 3] {
+    fn position(&self) -> [f32; 3] {
+        *self
+    }
 }
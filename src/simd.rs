@@ -0,0 +1,109 @@
+use wide::{f32x8, CmpLt};
+
+const LANES: usize = 8;
+
+/// A batch of points whose squared distances to a query point are
+/// computed together.
+pub(crate) struct Batch {
+    query: [f32x8; 3],
+    coords: [[f32; LANES]; 3],
+    point_indices: [usize; LANES],
+    len: usize,
+}
+
+impl Batch {
+    pub fn new(query_point: [f32; 3]) -> Self {
+        Self {
+            query: query_point.map(f32x8::splat),
+            coords: [[0.0; LANES]; 3],
+            point_indices: [0; LANES],
+            len: 0,
+        }
+    }
+
+    /// Adds a point to the batch, and returns true if the batch is full.
+    pub fn push(&mut self, position: [f32; 3], point_index: usize) -> bool {
+        let lane = self.len % LANES;
+        self.coords[0][lane] = position[0];
+        self.coords[1][lane] = position[1];
+        self.coords[2][lane] = position[2];
+        self.point_indices[lane] = point_index;
+        self.len += 1;
+        self.len == LANES
+    }
+
+    /// Updates `maybe_nearest` with the point in the batch that is closest
+    /// to the query point, if it's closer than the nearest point found so
+    /// far, and empties the batch.
+    pub fn scan(&mut self, maybe_nearest: &mut Option<(usize, f32)>) {
+        let len = std::mem::take(&mut self.len);
+        if len == 0 {
+            return;
+        }
+
+        let [x, y, z] = [0, 1, 2].map(|axis| f32x8::from(self.coords[axis]) - self.query[axis]);
+        let distances2 = x * x + y * y + z * z;
+
+        // Most batches have no point closer than the nearest point found so far, and
+        // those can be skipped without looking at each lane. Lanes past the end of the
+        // batch hold stale coordinates, but they're only ever compared here.
+        if let Some((_, nearest_distance2)) = *maybe_nearest {
+            if !distances2.cmp_lt(f32x8::splat(nearest_distance2)).any() {
+                return;
+            }
+        }
+
+        let distances2 = distances2.to_array();
+        let lanes = distances2.into_iter().zip(self.point_indices).take(len);
+        for (distance2, point_index) in lanes {
+            // A point whose distance is NaN, because its position or the query point has a
+            // NaN coordinate, is never the nearest point.
+            if distance2.is_nan() {
+                continue;
+            }
+            match maybe_nearest {
+                Some((_, nearest_distance2)) if *nearest_distance2 <= distance2 => {}
+                _ => *maybe_nearest = Some((point_index, distance2)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::uniform_grid::dist2;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    #[test]
+    fn batched_nearest_matches_scalar_nearest() {
+        let mut rng = StdRng::seed_from_u64(650);
+        for point_count in [0, 1, 7, 8, 9, 100, 1000] {
+            let mut positions: Vec<[f32; 3]> = (0..point_count).map(|_| rng.gen()).collect();
+            if point_count > 20 {
+                // Exact ties and NaN coordinates are broken and skipped the same way.
+                positions[17] = positions[3];
+                positions[5] = [f32::NAN, 0.5, 0.5];
+            }
+            for _ in 0..20 {
+                let query_point: [f32; 3] = rng.gen();
+                let mut batch = Batch::new(query_point);
+                let mut batched = None;
+                for (point_index, &position) in positions.iter().enumerate() {
+                    if batch.push(position, point_index) {
+                        batch.scan(&mut batched);
+                    }
+                }
+                batch.scan(&mut batched);
+
+                let scalar = positions
+                    .iter()
+                    .enumerate()
+                    .map(|(point_index, &position)| (point_index, dist2(query_point, position)))
+                    .filter(|(_, distance2)| !distance2.is_nan())
+                    .min_by(|(_, d1), (_, d2)| d1.total_cmp(d2));
+                assert_eq!(batched, scalar);
+            }
+        }
+    }
+}
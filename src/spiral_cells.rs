@@ -1,29 +1,85 @@
 use itertools::Itertools;
 use serde::{Deserialize, Serialize};
-use std::{ops::Neg, path::Path, vec};
+use std::{fmt, ops::Neg, path::Path, vec};
 
 use crate::offset3::Offset3;
 
+/// A cell in a vector of cells that "spiral" outward from an "origin cell" at
+/// `(0, 0, 0)`.
+///
+/// Distances between cells are measured in units of cell width, and the
+/// distance between two cells is the distance between their closest points.
 #[derive(Debug, Clone, Copy, Deserialize, Serialize)]
 pub struct SpiralCell {
-    /// 3-dimensional index of the cell in a 3-dimensional grid.
+    /// 3-dimensional offset of the cell from the "origin cell".
+    ///
+    /// Spiral cells are only generated for offsets where 0 <= x <= y <= z.
+    /// The offsets of the other cells that are the same distance from the
+    /// "origin cell" can be generated with `offset_variations`.
     pub offset: Offset3,
 
     /// Index into the last cell in a vector of sorted `SpiralCell`s
-    /// that could possibly contain points that are closer to the origin than
-    /// points in this cell.
+    /// that could possibly contain points that are closer to a point in the
+    /// "origin cell" than points in this cell.
     pub stop_cell_index1: usize,
 }
 
+impl SpiralCell {
+    /// Returns the 3-dimensional offset of the cell from the "origin cell".
+    pub fn offset(&self) -> Offset3 {
+        self.offset
+    }
+
+    /// Returns the index of the last cell in the vector of sorted
+    /// `SpiralCell`s that could possibly contain points that are closer to a
+    /// point in the "origin cell" than points in this cell.
+    pub fn stop_cell_index1(&self) -> usize {
+        self.stop_cell_index1
+    }
+}
+
+/// Error returned when spiral cells don't extend far enough to reach every
+/// cell of a uniform grid from every other cell of the grid.
+///
+/// Nearest-neighbor queries on such a grid can still be answered, but they
+/// may fall back to a brute-force search or return a point that isn't the
+/// nearest one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpiralTooSmall {
+    /// The number of cells that the spiral cells extend outward from the
+    /// origin cell along each axis, including the origin cell itself.
+    pub spiral_width: usize,
+
+    /// The number of cells along the grid's largest dimension, which is the
+    /// smallest spiral width that covers the grid.
+    pub grid_width: usize,
+}
+
+impl fmt::Display for SpiralTooSmall {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "spiral cells extend {} cells but the grid is {} cells wide",
+            self.spiral_width, self.grid_width
+        )
+    }
+}
+
+impl std::error::Error for SpiralTooSmall {}
+
 /// Returns a vector of `SpiralCell`s sorted by each cell's distance to the
-/// origin, as measured from each cell's closest corner to the origin.
+/// "origin cell", as measured between the closest points of the two cells.
+/// Cells at the same distance are sorted by the distance between their
+/// corners that are closest to the origin.
 ///
 /// The cells in the vector are sorted in such a way that they "spiral" out from
-/// the origin.
+/// the origin. The first cell is always the "origin cell" at `(0, 0, 0)`, and
+/// the distance to the "origin cell" never decreases along the vector.
 ///
 /// Each cell in the vector is associated with a `stop_cell_index`. The index
 /// points the last cell in the vector that could possibly contain points that
-/// are closer to the origin than points in the current cell.
+/// are closer to a point in the "origin cell" than points in the current cell.
+/// The `stop_cell_index` of a cell is never less than the cell's own index.
 ///
 /// The `stop_cell_index` is useful when searching for points closest to the
 /// origin because if we find a point in some cell, then the `stop_cell_index`
@@ -33,7 +89,7 @@ pub struct SpiralCell {
 /// to be checked before the search can be terminated.
 pub fn spiral_cells(width: usize) -> Vec<SpiralCell> {
     let mut offsets = wedge_offsets(width);
-    offsets.sort_by_key(|c| closest_to_origin2(*c));
+    offsets.sort_by_key(|c| (closest_to_origin2(*c), to_origin2(c.x, c.y, c.z)));
     offsets
         .iter()
         .enumerate()
@@ -53,7 +109,6 @@ pub fn read<P: AsRef<Path>>(path: P) -> Vec<SpiralCell> {
 fn wedge_offsets(width: usize) -> Vec<Offset3> {
     let width = width as i64;
     (0..width)
-        .into_iter()
         .flat_map(|x| (x..width).flat_map(move |y| (y..width).map(move |z| Offset3::new(x, y, z))))
         .collect_vec()
 }
@@ -61,11 +116,11 @@ fn wedge_offsets(width: usize) -> Vec<Offset3> {
 /// Finds the index of the "stop cell" for the given "start cell".
 ///
 /// The "stop cell" for a given "start cell" is the last cell in `sorted_coords`
-/// that could possibly contain points that are closer to the origin than points
-/// in the "start cell".
+/// that could possibly contain points that are closer to a point in the
+/// "origin cell" than points in the "start cell".
 ///
 /// * `sorted_coords` - Vector of cells sorted by each cell's distance to the
-///   origin, as measured from each cell's closest corner to the origin.
+///   "origin cell", as measured by `closest_to_origin2`.
 /// * `stop_cell_index` - Index into `sorted_coords` of the "start cell".
 /// * `stop_cell_coord` - Coordinates of the "start cell".
 fn find_stop_cell_index(
@@ -83,8 +138,9 @@ fn find_stop_cell_index(
     }
 }
 
-/// Returns the index of the first cell whose closest point to the origin is
-/// greater than the given squared distance.
+/// Returns the index of the first cell whose squared distance to the "origin
+/// cell", as measured by `closest_to_origin2`, is greater than the given
+/// squared distance.
 fn find_first_out_of_range(
     sorted_cell_offsets: &[Offset3],
     start_cell_index: usize,
@@ -107,27 +163,22 @@ fn find_first_out_of_range(
 /// 3-dimensional space around the "origin cell" at (0, 0, 0). For each spiral
 /// cell's offset, we can use this function to generate the offsets of other
 /// cells that are the same distance from the "origin cell". (Here, distance is
-/// measured from the corner of the spiral cell that is closed to the "origin
-/// cell" to the corner of the "origin cell" that is furthest from the spiral
-/// cell.)
+/// measured between the closest points of the spiral cell and the "origin
+/// cell".)
 pub fn offset_variations(cell_offset: Offset3) -> Vec<Offset3> {
-    [
-        cell_offset.x as i64,
-        cell_offset.y as i64,
-        cell_offset.z as i64,
-    ]
-    .iter()
-    .permutations(3)
-    .map(|cell_offset_compoonents| {
-        Offset3::new(
-            *cell_offset_compoonents[0],
-            *cell_offset_compoonents[1],
-            *cell_offset_compoonents[2],
-        )
-    })
-    .flat_map(negations)
-    .unique()
-    .collect_vec()
+    [cell_offset.x, cell_offset.y, cell_offset.z]
+        .iter()
+        .permutations(3)
+        .map(|cell_offset_compoonents| {
+            Offset3::new(
+                *cell_offset_compoonents[0],
+                *cell_offset_compoonents[1],
+                *cell_offset_compoonents[2],
+            )
+        })
+        .flat_map(negations)
+        .unique()
+        .collect_vec()
 }
 
 /// Returns all variations of the offset that can be generated by negating it's
@@ -145,17 +196,21 @@ fn negations(offset: Offset3) -> Vec<Offset3> {
     ]
 }
 
-/// Returns the squared distance between the origin and the cell's closest
-/// corner to the origin.
+/// Returns the squared distance between the closest pair of points in the
+/// "origin cell" and the cell.
+///
+/// Along each axis, a cell whose offset component is `c > 0` is separated from
+/// the "origin cell" by `c - 1` cells.
 ///
 /// This assumes that all components of `cell_offset` are non-negative, which
 /// should be the case when constructing a spiral cell table.
 pub fn closest_to_origin2(cell_offset: Offset3) -> i64 {
-    to_origin2(cell_offset.x, cell_offset.y, cell_offset.z)
+    let gap = |c: i64| (c - 1).max(0);
+    to_origin2(gap(cell_offset.x), gap(cell_offset.y), gap(cell_offset.z))
 }
 
-/// Returns the squared distance between the origin and the cell's furthest
-/// corner from the origin.
+/// Returns the squared distance between the furthest pair of points in the
+/// "origin cell" and the cell.
 ///
 /// This assumes that all components of `cell_offset` are non-negative, which
 /// should be the case when constructing a spiral cell table.
@@ -169,3 +224,31 @@ pub fn furthest_to_origin2(cell_offset: Offset3) -> i64 {
 fn to_origin2(x: i64, y: i64, z: i64) -> i64 {
     x * x + y * y + z * z
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spiral_cells_spiral_outward_with_stop_cells_ahead() {
+        let cells = spiral_cells(8);
+        assert_eq!(cells.len(), 8 * 9 * 10 / 6);
+        assert_eq!(cells[0].offset(), Offset3::new(0, 0, 0));
+
+        for (index, cell) in cells.iter().enumerate() {
+            let offset = cell.offset();
+            assert!(0 <= offset.x && offset.x <= offset.y && offset.y <= offset.z);
+            assert!(cell.stop_cell_index1() >= index);
+            assert!(cell.stop_cell_index1() < cells.len());
+
+            // Every cell past the stop cell is further from the origin cell than any
+            // point in this cell.
+            if let Some(after_stop) = cells.get(cell.stop_cell_index1() + 1) {
+                assert!(closest_to_origin2(after_stop.offset()) > furthest_to_origin2(offset));
+            }
+        }
+        for (cell, next) in cells.iter().tuple_windows() {
+            assert!(closest_to_origin2(cell.offset()) <= closest_to_origin2(next.offset()));
+        }
+    }
+}
@@ -1,398 +1,6847 @@
-use itertools::Itertools;
+use std::{
+    cmp::Ordering,
+    fmt,
+    ops::{ControlFlow, Range},
+    time::{Duration, Instant},
+};
+
+use itertools::{Either, Itertools};
 
 use crate::{
     bounding_box::BoundingBox,
+    builder::{CellSize, GridOptions},
+    cell_positions::CellPositions,
+    cells_along_ray::CellsAlongRay,
+    distance::Distance,
     f32::{max_f32, min_f32},
-    offset3::Offset3,
+    grid_index::{GridIndex, IndexMismatch},
+    grid_reader::GridReader,
+    metric::Metric,
+    offset3::{BucketBoundary, Offset3},
     point_object::PointObject,
-    spiral_cells::{self, SpiralCell},
+    spiral_cells::{self, SpiralCell, SpiralTooSmall},
+    validation::ValidationError,
 };
 
+#[cfg(feature = "simd")]
+use crate::simd::Batch;
+
 /// The uniform grid is a 3-dimensional grid of cube-shaped cells that covers a
 /// finite region in infinite 3-dimensional space. Each cell is a container for
-/// points that are positioned inside the space covered by the cell.
+/// points that are positioned inside the space covered by the cell. The cells
+/// can instead be boxes with a different width along each axis, as set by
+/// `UniformGridBuilder::cell_size`.
+///
+/// Positions are stored relative to the minimum corner of the grid, and query
+/// points are translated the same way before they're searched. This keeps the
+/// coordinates that the searches work with small, even when the points are far
+/// from the origin, so cell lookups and distances don't lose precision to
+/// large coordinates. Points and distances that are returned by queries are
+/// unaffected.
+///
+/// Cloning a grid copies its points and cells, so the clone can be changed,
+/// such as with `extend`, without affecting the original.
+#[derive(Clone)]
 pub struct UniformGrid<T>
 where
     T: PointObject,
 {
     point_objs: Vec<T>,
 
+    /// The position of each point, indexed the same way as `point_objs`.
+    positions: Vec<[f32; 3]>,
+
     /// A flat vector that contains one element for each cell in the
     /// 3-dimensional grid. Each element contains a count of the number of
     /// points that are bucketed into that cell.
     cell_point_counts: Vec<usize>,
 
-    /// A flat vector that contains one element for each cell in the
-    /// 3-dimensional grid. Each element contains a vector of the points that
-    /// are bucketed into that cell. Each point is represented by a tuple
-    /// containing the point's position in 3-dimensional space and the point's
-    /// index in `point_objs`.
-    cell_point_positions: Vec<Vec<([f32; 3], usize)>>,
+    /// Contains one element for each cell in the 3-dimensional grid. Each
+    /// element contains the points that are bucketed into that cell. Each point
+    /// is represented by its position relative to `min_position` and its index
+    /// in `point_objs`.
+    cell_point_positions: CellPositions,
 
     /// The minimum position in space that is covered by the uniform grid.
     min_position: [f32; 3],
 
-    // The width in space that is covered by each cube-shaped cell in the uniform grid.
-    cell_width: f32,
+    /// The width in space that is covered by each cell of the uniform grid along
+    /// each axis, which is the same along every axis unless the cells were
+    /// shaped with `CellSize::PerAxis`.
+    cell_widths: [f32; 3],
 
     /// The number of cells in each dimension of the uniform grid.
     grid_dimensions: (usize, usize, usize),
 
+    /// The options that were used to construct the uniform grid. They're kept
+    /// so that the grid can be rebuilt the same way.
+    options: GridOptions,
+
     /// Vector of `SpiralCell`s that indicate which cells to check when
     /// searching for nearest neighbors outward from some center cell.
     spiral_cells: Vec<SpiralCell>,
+
+    /// The number of cells, along each axis, that the spiral cells extend
+    /// outward from the center cell, including the center cell itself.
+    spiral_width: i64,
+
+    /// The source tag of each range of point indices whose points came from
+    /// the same source, sorted by the start of the range. Points outside of
+    /// every range have no source tag.
+    source_ranges: Vec<(u32, Range<usize>)>,
+
+    /// The minimum and maximum corners of the bounding box of the points'
+    /// positions, with their axes in the grid's order, or `None` if the grid
+    /// is empty.
+    data_corners: Option<([f32; 3], [f32; 3])>,
 }
 
 impl<T> UniformGrid<T>
 where
     T: PointObject,
 {
+    /// Constructs a uniform grid that contains the given points.
+    ///
+    /// See `UniformGridBuilder::scale` for how `scale` sets the number of
+    /// cells, including scales that are zero or negative. Panics if the grid
+    /// would have too many cells, which `UniformGridBuilder::try_build` returns
+    /// as an error instead.
     pub fn new(points: Vec<T>, scale: f32, spiral_cells: Vec<SpiralCell>) -> Self {
-        // The maximum number of cells that the grid will be able to contain.
-        let max_grid_width = scale * (points.len() as f32).cbrt();
-        let max_cell_count = (max_grid_width * max_grid_width * max_grid_width) as u32;
+        let options = GridOptions {
+            scale,
+            ..GridOptions::default()
+        };
+        Self::from_options(points, options, spiral_cells)
+    }
 
-        let bb = BoundingBox::new(&points);
-        println!(
-            "Bounding box: ({:.2?}, {:.2?}, {:.2?}), ({:.2?}, {:.2?}, {:.2?})",
-            bb.min[0], bb.min[1], bb.min[2], bb.x_width, bb.y_width, bb.z_width
-        );
-
-        // For simplicity we assume that we're constructing a uniform grid that has the
-        // same number of cells in each dimension. To save space, we should allow
-        // different widths in each dimension.
-        let cube_bb_width = max_f32(bb.x_width, max_f32(bb.y_width, bb.z_width));
-        // The max number of cells we can have in a single dimension while staying under
-        // the max cell count.
-        let cube_grid_width = (max_cell_count as f32).cbrt() as usize;
-        let grid_dimensions = (cube_grid_width, cube_grid_width, cube_grid_width);
-
-        // Make each cell slightly larger than is necessary to fit perfectly within the
-        // bounding box so that points on a maximum face of the bounding box can fit
-        // into a cell.
-        let cell_width = cube_bb_width * 1.01 / cube_grid_width as f32;
-
-        let cell_count = grid_dimensions.0 * grid_dimensions.1 * grid_dimensions.2;
-        let mut cell_point_counts: Vec<usize> = vec![0; cell_count];
-        for point in &points {
-            let cell_index =
-                point_into_index1(point.position(), bb.min, cell_width, grid_dimensions).unwrap();
-            cell_point_counts[cell_index] += 1;
+    /// Constructs a uniform grid like `new`, but returns an error if the
+    /// spiral cells don't extend far enough to reach every cell of the grid
+    /// from every other cell.
+    ///
+    /// With spiral cells that are too small for the grid, queries silently
+    /// fall back to slower searches that may not find the nearest point. This
+    /// catches spiral cells that were generated or read for a smaller grid.
+    /// The points are dropped along with the grid when an error is returned;
+    /// `spiral_covers_grid` makes the same check on an existing grid.
+    pub fn try_new(
+        points: Vec<T>,
+        scale: f32,
+        spiral_cells: Vec<SpiralCell>,
+    ) -> Result<Self, SpiralTooSmall> {
+        let grid = Self::new(points, scale, spiral_cells);
+        if grid.spiral_covers_grid() {
+            Ok(grid)
+        } else {
+            let (x_width, y_width, z_width) = grid.grid_dimensions;
+            Err(SpiralTooSmall {
+                spiral_width: grid.spiral_width as usize,
+                grid_width: x_width.max(y_width).max(z_width),
+            })
+        }
+    }
+
+    /// Constructs a uniform grid that contains the points of several sources,
+    /// and remembers the source that each point came from.
+    ///
+    /// Each source is given by its tag and its points. The points of the
+    /// sources are concatenated in order, so the points of each source get
+    /// consecutive indices. The tag of a point's source is returned by
+    /// `source_tag` and `nearest_neighbor_with_source`.
+    pub fn from_tagged_sources(
+        sources: Vec<(u32, Vec<T>)>,
+        scale: f32,
+        spiral_cells: Vec<SpiralCell>,
+    ) -> Self {
+        let mut points = Vec::with_capacity(sources.iter().map(|(_, points)| points.len()).sum());
+        let mut source_ranges = Vec::with_capacity(sources.len());
+        for (tag, source_points) in sources {
+            let start = points.len();
+            points.extend(source_points);
+            source_ranges.push((tag, start..points.len()));
         }
 
-        // Pre-allocate the necessary space for the vector in each cell so that the
-        // vectors don't need to get re-allocated as new points are added.
-        let mut cell_point_positions = cell_point_counts
-            .iter()
-            .map(|&count| Vec::with_capacity(count))
-            .collect_vec();
+        let mut grid = Self::new(points, scale, spiral_cells);
+        grid.source_ranges = source_ranges;
+        grid
+    }
 
-        for (point_index, point) in points.iter().enumerate() {
-            let cell_index =
-                point_into_index1(point.position(), bb.min, cell_width, grid_dimensions).unwrap();
-            cell_point_positions[cell_index].push((point.position(), point_index));
+    /// Constructs a uniform grid like `new`, but over the given bounding box
+    /// instead of one that's computed from the points.
+    ///
+    /// This saves a pass over the points when their bounding box is already
+    /// known, such as from a previous pass or from metadata. The box doesn't
+    /// need to be the smallest box that contains the points, but the grid only
+    /// covers the box, so a box much larger than the points wastes cells.
+    ///
+    /// Panics if any point lies outside of the box. Points on the faces of the
+    /// box are inside it.
+    pub fn new_with_bounds(
+        points: Vec<T>,
+        bb: BoundingBox,
+        scale: f32,
+        spiral_cells: Vec<SpiralCell>,
+    ) -> Self {
+        if let Some(point_index) = points.iter().position(|p| !bb.contains(p.position())) {
+            panic!("point {} lies outside of the bounding box", point_index);
         }
+        let options = GridOptions {
+            scale,
+            ..GridOptions::default()
+        };
+        Self::from_options_and_bounds(points, options, bb, spiral_cells, |_, _| {}, &SerialPasses)
+            .unwrap_or_else(|error| panic!("{}", error))
+    }
 
-        Self {
+    /// Constructs a uniform grid like `new`, and reports how long each phase
+    /// of the construction takes.
+    ///
+    /// `on_phase` is called at the end of each phase with the phase and the
+    /// time that it took, in the order that the phases are listed in
+    /// `BuildPhase`. The callback only observes the construction, and the grid
+    /// is the same as the one that `new` constructs.
+    pub fn new_with_progress<P>(
+        points: Vec<T>,
+        scale: f32,
+        spiral_cells: Vec<SpiralCell>,
+        on_phase: P,
+    ) -> Self
+    where
+        P: FnMut(BuildPhase, Duration),
+    {
+        let options = GridOptions {
+            scale,
+            ..GridOptions::default()
+        };
+        Self::from_options_with_progress(points, options, spiral_cells, on_phase, &SerialPasses)
+            .unwrap_or_else(|error| panic!("{}", error))
+    }
+
+    /// Constructs a uniform grid like `new`, from points that are produced by
+    /// an iterator rather than collected into a vector first.
+    ///
+    /// `points` is called twice, and both iterators that it returns must
+    /// produce the same points in the same order. The first pass only reads
+    /// the positions of the points to find their bounding box and count them,
+    /// and the second pass moves the points into the grid, which is allocated
+    /// at its final size. The grid stores the points, so they do end up in
+    /// memory, but only once, without the reallocations and spare capacity of
+    /// collecting an iterator of unknown length. This suits points that are
+    /// cheap to stream from a file or to generate again.
+    ///
+    /// Panics if the second pass produces a different number of points than
+    /// the first, or a point outside of the bounding box that the first pass
+    /// found.
+    pub fn from_iter_two_pass<F, I>(points: F, scale: f32, spiral_cells: Vec<SpiralCell>) -> Self
+    where
+        F: Fn() -> I,
+        I: IntoIterator<Item = T>,
+    {
+        let mut point_count = 0;
+        let bb = BoundingBox::from_positions(points().into_iter().map(|point| {
+            point_count += 1;
+            point.position()
+        }));
+
+        let mut collected = Vec::with_capacity(point_count);
+        for point in points() {
+            assert!(
+                collected.len() < point_count,
+                "second pass produced more points than the first"
+            );
+            assert!(
+                bb.contains(point.position()),
+                "second pass produced point {} outside of the first pass's bounding box",
+                collected.len()
+            );
+            collected.push(point);
+        }
+        assert_eq!(
+            collected.len(),
+            point_count,
+            "second pass produced fewer points than the first"
+        );
+
+        let options = GridOptions {
+            scale,
+            ..GridOptions::default()
+        };
+        Self::from_options_and_bounds(
+            collected,
+            options,
+            bb,
+            spiral_cells,
+            |_, _| {},
+            &SerialPasses,
+        )
+        .unwrap_or_else(|error| panic!("{}", error))
+    }
+
+    pub(crate) fn from_options(
+        points: Vec<T>,
+        options: GridOptions,
+        spiral_cells: Vec<SpiralCell>,
+    ) -> Self {
+        Self::try_from_options(points, options, spiral_cells)
+            .unwrap_or_else(|error| panic!("{}", error))
+    }
+
+    pub(crate) fn try_from_options(
+        points: Vec<T>,
+        options: GridOptions,
+        spiral_cells: Vec<SpiralCell>,
+    ) -> Result<Self, GridTooLarge> {
+        Self::try_from_options_with(points, options, spiral_cells, &SerialPasses)
+    }
+
+    /// Constructs a uniform grid like `try_from_options`, making the passes
+    /// over the points with `passes`.
+    pub(crate) fn try_from_options_with<B>(
+        points: Vec<T>,
+        options: GridOptions,
+        spiral_cells: Vec<SpiralCell>,
+        passes: &B,
+    ) -> Result<Self, GridTooLarge>
+    where
+        B: BuildPasses<T>,
+    {
+        Self::from_options_with_progress(points, options, spiral_cells, |_, _| {}, passes)
+    }
+
+    fn from_options_with_progress<P, B>(
+        points: Vec<T>,
+        options: GridOptions,
+        spiral_cells: Vec<SpiralCell>,
+        mut on_phase: P,
+        passes: &B,
+    ) -> Result<Self, GridTooLarge>
+    where
+        P: FnMut(BuildPhase, Duration),
+        B: BuildPasses<T>,
+    {
+        let phase_start = Instant::now();
+        let bb = match options.periodic_box {
+            Some((box_min, box_max)) => BoundingBox {
+                min: box_min,
+                x_width: box_max[0] - box_min[0],
+                y_width: box_max[1] - box_min[1],
+                z_width: box_max[2] - box_min[2],
+            },
+            None => passes.bounding_box(&points, &options),
+        };
+        on_phase(BuildPhase::BoundingBox, phase_start.elapsed());
+        Self::from_options_and_bounds(points, options, bb, spiral_cells, on_phase, passes)
+    }
+
+    /// Constructs a uniform grid over the given bounding box, which must
+    /// contain the positions of all of the points with their axes in the
+    /// grid's order.
+    ///
+    /// `on_phase` is called at the end of each phase after the bounding box.
+    /// Returns an error, before anything is allocated for the cells, if the
+    /// grid would have too many cells.
+    fn from_options_and_bounds<P, B>(
+        mut points: Vec<T>,
+        options: GridOptions,
+        bb: BoundingBox,
+        spiral_cells: Vec<SpiralCell>,
+        mut on_phase: P,
+        passes: &B,
+    ) -> Result<Self, GridTooLarge>
+    where
+        P: FnMut(BuildPhase, Duration),
+        B: BuildPasses<T>,
+    {
+        let mut phase_start = Instant::now();
+        let mut end_phase = |phase: BuildPhase| {
+            on_phase(phase, phase_start.elapsed());
+            phase_start = Instant::now();
+        };
+
+        // The maximum number of cells that the grid will be able to contain. A scale
+        // that's zero, negative, or NaN would leave no cells, so the grid always gets
+        // at least one cell, which holds every point.
+        let max_grid_width = options.scale * (points.len() as f32).cbrt();
+        let max_cell_count = ((max_grid_width * max_grid_width * max_grid_width) as u32).max(1);
+
+        if options.canonical_order {
+            let extents = [bb.x_width, bb.y_width, bb.z_width];
+            points.sort_by_cached_key(|p| {
+                let pos = options.grid_position(p.position());
+                (morton_code(pos, bb.min, extents), pos.map(f32::to_bits))
+            });
+        }
+
+        // Points in a periodic grid are wrapped into the periodic box, and positions
+        // are stored relative to the minimum corner of the grid.
+        let bucketed_position = |point: &T| bucketed_position(point.position(), &options, bb.min);
+
+        // By default, make the grid slightly larger than is necessary to fit perfectly
+        // around the bounding box, so that the cells line up less often with points on
+        // a maximum face of the bounding box. Those points are clamped into the grid
+        // when they're bucketed either way.
+        let grid_extents =
+            [bb.x_width, bb.y_width, bb.z_width].map(|extent| extent * options.boundary_epsilon);
+
+        // Float noise in the bounding box shouldn't give axes that are meant to have
+        // the same width different numbers of cells.
+        let grid_extents = equalize_nearly_equal_extents(grid_extents);
+
+        let (min_x, min_y, min_z) = options.min_dimensions;
+        let cell_widths = match options.cell_size {
+            CellSize::Cubic => {
+                // Cells are cubes, but each dimension only gets as many cells as it needs to
+                // cover the bounding box, so elongated point sets don't waste cells along
+                // their short axes.
+                let cell_width = match options.target_occupancy {
+                    Some(target_occupancy) => {
+                        let positions = points.iter().map(bucketed_position).collect_vec();
+                        cell_width_for_occupancy(
+                            &positions,
+                            grid_extents,
+                            target_occupancy,
+                            options.bucket_boundary,
+                        )
+                    }
+                    None => cell_width_for_cell_count(grid_extents, max_cell_count as f32),
+                };
+
+                // Shrink the cells if the bounding box wouldn't otherwise span the minimum
+                // number of cells along each dimension.
+                let cell_width = [min_x, min_y, min_z]
+                    .into_iter()
+                    .zip(grid_extents)
+                    .filter(|&(min_cells, extent)| {
+                        extent > 0.0 && axis_cell_count(extent, cell_width) < min_cells
+                    })
+                    .map(|(min_cells, extent)| extent / min_cells as f32)
+                    .fold(cell_width, min_f32);
+
+                // Grow the cells if the bounding box would otherwise span more than the
+                // maximum number of cells along some dimension.
+                let cell_width = match options.max_dimension {
+                    Some(max_dimension) => grid_extents
+                        .into_iter()
+                        .map(|extent| extent / max_dimension.max(1) as f32)
+                        .fold(cell_width, max_f32),
+                    None => cell_width,
+                };
+                [cell_width; 3]
+            }
+
+            // Each axis is sized on its own, so the minimum and maximum numbers of cells
+            // only change the width along the axes that need it.
+            CellSize::PerAxis(cell_widths) => {
+                let min_dimensions = [min_x, min_y, min_z];
+                [0, 1, 2].map(|axis| {
+                    let (extent, min_cells) = (grid_extents[axis], min_dimensions[axis]);
+                    let cell_width =
+                        if extent > 0.0 && axis_cell_count(extent, cell_widths[axis]) < min_cells {
+                            extent / min_cells as f32
+                        } else {
+                            cell_widths[axis]
+                        };
+                    match options.max_dimension {
+                        Some(max_dimension) => {
+                            max_f32(cell_width, extent / max_dimension.max(1) as f32)
+                        }
+                        None => cell_width,
+                    }
+                })
+            }
+        };
+
+        // Dimensions along which the bounding box is flat still get the minimum
+        // number of cells, which extend past the bounding box.
+        let grid_dimensions = (
+            axis_cell_count(grid_extents[0], cell_widths[0]).max(min_x),
+            axis_cell_count(grid_extents[1], cell_widths[1]).max(min_y),
+            axis_cell_count(grid_extents[2], cell_widths[2]).max(min_z),
+        );
+
+        let cell_count = checked_cell_count(grid_dimensions).ok_or(GridTooLarge {
+            dimensions: grid_dimensions,
+        })?;
+        let layout = CellLayout {
+            min_position: bb.min,
+            cell_widths,
+            grid_dimensions,
+            cell_count,
+        };
+        let (cell_point_counts, cell_point_positions) =
+            passes.fill_cells(&points, &options, &layout, &mut end_phase);
+        let positions = points.iter().map(PointObject::position).collect_vec();
+        let data_corners = data_corners(&positions, &options, None);
+        end_phase(BuildPhase::Fill);
+
+        Ok(Self {
+            data_corners,
+            positions,
             point_objs: points,
             cell_point_counts,
             cell_point_positions,
             min_position: bb.min,
-            cell_width,
+            cell_widths,
             grid_dimensions,
+            options,
+            spiral_width: spiral_width(&spiral_cells),
             spiral_cells,
-        }
+            source_ranges: vec![],
+        })
     }
 
-    /// Finds the point in the uniform grid that is closest to the given query
-    /// point.
+    /// Constructs a uniform grid along with a vector of spiral cells that
+    /// extends `radius` cells outward from the origin cell, including the
+    /// origin cell itself.
     ///
-    /// Distance between points is Euclidean distance.
-    pub fn nearest_neighbor(&self, query_point: [f32; 3]) -> Option<(&T, f32)> {
-        let query_cell_offset = self.point_into_offset(query_point);
-        self.nearest_neighbor_in_query_cell(query_point, query_cell_offset)
-            .or_else(|| self.nearest_neighbor_spiral_search(query_point, query_cell_offset))
-            .or_else(|| self.nearest_neighbor_brute_force(query_point))
-            .map(|sr| {
-                (
-                    &self.point_objs[sr.point_object_index],
-                    sr.distance2_to_query,
-                )
-            })
+    /// The grid gets at most `radius` cells along each dimension, so the
+    /// spiral cells reach every cell of the grid from any query cell inside
+    /// it. Nearest-neighbor queries from inside the grid are then always exact
+    /// without needing to fall back to a brute-force search. The grid
+    /// otherwise gets as many cells as it would with the default options. A
+    /// `radius` of `0` is treated as `1`.
+    pub fn with_spiral_radius(points: Vec<T>, radius: usize) -> Self {
+        let radius = radius.max(1);
+        let options = GridOptions {
+            max_dimension: Some(radius),
+            ..GridOptions::default()
+        };
+        Self::from_options(points, options, spiral_cells::spiral_cells(radius))
     }
 
-    fn nearest_neighbor_in_query_cell(
-        &self,
-        query_point: [f32; 3],
-        query_cell_offset: Offset3,
-    ) -> Option<SearchResult> {
-        self.offset_into_index1(query_cell_offset)
-            .filter(|&query_cell_index| self.cell_point_counts[query_cell_index] > 0)
-            .map(|query_cell_index| {
-                // We know there is at least one point in the cell so this is ok.
-                let nearest_in_query_cell =
-                    nearest(query_point, &self.cell_point_positions[query_cell_index]).unwrap();
-
-                let dist_to_wall =
-                    self.nearest_wall_dist(nearest_in_query_cell.position, query_cell_offset);
-                if dist_to_wall * dist_to_wall > nearest_in_query_cell.distance2_to_query {
-                    // The neighbor is closer than any of the cell walls, so no need to search in
-                    // other cells.
-                    nearest_in_query_cell
-                } else {
-                    // Check the neighboring cells for points that might be closer.
-                    let maybe_nearest_in_neighbor_cells = self.nearest_in_cell_offsets(
-                        query_point,
-                        query_cell_offset,
-                        neighbor_offsets(),
-                    );
+    /// Constructs a uniform grid that contains the given points, with a scale
+    /// that's chosen from the points instead of given.
+    ///
+    /// The cells are sized so that there are between one and two points, on
+    /// average, in each non-empty cell, as with a target occupancy of `1.5`
+    /// set by `UniformGridBuilder::target_occupancy`. Since the number of cells
+    /// isn't known until the points have been analyzed, the spiral cells are
+    /// made by calling `spiral_cells` with the number of cells along the grid's
+    /// widest dimension, which `spiral_cells::spiral_cells` accepts directly.
+    ///
+    /// Choosing the scale buckets the points many times over, so construction
+    /// takes roughly ten to twenty times longer than with `new`, not counting
+    /// the spiral cells.
+    pub fn new_auto_scale<F>(points: Vec<T>, spiral_cells: F) -> Self
+    where
+        F: FnOnce(usize) -> Vec<SpiralCell>,
+    {
+        let options = GridOptions {
+            target_occupancy: Some(AUTO_SCALE_OCCUPANCY),
+            ..GridOptions::default()
+        };
+        Self::with_spiral_cells_for_grid(points, options, spiral_cells, &SerialPasses)
+            .unwrap_or_else(|error| panic!("{}", error))
+    }
 
-                    if let Some(nearest_in_neighbor_cells) = maybe_nearest_in_neighbor_cells {
-                        if nearest_in_query_cell.distance2_to_query
-                            <= nearest_in_neighbor_cells.distance2_to_query
-                        {
-                            nearest_in_query_cell
-                        } else {
-                            nearest_in_neighbor_cells
-                        }
-                    } else {
-                        nearest_in_query_cell
-                    }
-                }
-            })
+    /// Constructs a uniform grid along with a vector of spiral cells that is
+    /// large enough to cover the grid's widest dimension.
+    pub(crate) fn with_generated_spiral_cells<B>(
+        points: Vec<T>,
+        options: GridOptions,
+        passes: &B,
+    ) -> Result<Self, GridTooLarge>
+    where
+        B: BuildPasses<T>,
+    {
+        Self::with_spiral_cells_for_grid(points, options, spiral_cells::spiral_cells, passes)
     }
 
-    fn nearest_neighbor_spiral_search(
-        &self,
-        query_point: [f32; 3],
-        query_cell_offset: Offset3,
-    ) -> Option<SearchResult> {
-        // Use the sprial cells to spiral out and check points in each batch of cells
-        // that are equidistanct from the center cell until...
-        // - a first point is found in some cell, and then that cell's stop cell is
-        //   reached
-        // - or all spiral cells are exhausted
-        let mut maybe_stop_cell_index1: Option<usize> = None;
-        let mut maybe_nearest_so_far: Option<SearchResult> = None;
+    /// Constructs a uniform grid, and then its spiral cells by calling
+    /// `spiral_cells` with the number of cells along the grid's widest
+    /// dimension. A grid whose cells aren't cubes is searched without spiral
+    /// cells, so none are generated for it.
+    fn with_spiral_cells_for_grid<F, B>(
+        points: Vec<T>,
+        options: GridOptions,
+        spiral_cells: F,
+        passes: &B,
+    ) -> Result<Self, GridTooLarge>
+    where
+        F: FnOnce(usize) -> Vec<SpiralCell>,
+        B: BuildPasses<T>,
+    {
+        let mut grid = Self::try_from_options_with(points, options, vec![], passes)?;
+        if !grid.has_cubic_cells() {
+            return Ok(grid);
+        }
+        let (x, y, z) = grid.grid_dimensions;
+        grid.spiral_cells = spiral_cells(x.max(y).max(z));
+        grid.spiral_width = spiral_width(&grid.spiral_cells);
+        Ok(grid)
+    }
 
-        // Skip the first spiral cell, which is always (0, 0, 0), since that cell is
-        // checked before attempting spiral search.
-        for (spiral_cell_index1, spiral_cell) in self.spiral_cells.iter().enumerate().skip(1) {
-            // Terminate after the stop cell is checked.
-            if let Some(stop_cell_index1) = maybe_stop_cell_index1 {
-                if spiral_cell_index1 > stop_cell_index1 {
-                    break;
-                }
-            }
+    /// Exports the spatial index of the uniform grid, without its points.
+    ///
+    /// The index can be serialized and later combined with the same points,
+    /// in the same order, to rebuild the grid with `from_index`.
+    pub fn export_index(&self) -> GridIndex {
+        GridIndex {
+            cell_point_indices: (0..self.cell_point_positions.cell_count())
+                .map(|cell_index| {
+                    self.cell_points(cell_index)
+                        .map(|(_, point_index)| point_index)
+                        .collect()
+                })
+                .collect(),
+            min_position: self.min_position,
+            cell_widths: self.cell_widths,
+            grid_dimensions: self.grid_dimensions,
+            options: self.options.clone(),
+            spiral_cells: self.spiral_cells.clone(),
+        }
+    }
 
-            // Look for the nearest point in the next batch of cells that are equidistant
-            // from the center cell.
-            let maybe_nearest_in_spiral_cell = self.nearest_in_cell_offsets(
-                query_point,
-                query_cell_offset,
-                spiral_cells::offset_variations(spiral_cell.offset),
-            );
+    /// Rebuilds a uniform grid from an index that was exported with
+    /// `export_index` and the grid's points, in the same order.
+    ///
+    /// The points aren't re-bucketed, so this skips computing the bounding box
+    /// and laying out the grid. Returns an error if the index doesn't refer to
+    /// exactly one point at each index of `points`. If the points have moved
+    /// since the index was exported, then queries may return wrong results.
+    pub fn from_index(index: GridIndex, points: Vec<T>) -> Result<Self, IndexMismatch> {
+        let GridIndex {
+            cell_point_indices,
+            min_position,
+            cell_widths,
+            grid_dimensions,
+            options,
+            spiral_cells,
+        } = index;
 
-            if let Some(nearest_in_spiral_cell) = maybe_nearest_in_spiral_cell {
-                // A point has been found, so we don't need to search past the stop cell.
-                if maybe_stop_cell_index1.is_none() {
-                    maybe_stop_cell_index1 = Some(spiral_cell.stop_cell_index1);
-                }
+        if checked_cell_count(grid_dimensions) != Some(cell_point_indices.len()) {
+            return Err(IndexMismatch::CellCount);
+        }
+        let point_count = cell_point_indices.iter().map(Vec::len).sum();
+        if point_count != points.len() {
+            return Err(IndexMismatch::PointCount {
+                expected: point_count,
+                found: points.len(),
+            });
+        }
+        let mut is_indexed = vec![false; points.len()];
+        for &point_index in cell_point_indices.iter().flatten() {
+            match is_indexed.get_mut(point_index) {
+                Some(is_indexed @ false) => *is_indexed = true,
+                _ => return Err(IndexMismatch::InvalidPointIndex(point_index)),
+            }
+        }
 
-                // Check if the point that's found is the new nearest neighbor.
-                let is_new_nearest = match &maybe_nearest_so_far {
-                    None => true,
-                    Some(nearest_so_far) => {
-                        nearest_in_spiral_cell.distance2_to_query
-                            < nearest_so_far.distance2_to_query
-                    }
+        let cell_point_counts = cell_point_indices.iter().map(Vec::len).collect_vec();
+        let mut cell_point_positions = CellPositions::with_capacities(&cell_point_counts, &options);
+        for (cell_index, point_indices) in cell_point_indices.iter().enumerate() {
+            for &point_index in point_indices {
+                let position = options.grid_position(points[point_index].position());
+                let position = match options.periodic_box {
+                    Some(periodic_box) => wrap_into_box(position, periodic_box),
+                    None => position,
                 };
-
-                // Update the nearest neighbor.
-                if is_new_nearest {
-                    maybe_nearest_so_far = Some(nearest_in_spiral_cell)
-                }
+                cell_point_positions.push(
+                    cell_index,
+                    || cell_min(cell_index, cell_widths, grid_dimensions),
+                    to_local(position, min_position),
+                    point_index,
+                );
             }
         }
 
-        maybe_nearest_so_far
+        let positions = points.iter().map(PointObject::position).collect_vec();
+        Ok(Self {
+            data_corners: data_corners(&positions, &options, None),
+            positions,
+            point_objs: points,
+            cell_point_counts,
+            cell_point_positions,
+            min_position,
+            cell_widths,
+            grid_dimensions,
+            options,
+            spiral_width: spiral_width(&spiral_cells),
+            spiral_cells,
+            source_ranges: vec![],
+        })
     }
 
-    fn nearest_neighbor_brute_force(&self, query_point: [f32; 3]) -> Option<SearchResult> {
-        nearest(query_point, self.cell_point_positions.iter().flatten())
+    /// Returns a coarser copy of the uniform grid, whose cells are `factor`
+    /// times as wide, as if each block of `factor` by `factor` by `factor`
+    /// cells were merged into one.
+    ///
+    /// The coarse grid starts at the same minimum corner and has `factor`
+    /// times fewer cells along each dimension, rounded up. The points are
+    /// copied and bucketed into the wider cells without recomputing the
+    /// bounding box or laying out the grid again, which makes this a quick way
+    /// to get a lower level of detail. Queries on the coarse grid find the
+    /// same nearest points as on this grid. Its scale is divided by `factor`,
+    /// so it keeps about the same cell width if `extend` rebuilds it. A
+    /// `factor` of `0` is treated as `1`.
+    pub fn coarsened(&self, factor: usize) -> UniformGrid<T>
+    where
+        T: Clone,
+    {
+        let factor = factor.max(1);
+        let cell_widths = self
+            .cell_widths
+            .map(|cell_width| cell_width * factor as f32);
+        let (x_width, y_width, z_width) = self.grid_dimensions;
+        let grid_dimensions = (
+            x_width.div_ceil(factor),
+            y_width.div_ceil(factor),
+            z_width.div_ceil(factor),
+        );
+
+        let mut cell_point_indices =
+            vec![vec![]; grid_dimensions.0 * grid_dimensions.1 * grid_dimensions.2];
+        for point_index in 0..self.point_objs.len() {
+            let cell_index = point_into_index1(
+                self.bucketed_position(point_index),
+                cell_widths,
+                grid_dimensions,
+                self.options.bucket_boundary,
+            )
+            .unwrap();
+            cell_point_indices[cell_index].push(point_index);
+        }
+
+        let index = GridIndex {
+            cell_point_indices,
+            min_position: self.min_position,
+            cell_widths,
+            grid_dimensions,
+            options: GridOptions {
+                scale: self.options.scale / factor as f32,
+                ..self.options.clone()
+            },
+            spiral_cells: self.spiral_cells.clone(),
+        };
+        let mut grid = Self::from_index(index, self.point_objs.clone())
+            .expect("every point is bucketed into exactly one cell");
+        grid.source_ranges = self.source_ranges.clone();
+        grid
     }
 
-    /// Returns the distance between the point and the nearest wall of the cell
-    /// that contains the point.
+    /// Adds a batch of points to the uniform grid.
     ///
-    /// The 3-dimensional offset, `cell_offset`, is relative to the uniform
-    /// grid's "origin cell" at `(0, 0, 0)`.
-    fn nearest_wall_dist(&self, point: [f32; 3], cell_offset: Offset3) -> f32 {
-        let dist_to_x_wall = min_f32(
-            point[0] - (cell_offset.x as f32 * self.cell_width),
-            (cell_offset.x + 1) as f32 * self.cell_width - point[0],
-        );
-        let dist_to_y_wall = min_f32(
-            point[1] - (cell_offset.y as f32 * self.cell_width),
-            (cell_offset.y + 1) as f32 * self.cell_width - point[1],
-        );
-        let dist_to_z_wall = min_f32(
-            point[2] - (cell_offset.z as f32 * self.cell_width),
-            (cell_offset.z + 1) as f32 * self.cell_width - point[1],
+    /// Points that fall inside the region of space covered by the grid are
+    /// bucketed into their cells in a single pass. If any point in the batch
+    /// falls outside of that region, then the grid is rebuilt with the same
+    /// options and spiral cells over the bounding box of all points.
+    ///
+    /// Indices of existing points are preserved, and the new points are
+    /// appended in iteration order.
+    pub fn extend<I>(&mut self, points: I)
+    where
+        I: IntoIterator<Item = T>,
+    {
+        let (min_position, cell_widths, grid_dimensions) =
+            (self.min_position, self.cell_widths, self.grid_dimensions);
+        let boundary = self.options.bucket_boundary;
+        let first_new_index = self.point_objs.len();
+        let mut needs_rebuild = false;
+        let mut new_cell_points = vec![];
+        for point in points {
+            let point_index = self.point_objs.len();
+            let position = self.options.grid_position(point.position());
+            let position = match self.options.periodic_box {
+                Some(periodic_box) => wrap_into_box(position, periodic_box),
+                None => position,
+            };
+            let position = to_local(position, min_position);
+            self.positions.push(point.position());
+            self.point_objs.push(point);
+
+            // Once a rebuild is needed there's no point in bucketing the rest of the batch.
+            if needs_rebuild {
+                continue;
+            }
+            match point_into_index1(position, cell_widths, grid_dimensions, boundary) {
+                Some(cell_index) => new_cell_points.push((cell_index, position, point_index)),
+                None => needs_rebuild = true,
+            }
+        }
+        self.data_corners = data_corners(
+            &self.positions[first_new_index..],
+            &self.options,
+            self.data_corners,
         );
-        min_f32(dist_to_x_wall, min_f32(dist_to_y_wall, dist_to_z_wall))
+
+        if needs_rebuild {
+            let points = std::mem::take(&mut self.point_objs);
+            self.positions = vec![];
+            let spiral_cells = std::mem::take(&mut self.spiral_cells);
+            let source_ranges = std::mem::take(&mut self.source_ranges);
+            *self = Self::from_options(points, self.options.clone(), spiral_cells);
+            self.source_ranges = source_ranges;
+            return;
+        }
+
+        // Make room for the whole batch at once, so that the cells are moved at most once.
+        let new_cell_indices = new_cell_points
+            .iter()
+            .map(|&(cell_index, ..)| cell_index)
+            .collect_vec();
+        self.cell_point_positions.reserve(&new_cell_indices);
+        for (cell_index, position, point_index) in new_cell_points {
+            self.cell_point_counts[cell_index] += 1;
+            self.cell_point_positions.push(
+                cell_index,
+                || cell_min(cell_index, cell_widths, grid_dimensions),
+                position,
+                point_index,
+            );
+        }
     }
 
-    /// Returns the 3-dimensional offset of the cell in which the point would be
-    /// bucketed.
+    /// Adds a single point to the uniform grid, and returns its index into
+    /// `points()`.
     ///
-    /// The 3-dimensional offset is relative to the uniform grid's "origin cell"
-    /// at `(0, 0, 0)`. The uniform grid has a finite width in each
-    /// dimension, so the offset may refer to a "cell" that doesn't actually
-    /// exist. This will happen if the given point lies outside the region
-    /// of space that is covered by the uniform grid.
-    fn point_into_offset(&self, point: [f32; 3]) -> Offset3 {
-        point_into_offset(point, self.min_position, self.cell_width)
+    /// A point that falls inside the region of space covered by the grid is
+    /// bucketed into its cell without touching the other cells. A point that
+    /// falls outside of that region causes the grid to be rebuilt, as
+    /// described for `extend`. When adding many points at once, `extend`
+    /// rebuilds at most once for the whole batch.
+    pub fn insert(&mut self, point: T) -> usize {
+        let point_index = self.point_objs.len();
+        self.extend(std::iter::once(point));
+        point_index
     }
 
-    /// Converts the 3-dimensional offset of a cell in the uniform grid into an
-    /// index into the 1-dimensional vector that stores the cells of the uniform
-    /// grid.
+    /// Removes the point at the given index from the uniform grid and returns
+    /// it.
     ///
-    /// The 3-dimensional offset is relative to the uniform grid's "origin cell"
-    /// at `(0, 0, 0)`.
+    /// The point is removed from its cell without touching the other cells,
+    /// so points can be removed one at a time without rebuilding the grid. To
+    /// keep the indices of the points consecutive, the last point is moved
+    /// into the index of the removed point, like `Vec::swap_remove`, and
+    /// keeps its source tag. The indices of the other points are unchanged.
     ///
-    /// This returns `None` if the offset refers to a "cell" that doesn't exist
-    /// because it is outside the finite bounds of the uniform grid.
-    fn offset_into_index1(&self, offset_from_origin: Offset3) -> Option<usize> {
-        offset_from_origin.into_grid_index1(self.grid_dimensions)
-    }
+    /// # Panics
+    ///
+    /// Panics if `point_index` is out of bounds.
+    pub fn remove(&mut self, point_index: usize) -> T {
+        let last_index = self.point_objs.len() - 1;
+        let cell_index = self.point_cell_index(point_index);
+        self.cell_point_counts[cell_index] -= 1;
+        self.cell_point_positions.remove(cell_index, point_index);
+        if point_index != last_index {
+            let last_cell_index = self.point_cell_index(last_index);
+            self.cell_point_positions
+                .reindex(last_cell_index, last_index, point_index);
+        }
+        swap_remove_source(&mut self.source_ranges, point_index, last_index);
 
-    /// Checks each of the cells that are identified by the offsets from the
-    /// center cell, and return the point in those cells that is nearest to the
-    /// query point.
-    fn nearest_in_cell_offsets(
-        &self,
-        query_point: [f32; 3],
-        center_cell_offset: Offset3,
-        cell_offsets: Vec<Offset3>,
-    ) -> Option<SearchResult> {
-        let mut min_point: Option<SearchResult> = None;
-        for o in cell_offsets {
-            if let Some(cell_idx) = self.offset_into_index1(center_cell_offset + o) {
-                let count = &self.cell_point_counts[cell_idx];
-                if *count > 0 {
-                    for (pos, pt_idx) in &self.cell_point_positions[cell_idx] {
-                        if let Some(sr) = &min_point {
-                            let d2 = dist2(query_point, *pos);
-                            if d2 < sr.distance2_to_query {
-                                min_point = Some(SearchResult {
-                                    position: *pos,
-                                    point_object_index: *pt_idx,
-                                    distance2_to_query: d2,
-                                })
-                            }
-                        } else {
-                            min_point = Some(SearchResult {
-                                position: *pos,
-                                point_object_index: *pt_idx,
-                                distance2_to_query: dist2(query_point, *pos),
-                            })
-                        }
-                    }
-                }
+        let removed_position = self
+            .options
+            .grid_position(self.positions.swap_remove(point_index));
+        let point = self.point_objs.swap_remove(point_index);
+
+        // The bounding box can only shrink if the removed point was on one of its faces.
+        if let Some((min, max)) = self.data_corners {
+            let is_on_face = (0..3).any(|axis| {
+                removed_position[axis] == min[axis] || removed_position[axis] == max[axis]
+            });
+            if is_on_face {
+                self.data_corners = data_corners(&self.positions, &self.options, None);
             }
         }
-        min_point
+        point
+    }
 
-        // let points = cell_offsets
-        //     .iter()
-        //     .filter_map(|o| self.offset_into_index1(center_cell_offset + o))
-        //     .filter(|&i| self.cell_point_counts[i] > 0)
-        //     .flat_map(|i| &self.cell_point_positions[i]);
-        // nearest(query_point, points)
+    /// Releases the memory that the uniform grid holds beyond what its points
+    /// need, such as the room that's left in cells after points are removed.
+    ///
+    /// `remove` keeps the indices of the points consecutive, so compacting
+    /// doesn't change the index of any point. The grid's layout is unchanged,
+    /// and adding points to it afterwards makes room for them again.
+    pub fn compact(&mut self) {
+        self.cell_point_positions.compact();
+        self.point_objs.shrink_to_fit();
+        self.positions.shrink_to_fit();
+        self.source_ranges.shrink_to_fit();
     }
-}
 
-struct SearchResult {
-    pub position: [f32; 3],
-    pub point_object_index: usize,
-    pub distance2_to_query: f32,
-}
+    /// Combines two uniform grids into a single grid that covers the union of
+    /// their points.
+    ///
+    /// The grid is rebuilt over the bounding box of all points, using this
+    /// grid's options and whichever of the two grids' spiral cell vectors is
+    /// larger. Points are re-indexed: the points of `self` keep their indices,
+    /// and the points of `other` are appended after them, so each of their
+    /// indices is shifted by the number of points in `self`. Source tags are
+    /// kept, with the ranges of `other` shifted the same way.
+    pub fn merge(self, other: UniformGrid<T>) -> UniformGrid<T> {
+        let spiral_cells = if other.spiral_cells.len() > self.spiral_cells.len() {
+            other.spiral_cells
+        } else {
+            self.spiral_cells
+        };
+        let shift = self.point_objs.len();
+        let mut source_ranges = self.source_ranges;
+        source_ranges.extend(
+            other
+                .source_ranges
+                .into_iter()
+                .map(|(tag, range)| (tag, range.start + shift..range.end + shift)),
+        );
+        let mut points = self.point_objs;
+        points.extend(other.point_objs);
+        let mut grid = Self::from_options(points, self.options, spiral_cells);
+        grid.source_ranges = source_ranges;
+        grid
+    }
 
-fn neighbor_offsets() -> Vec<Offset3> {
-    vec![
-        Offset3::new(-1, -1, -1),
-        Offset3::new(0, -1, -1),
-        Offset3::new(1, -1, -1),
-        Offset3::new(-1, 0, -1),
-        Offset3::new(0, 0, -1),
-        Offset3::new(1, 0, -1),
-        Offset3::new(-1, 1, -1),
-        Offset3::new(0, 1, -1),
-        Offset3::new(1, 1, -1),
-        Offset3::new(-1, -1, 0),
-        Offset3::new(0, -1, 0),
-        Offset3::new(1, -1, 0),
-        Offset3::new(-1, 0, 0),
-        Offset3::new(1, 0, 0),
-        Offset3::new(-1, 1, 0),
-        Offset3::new(0, 1, 0),
-        Offset3::new(1, 1, 0),
-        Offset3::new(-1, -1, 1),
-        Offset3::new(0, -1, 1),
-        Offset3::new(1, -1, 1),
-        Offset3::new(-1, 0, 1),
-        Offset3::new(0, 0, 1),
-        Offset3::new(1, 0, 1),
-        Offset3::new(-1, 1, 1),
-        Offset3::new(0, 1, 1),
-        Offset3::new(1, 1, 1),
-    ]
-}
+    /// Returns a read-only view of the uniform grid for a phase of queries.
+    ///
+    /// See `GridReader` for how it separates queries from mutations.
+    pub fn reader(&self) -> GridReader<'_, T> {
+        GridReader::new(self)
+    }
 
-fn point_into_offset(point: [f32; 3], min_point: [f32; 3], cell_width: f32) -> Offset3 {
-    let relative_pos = [
-        point[0] - min_point[0],
-        point[1] - min_point[1],
-        point[2] - min_point[2],
-    ];
-    let x = (relative_pos[0] / cell_width) as i64;
-    let y = (relative_pos[1] / cell_width) as i64;
-    let z = (relative_pos[2] / cell_width) as i64;
-    Offset3::new(x, y, z)
-}
+    /// Returns the points in the uniform grid, in the order that they were
+    /// added.
+    ///
+    /// A point's position in the slice is the index that identifies the point
+    /// in the grid.
+    pub fn points(&self) -> &[T] {
+        &self.point_objs
+    }
 
-fn point_into_index1(
-    point: [f32; 3],
-    min_point: [f32; 3],
-    cell_width: f32,
-    grid_size: (usize, usize, usize),
-) -> Option<usize> {
-    point_into_offset(point, min_point, cell_width).into_grid_index1(grid_size)
-}
+    /// Returns the positions of the points in the uniform grid, in the same
+    /// order as `points()`.
+    ///
+    /// `positions()[i]` is `points()[i].position()`. The positions are stored
+    /// contiguously, so they can be copied or uploaded as a single block of
+    /// memory.
+    pub fn positions(&self) -> &[[f32; 3]] {
+        &self.positions
+    }
 
-fn nearest<'a, I>(query_point: [f32; 3], points: I) -> Option<SearchResult>
-where
-    I: IntoIterator<Item = &'a ([f32; 3], usize)>,
-{
-    points
-        .into_iter()
-        .map(|(p, p_obj_idx)| SearchResult {
-            position: *p,
-            point_object_index: *p_obj_idx,
-            distance2_to_query: dist2(query_point, *p),
+    /// Consumes the uniform grid and returns its points, in the same order as
+    /// `points()`.
+    pub fn into_points(self) -> Vec<T> {
+        self.point_objs
+    }
+
+    /// Consumes the uniform grid and returns its points along with the
+    /// layout of its cells and its spiral cells.
+    ///
+    /// The spiral cells can be expensive to generate, so they can be reused to
+    /// construct another grid.
+    pub fn into_parts(self) -> GridParts<T> {
+        GridParts {
+            points: self.point_objs,
+            spiral_cells: self.spiral_cells,
+            min_position: self.min_position,
+            cell_widths: self.cell_widths,
+            dimensions: self.grid_dimensions,
+        }
+    }
+
+    /// Returns the tag of the source that the point at the given index came
+    /// from, if the grid was constructed with `from_tagged_sources`.
+    ///
+    /// Returns `None` for points that weren't in any source, such as points
+    /// that were added with `extend`.
+    pub fn source_tag(&self, point_index: usize) -> Option<u32> {
+        source_tag(&self.source_ranges, point_index)
+    }
+
+    /// Returns the number of points in the uniform grid.
+    pub fn len(&self) -> usize {
+        self.point_objs.len()
+    }
+
+    /// Returns true if the uniform grid contains no points.
+    pub fn is_empty(&self) -> bool {
+        self.point_objs.is_empty()
+    }
+
+    /// Returns the box that covers the region of space that's divided into
+    /// the grid's cells.
+    ///
+    /// The box contains every point that was used to lay out the grid, but it
+    /// is slightly larger than the bounding box of those points, since the
+    /// grid is made up of whole cells. For a periodic grid, the box contains
+    /// the periodic box.
+    pub fn bounding_box(&self) -> BoundingBox {
+        let (x_width, y_width, z_width) = self.grid_dimensions;
+        BoundingBox {
+            min: self.min_position,
+            x_width: x_width as f32 * self.cell_widths[0],
+            y_width: y_width as f32 * self.cell_widths[1],
+            z_width: z_width as f32 * self.cell_widths[2],
+        }
+    }
+
+    /// Returns the smallest box that contains the positions of all of the
+    /// points in the uniform grid, or `None` if the grid is empty.
+    ///
+    /// The box has its axes in the grid's order. Unlike `bounding_box`, which
+    /// covers the grid's cells, this is where the points actually are, and
+    /// it's kept up to date as points are added. Points are used as they
+    /// were given, without wrapping them into a periodic box, and points with
+    /// a NaN coordinate are left out.
+    pub fn data_bounds(&self) -> Option<BoundingBox> {
+        self.data_corners.map(|(min, max)| BoundingBox {
+            min,
+            x_width: max[0] - min[0],
+            y_width: max[1] - min[1],
+            z_width: max[2] - min[2],
         })
-        .min_by(|sr1, sr2| {
-            sr1.distance2_to_query
-                .partial_cmp(&sr2.distance2_to_query)
-                .unwrap()
+    }
+
+    /// Returns true if the spiral cells extend far enough to reach every cell
+    /// of the uniform grid from every other cell.
+    ///
+    /// When this is true, the spiral search from any query point inside the
+    /// grid reaches every cell, so it never needs to fall back to a
+    /// brute-force search. A grid whose cells aren't cubes doesn't use the
+    /// spiral cells, and always searches out to every cell that it needs to,
+    /// so this is always true for it.
+    pub fn spiral_covers_grid(&self) -> bool {
+        let (x_width, y_width, z_width) = self.grid_dimensions;
+        !self.has_cubic_cells() || x_width.max(y_width).max(z_width) as i64 <= self.spiral_width
+    }
+
+    /// Returns true if the cells of the uniform grid are cubes, which is what
+    /// the spiral cells assume.
+    fn has_cubic_cells(&self) -> bool {
+        let [x_width, y_width, z_width] = self.cell_widths;
+        x_width == y_width && y_width == z_width
+    }
+
+    /// Returns the cell that the point would be bucketed into, computed the
+    /// same way as when points are added to the grid.
+    ///
+    /// The point is wrapped into the periodic box first if the grid is
+    /// periodic. This is meant for diagnosing how points are bucketed, such
+    /// as for points that lie on or near the maximum faces of the grid.
+    pub fn debug_locate(&self, point: [f32; 3]) -> CellLocation {
+        let position = match self.options.periodic_box {
+            Some(periodic_box) => wrap_into_box(point, periodic_box),
+            None => point,
+        };
+        let offset = self.bucket_into_offset(self.to_local(position));
+        let index = self.offset_into_index1(offset);
+        CellLocation {
+            offset,
+            index,
+            in_bounds: index.is_some(),
+        }
+    }
+
+    /// Returns true if the two points would be bucketed into the same cell or
+    /// into cells that touch, including diagonally.
+    ///
+    /// Points that are further apart than the width of two cells along some
+    /// axis are never in the same or touching cells, so this is a cheap test
+    /// for whether two points could be close. Cells are found the same way as
+    /// `debug_locate` finds them. Returns false if either point is outside of
+    /// the grid or has a NaN coordinate. In a periodic grid, cells on opposite
+    /// faces of the periodic box aren't considered to touch.
+    pub fn same_or_adjacent_cell(&self, a: [f32; 3], b: [f32; 3]) -> bool {
+        if a.iter().chain(&b).any(|c| c.is_nan()) {
+            return false;
+        }
+        let (a, b) = (self.debug_locate(a), self.debug_locate(b));
+        a.in_bounds && b.in_bounds && rings_between(a.offset, b.offset) <= 1
+    }
+
+    /// Returns the voxel coordinates of the cell that contains the point, or
+    /// `None` if the point is outside of the grid or has a coordinate that
+    /// isn't finite.
+    ///
+    /// Voxel coordinates are the components of the cell's offset from the
+    /// grid's "origin cell" at `(0, 0, 0)`, which are never negative for
+    /// cells inside the grid. The cell is found the same way as
+    /// `debug_locate` finds it, so a point on one of the maximum faces of the
+    /// grid is in the last cell along that axis, but the point isn't wrapped
+    /// into the periodic box of a periodic grid.
+    pub fn world_to_voxel(&self, point: [f32; 3]) -> Option<(u32, u32, u32)> {
+        if !point.iter().all(|c| c.is_finite()) {
+            return None;
+        }
+        let offset = self.bucket_into_offset(self.to_local(point));
+        self.offset_into_index1(offset)?;
+        Some((
+            u32::try_from(offset.x).ok()?,
+            u32::try_from(offset.y).ok()?,
+            u32::try_from(offset.z).ok()?,
+        ))
+    }
+
+    /// Returns the position of the center of the cell with the given voxel
+    /// coordinates.
+    ///
+    /// See `world_to_voxel` for voxel coordinates. The voxel doesn't need to
+    /// be inside the grid.
+    pub fn voxel_to_world_center(&self, voxel: (u32, u32, u32)) -> [f32; 3] {
+        let (x, y, z) = voxel;
+        let voxel = [x, y, z];
+        [0, 1, 2].map(|axis| {
+            self.min_position[axis] + (voxel[axis] as f32 + 0.5) * self.cell_widths[axis]
         })
-}
+    }
 
-fn dist2(p: [f32; 3], q: [f32; 3]) -> f32 {
-    let x = q[0] - p[0];
-    let y = q[1] - p[1];
-    let z = q[2] - p[2];
-    x * x + y * y + z * z
+    /// Checks that the internal invariants of the uniform grid hold.
+    ///
+    /// Every point must be stored in exactly one cell, which must be the cell
+    /// that its position buckets into, and the count of points in each cell
+    /// must match the points that are stored in it. If positions are stored at
+    /// full precision, then the stored position of each point must also match
+    /// its position. Returns the first violation that's found.
+    ///
+    /// This scans the whole grid, so it's meant for debugging.
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        let (x_width, y_width, z_width) = self.grid_dimensions;
+        let cell_count = x_width * y_width * z_width;
+        if self.cell_point_counts.len() != cell_count
+            || self.cell_point_positions.cell_count() != cell_count
+        {
+            return Err(ValidationError::CellCount);
+        }
+
+        let mut is_stored = vec![false; self.point_objs.len()];
+        for cell_index in 0..cell_count {
+            let mut stored = 0;
+            for (position, point_index) in self.cell_points(cell_index) {
+                match is_stored.get_mut(point_index) {
+                    Some(is_stored @ false) => *is_stored = true,
+                    _ => return Err(ValidationError::InvalidPointIndex(point_index)),
+                }
+
+                let bucketed_position = self.bucketed_position(point_index);
+                if point_into_index1(
+                    bucketed_position,
+                    self.cell_widths,
+                    self.grid_dimensions,
+                    self.options.bucket_boundary,
+                ) != Some(cell_index)
+                {
+                    return Err(ValidationError::WrongCell {
+                        point_index,
+                        cell_index,
+                    });
+                }
+                if !self.cell_point_positions.is_lossy()
+                    && position.map(f32::to_bits) != bucketed_position.map(f32::to_bits)
+                {
+                    return Err(ValidationError::PositionMismatch(point_index));
+                }
+                stored += 1;
+            }
+
+            let counted = self.cell_point_counts[cell_index];
+            if counted != stored {
+                return Err(ValidationError::PointCount {
+                    cell_index,
+                    counted,
+                    stored,
+                });
+            }
+        }
+
+        match is_stored.iter().position(|&is_stored| !is_stored) {
+            Some(point_index) => Err(ValidationError::MissingPoint(point_index)),
+            None => Ok(()),
+        }
+    }
+
+    /// Returns the number of cells along each dimension of the uniform grid.
+    pub fn dimensions(&self) -> (usize, usize, usize) {
+        self.grid_dimensions
+    }
+
+    /// Returns the width of the cells of the uniform grid along each axis,
+    /// which is the same along every axis unless the cells were shaped with
+    /// `CellSize::PerAxis`.
+    pub fn cell_widths(&self) -> [f32; 3] {
+        self.cell_widths
+    }
+
+    /// Returns the number of points in each cell of the uniform grid, along
+    /// with the number of cells along each dimension.
+    ///
+    /// The counts are in a flat vector with one element for each cell. The
+    /// count for the cell at offset `(x, y, z)` is at index
+    /// `x + y * x_width + z * x_width * y_width`, which is the index that
+    /// `Offset3::into_grid_index1` returns for the offset.
+    pub fn occupancy_volume(&self) -> (Vec<usize>, (usize, usize, usize)) {
+        (self.cell_point_counts.clone(), self.grid_dimensions)
+    }
+
+    /// Returns an estimate of the number of bytes of memory that are used by
+    /// the uniform grid.
+    ///
+    /// The estimate includes the grid itself and the heap memory of its
+    /// vectors, including their unused capacity. It doesn't include any heap
+    /// memory that's owned by the point objects, and it doesn't include the
+    /// overhead of the allocator.
+    pub fn memory_bytes(&self) -> usize {
+        std::mem::size_of::<Self>()
+            + self.point_objs.capacity() * std::mem::size_of::<T>()
+            + self.positions.capacity() * std::mem::size_of::<[f32; 3]>()
+            + self.cell_point_counts.capacity() * std::mem::size_of::<usize>()
+            + self.cell_point_positions.memory_bytes()
+            + self.spiral_cells.capacity() * std::mem::size_of::<SpiralCell>()
+    }
+
+    /// Returns the mean number of points in each non-empty cell.
+    ///
+    /// A mean occupancy close to `1.0` usually means the grid has many more
+    /// cells than it needs, most of which are empty.
+    pub fn mean_occupancy(&self) -> f32 {
+        let non_empty_count = self
+            .cell_point_counts
+            .iter()
+            .filter(|&&count| count > 0)
+            .count();
+        if non_empty_count == 0 {
+            0.0
+        } else {
+            self.point_objs.len() as f32 / non_empty_count as f32
+        }
+    }
+
+    /// Picks one representative point from each non-empty cell, which gives a
+    /// simplified version of the point set with at most one point per cell.
+    ///
+    /// The representative of a cell is the point in the cell that is nearest
+    /// to the centroid of the cell's points. Returns the index into `points()`
+    /// of each representative, ordered by the index of its cell.
+    pub fn decimate(&self) -> Vec<usize> {
+        (0..self.cell_point_counts.len())
+            .filter(|&cell_index| self.cell_point_counts[cell_index] > 0)
+            .filter_map(|cell_index| {
+                let centroid = self.cell_centroid(cell_index);
+                nearest(centroid, self.cell_points(cell_index), cell_index)
+                    .map(|sr| sr.point_object_index)
+                    // Every point in the cell has a NaN coordinate, so any of them will do.
+                    .or_else(|| self.cell_points(cell_index).next().map(|(_, i)| i))
+            })
+            .collect()
+    }
+
+    /// Picks a random point from the uniform grid, by picking a cell with a
+    /// probability that's proportional to its number of points and then
+    /// picking one of the cell's points uniformly.
+    ///
+    /// Every point is equally likely to be picked, so the cells of the points
+    /// that are picked follow the grid's occupancy, and dense regions are
+    /// sampled more often than sparse ones. Returns the index into `points()`
+    /// of the point, or `None` if the grid is empty. Each pick walks the
+    /// grid's cell counts, so it takes time proportional to the number of
+    /// cells.
+    #[cfg(feature = "rand")]
+    pub fn sample_point<R>(&self, rng: &mut R) -> Option<usize>
+    where
+        R: rand::Rng,
+    {
+        if self.point_objs.is_empty() {
+            return None;
+        }
+
+        // A single draw over all of the points picks the cell that holds that point
+        // with probability proportional to the cell's count, and then the point within
+        // the cell uniformly.
+        let mut nth = rng.gen_range(0..self.point_objs.len());
+        let cell_index = self.cell_point_counts.iter().position(|&count| {
+            if nth < count {
+                true
+            } else {
+                nth -= count;
+                false
+            }
+        })?;
+        self.cell_points(cell_index)
+            .nth(nth)
+            .map(|(_, point_index)| point_index)
+    }
+
+    /// Finds the point in the uniform grid that is closest to the given query
+    /// point, without using the spiral cells.
+    ///
+    /// Cells are searched in rings of growing size around the query cell,
+    /// where ring `n` contains the cells that are `n` cells away from the
+    /// query cell along at least one axis. The search stops as soon as no cell
+    /// outside the rings that have been searched can contain a point that's
+    /// closer than the nearest point found so far. The result is always exact,
+    /// regardless of how far the spiral cells extend.
+    ///
+    /// Returns the point along with its distance to the query point, like
+    /// `nearest_neighbor`.
+    pub fn nearest_neighbor_no_table(&self, query_point: [f32; 3]) -> Option<(&T, Distance)> {
+        let query_point = self.to_local(query_point);
+        match self.local_periodic_box() {
+            Some(periodic_box) => {
+                self.nearest_neighbor_periodic_search(query_point, periodic_box, |image| {
+                    self.nearest_neighbor_ring_search(image, f32::INFINITY)
+                })
+            }
+            None => self.nearest_neighbor_ring_search(query_point, f32::INFINITY),
+        }
+        .map(|sr| {
+            (
+                &self.point_objs[sr.point_object_index],
+                Distance::from_squared(sr.distance2_to_query),
+            )
+        })
+    }
+
+    /// Finds the point in the uniform grid that is closest to the given query
+    /// point, out of the points that are within `max_distance` of it.
+    ///
+    /// Cells are searched in rings around the query cell, like
+    /// `nearest_neighbor_no_table`, but only out to the rings that overlap the
+    /// sphere of radius `max_distance` around the query point, and the search
+    /// never falls back to scanning the whole grid. Returns `None` as soon as
+    /// those rings have been searched if no point is close enough, so a query
+    /// in a sparse region doesn't pay for a long search. The result is exact.
+    ///
+    /// Returns the point along with its distance to the query point, like
+    /// `nearest_neighbor`, or `None` if `max_distance` is negative.
+    pub fn nearest_neighbor_within(
+        &self,
+        query_point: [f32; 3],
+        max_distance: f32,
+    ) -> Option<(&T, Distance)> {
+        let query_point = self.to_local(query_point);
+        let max_distance2 = squared_radius(max_distance);
+        match self.local_periodic_box() {
+            Some(periodic_box) => {
+                self.nearest_neighbor_periodic_search(query_point, periodic_box, |image| {
+                    self.nearest_neighbor_ring_search(image, max_distance2)
+                })
+            }
+            None => self.nearest_neighbor_ring_search(query_point, max_distance2),
+        }
+        .map(|sr| {
+            (
+                &self.point_objs[sr.point_object_index],
+                Distance::from_squared(sr.distance2_to_query),
+            )
+        })
+    }
+
+    /// Finds the point in the uniform grid that is closest to the given query
+    /// point under the given metric.
+    ///
+    /// Cells are searched in rings around the query cell, like
+    /// `nearest_neighbor_no_table`, and the search stops as soon as no cell
+    /// outside the rings that have been searched can contain a point that's
+    /// closer under the metric than the nearest point found so far. The
+    /// result is exact.
+    ///
+    /// Returns the point along with its distance to the query point under the
+    /// metric.
+    pub fn nearest_neighbor_by_metric<M>(
+        &self,
+        query_point: [f32; 3],
+        metric: &M,
+    ) -> Option<(&T, f32)>
+    where
+        M: Metric,
+    {
+        let query_point = self.to_local(query_point);
+        let nearest = match self.local_periodic_box() {
+            Some(periodic_box) => {
+                let mut maybe_nearest_so_far: Option<(usize, f32)> = None;
+                for image in periodic_images(query_point, periodic_box) {
+                    // No point in the periodic box is closer to the image than the box
+                    // itself.
+                    let box_distance = metric.distance(image, clamp_into_box(image, periodic_box));
+                    if maybe_nearest_so_far.is_some_and(|(_, distance)| box_distance >= distance) {
+                        continue;
+                    }
+                    if let Some(nearest) = self.nearest_neighbor_metric_ring_search(image, metric) {
+                        if maybe_nearest_so_far.is_none_or(|(_, distance)| nearest.1 < distance) {
+                            maybe_nearest_so_far = Some(nearest);
+                        }
+                    }
+                }
+                maybe_nearest_so_far
+            }
+            None => self.nearest_neighbor_metric_ring_search(query_point, metric),
+        };
+        nearest.map(|(point_index, distance)| (&self.point_objs[point_index], distance))
+    }
+
+    /// Finds the point in the uniform grid that is closest to `origin`, out of
+    /// the points that are inside the cone with its apex at `origin`, its axis
+    /// along `direction`, and the given half angle, in radians.
+    ///
+    /// A point is inside the cone if the angle between `direction` and the
+    /// direction from `origin` to the point is at most `half_angle`, give or
+    /// take a tiny tolerance. With a `half_angle` of `0.0`, the cone is a ray,
+    /// and only points that lie on the ray are considered. Points at `origin`
+    /// have no direction, so they're never inside the cone. Returns `None` if
+    /// no point is inside the cone or if `direction` is zero.
+    ///
+    /// Periodicity is ignored, so only points inside the cone itself, and not
+    /// their periodic images, are considered.
+    pub fn nearest_in_cone(
+        &self,
+        origin: [f32; 3],
+        direction: [f32; 3],
+        half_angle: f32,
+    ) -> Option<(&T, Distance)> {
+        if direction.iter().all(|&d| d == 0.0) {
+            return None;
+        }
+
+        let mut trace = SearchTrace::default();
+        let in_cone = |point_index: usize| {
+            let position = self.point_position(point_index);
+            let to_point = [0, 1, 2].map(|axis| position[axis] - origin[axis]);
+            to_point.iter().any(|&c| c != 0.0)
+                && angle_between(direction, to_point) <= half_angle + CONE_ANGLE_EPSILON
+        };
+        self.nearest_neighbor_grid_search(self.to_local(origin), &mut trace, &in_cone)
+            .map(|sr| {
+                (
+                    &self.point_objs[sr.point_object_index],
+                    Distance::from_squared(sr.distance2_to_query),
+                )
+            })
+    }
+
+    /// Finds the point in the uniform grid that is closest to the given query
+    /// point, out of the points in the given category.
+    ///
+    /// Points in other categories are skipped, even if they're closer to the
+    /// query point. Returns `None` if no point is in the category.
+    pub fn nearest_neighbor_in_category(
+        &self,
+        query_point: [f32; 3],
+        category: u32,
+    ) -> Option<(&T, Distance)> {
+        let mut trace = SearchTrace::default();
+        let in_category = |point_index: usize| self.point_objs[point_index].category() == category;
+        self.nearest_neighbor_search(query_point, &mut trace, &in_category)
+            .map(|sr| {
+                (
+                    &self.point_objs[sr.point_object_index],
+                    Distance::from_squared(sr.distance2_to_query),
+                )
+            })
+    }
+
+    /// Finds the point in the uniform grid that is closest to the given query
+    /// point, out of the points that are active in the mask.
+    ///
+    /// `mask[i]` is true if the point at index `i` in `points()` is active.
+    /// Inactive points are skipped, even if they're closer to the query point,
+    /// so the active points can change between queries without rebuilding the
+    /// grid. Returns `None` if no point is active.
+    ///
+    /// Panics if the mask's length doesn't equal the number of points.
+    pub fn nearest_neighbor_masked(
+        &self,
+        query_point: [f32; 3],
+        mask: &[bool],
+    ) -> Option<(&T, Distance)> {
+        assert_eq!(
+            mask.len(),
+            self.point_objs.len(),
+            "mask length must equal the number of points"
+        );
+        let mut trace = SearchTrace::default();
+        let is_active = |point_index: usize| mask[point_index];
+        self.nearest_neighbor_search(query_point, &mut trace, &is_active)
+            .map(|sr| {
+                (
+                    &self.point_objs[sr.point_object_index],
+                    Distance::from_squared(sr.distance2_to_query),
+                )
+            })
+    }
+
+    /// Finds the point in the uniform grid that is closest to the point at the
+    /// given index in `points()`, other than that point itself.
+    ///
+    /// Returns the point along with its distance to the point at the given
+    /// index. Other points at the same position aren't excluded, so they're
+    /// found at a distance of zero. Returns `None` if the grid has no other
+    /// points, or if the point has a NaN coordinate.
+    ///
+    /// Panics if the index is out of bounds.
+    pub fn nearest_neighbor_of(&self, point_index: usize) -> Option<(&T, Distance)> {
+        let query_point = self.point_position(point_index);
+        if query_point.iter().any(|c| c.is_nan()) {
+            return None;
+        }
+        let mut trace = SearchTrace::default();
+        let is_other = |other_index: usize| other_index != point_index;
+        self.nearest_neighbor_search(query_point, &mut trace, &is_other)
+            .map(|sr| {
+                (
+                    &self.point_objs[sr.point_object_index],
+                    Distance::from_squared(sr.distance2_to_query),
+                )
+            })
+    }
+
+    /// Finds the point in the uniform grid that is closest to the given query
+    /// point, out of the points that `predicate` accepts.
+    ///
+    /// Points that `predicate` rejects are skipped while searching, even if
+    /// they're closer to the query point, so the search goes on until the
+    /// nearest accepted point is found, however many points are rejected.
+    /// Returns `None` if no point is accepted.
+    pub fn nearest_neighbor_filtered<P>(
+        &self,
+        query_point: [f32; 3],
+        predicate: P,
+    ) -> Option<(&T, Distance)>
+    where
+        P: Fn(&T) -> bool,
+    {
+        let mut trace = SearchTrace::default();
+        let is_accepted = |point_index: usize| predicate(&self.point_objs[point_index]);
+        self.nearest_neighbor_search(query_point, &mut trace, &is_accepted)
+            .map(|sr| {
+                (
+                    &self.point_objs[sr.point_object_index],
+                    Distance::from_squared(sr.distance2_to_query),
+                )
+            })
+    }
+
+    /// Searches outward from the query point and returns the closest point for
+    /// which `accept` returns true.
+    ///
+    /// `accept` is called with a point and its distance to the query point,
+    /// so the acceptance rule can depend on both, such as skipping
+    /// points that are occupied or that are closer than some threshold.
+    /// Points that aren't accepted are skipped, even if they're closer to the
+    /// query point, and the search stops as soon as no closer point could be
+    /// accepted. `accept` may be called for points in any order. Returns
+    /// `None` if no point is accepted.
+    ///
+    /// Periodicity is ignored, so only the points themselves, and not their
+    /// periodic images, are considered.
+    pub fn find_outward<A>(&self, query_point: [f32; 3], accept: A) -> Option<(&T, Distance)>
+    where
+        A: Fn(&T, Distance) -> bool,
+    {
+        let mut trace = SearchTrace::default();
+        let is_accepted = |point_index: usize| {
+            let distance2 = dist2(query_point, self.point_position(point_index));
+            accept(
+                &self.point_objs[point_index],
+                Distance::from_squared(distance2),
+            )
+        };
+        self.nearest_neighbor_grid_search(self.to_local(query_point), &mut trace, &is_accepted)
+            .map(|sr| {
+                (
+                    &self.point_objs[sr.point_object_index],
+                    Distance::from_squared(sr.distance2_to_query),
+                )
+            })
+    }
+
+    /// Returns the offsets of the up to 26 cells that neighbor the given cell
+    /// and are inside the grid.
+    ///
+    /// Cells on the faces, edges, and corners of the grid have fewer
+    /// neighbors. The given cell doesn't need to be inside the grid itself.
+    pub fn neighbor_cells(&self, cell_offset: Offset3) -> impl Iterator<Item = Offset3> + '_ {
+        neighbor_offsets()
+            .into_iter()
+            .map(move |neighbor_offset| cell_offset + neighbor_offset)
+            .filter(|&offset| self.offset_into_index1(offset).is_some())
+    }
+
+    /// Calls `visit` with each point that is bucketed into the cell at the
+    /// given offset.
+    ///
+    /// The traversal stops when `visit` returns `ControlFlow::Break`. Nothing
+    /// is visited if the offset is outside of the grid.
+    pub fn for_each_in_cell<F>(&self, cell_offset: Offset3, mut visit: F)
+    where
+        F: FnMut(&T) -> ControlFlow<()>,
+    {
+        let Some(cell_index) = self.offset_into_index1(cell_offset) else {
+            return;
+        };
+        for (_, point_index) in self.cell_points(cell_index) {
+            if visit(&self.point_objs[point_index]).is_break() {
+                return;
+            }
+        }
+    }
+
+    /// Visits the points of the grid outward from the given cell, one group of
+    /// cells at a time, in the same order as nearest-neighbor queries search
+    /// them.
+    ///
+    /// Each group is the set of cells whose closest points to the query cell
+    /// are the same distance from it, and groups are visited in increasing
+    /// order of that distance. Once the spiral cells are exhausted, the rest of
+    /// the grid is visited one ring of cells at a time, where a ring is the
+    /// cells that are the same number of cells away from the query cell along
+    /// at least one axis. A grid whose cells aren't cubes is visited one ring
+    /// at a time from the start. `visit` is called with the positions, in the grid's
+    /// axes, and indices of the points in each group, and groups without
+    /// points are skipped. The traversal stops when `visit` returns
+    /// `ControlFlow::Break`, and otherwise visits every point exactly once.
+    ///
+    /// The query cell doesn't need to be inside the grid. Periodicity is
+    /// ignored.
+    pub fn spiral_visit<V>(&self, query_cell_offset: Offset3, mut visit: V)
+    where
+        V: FnMut(&[([f32; 3], usize)]) -> ControlFlow<()>,
+    {
+        // The spiral cells only order the cells by distance when the cells are cubes,
+        // so other grids are visited one ring at a time from the query cell.
+        let (spiral_cells, spiral_width) = if self.has_cubic_cells() {
+            (&self.spiral_cells[..], self.spiral_width)
+        } else {
+            (&[][..], 0)
+        };
+        let reach = self.sorted_reach(query_cell_offset);
+        let spiral_groups = spiral_cells
+            .iter()
+            .filter(|spiral_cell| can_reach(spiral_cell.offset, reach))
+            .map(|spiral_cell| {
+                spiral_cells::offset_variations(spiral_cell.offset)
+                    .into_iter()
+                    .filter_map(|offset| self.offset_into_index1(query_cell_offset + offset))
+                    .collect_vec()
+            });
+        // Rings closer than the first ring that reaches the grid are empty.
+        let first_ring = spiral_width.max(self.first_ring(query_cell_offset));
+        let ring_groups = (first_ring..=reach[2])
+            .map(|ring| self.ring_cells(query_cell_offset, ring).collect_vec());
+
+        let mut group_points = Vec::new();
+        for cell_indices in spiral_groups.chain(ring_groups) {
+            group_points.clear();
+            group_points.extend(cell_indices.into_iter().flat_map(|cell_index| {
+                self.cell_points(cell_index)
+                    .map(|(_, point_index)| (self.point_position(point_index), point_index))
+            }));
+            if !group_points.is_empty() && visit(&group_points).is_break() {
+                return;
+            }
+        }
+    }
+
+    /// Returns the offsets of the cells that a ray passes through, in the order
+    /// that the ray passes through them.
+    ///
+    /// The ray starts at `origin` and travels in the direction `direction`,
+    /// which doesn't need to be normalized. A ray that starts outside the grid
+    /// is advanced to where it enters the grid, and the iterator ends when the
+    /// ray leaves the grid. If the ray never passes through the grid, or if
+    /// `direction` is zero, then no cells are returned.
+    pub fn cells_along_ray(
+        &self,
+        origin: [f32; 3],
+        direction: [f32; 3],
+    ) -> impl Iterator<Item = Offset3> {
+        CellsAlongRay::new(
+            self.to_local(origin),
+            direction,
+            self.cell_widths,
+            self.grid_dimensions,
+        )
+    }
+
+    /// Finds the point in the uniform grid that is closest to the given query
+    /// point.
+    ///
+    /// Distance between points is Euclidean distance. Returns the point along
+    /// with its distance to the query point.
+    pub fn nearest_neighbor(&self, query_point: [f32; 3]) -> Option<(&T, Distance)> {
+        let mut trace = SearchTrace::default();
+        self.nearest_neighbor_search(query_point, &mut trace, &|_| true)
+            .map(|sr| {
+                (
+                    &self.point_objs[sr.point_object_index],
+                    Distance::from_squared(sr.distance2_to_query),
+                )
+            })
+    }
+
+    /// Finds all of the points in the uniform grid that tie for being closest
+    /// to the given query point.
+    ///
+    /// A point ties if its distance to the query point is at most `tol`
+    /// greater than the distance to the nearest point. Returns each point
+    /// along with its distance to the query point, sorted from nearest to
+    /// furthest, with ties broken by the points' indices. Returns nothing if
+    /// `tol` is negative enough that no point is within it. Points with a NaN
+    /// coordinate are never returned.
+    pub fn nearest_neighbors_tied(&self, query_point: [f32; 3], tol: f32) -> Vec<(&T, Distance)> {
+        let mut trace = SearchTrace::default();
+        let Some(nearest) = self.nearest_neighbor_search(query_point, &mut trace, &|_| true) else {
+            return vec![];
+        };
+
+        let radius = nearest.distance2_to_query.sqrt() + tol;
+        let mut tied = self.neighbor_indices_within_radius2(query_point, squared_radius(radius));
+        // A point with a NaN coordinate never ties.
+        tied.retain(|&(_, distance2)| !distance2.is_nan());
+        tied.sort_by(|(i1, d1), (i2, d2)| d1.total_cmp(d2).then(i1.cmp(i2)));
+        tied.into_iter()
+            .map(|(point_index, distance2)| {
+                (
+                    &self.point_objs[point_index],
+                    Distance::from_squared(distance2),
+                )
+            })
+            .collect()
+    }
+
+    /// Finds the two points in the uniform grid that are closest to the given
+    /// query point, in a single search.
+    ///
+    /// Returns the nearest point and the second-nearest point, each along with
+    /// its distance to the query point, which saves a second search
+    /// when comparing the two distances. Points that are equally close are
+    /// ordered by their indices. The second point is `None` if the grid only
+    /// has one point, and both are `None` if the grid is empty.
+    #[allow(clippy::type_complexity)]
+    pub fn two_nearest(
+        &self,
+        query_point: [f32; 3],
+    ) -> (Option<(&T, Distance)>, Option<(&T, Distance)>) {
+        let query_point = self.to_local(query_point);
+        let mut two_nearest = TwoNearest::default();
+        match self.local_periodic_box() {
+            Some(periodic_box) => {
+                for image in periodic_images(query_point, periodic_box) {
+                    // The images of the query point are searched into the same two points,
+                    // so an image that's further from the periodic box than the second point
+                    // found so far can't have a closer point.
+                    if let Some(second) = &two_nearest.second {
+                        if box_dist2(image, periodic_box) >= second.distance2_to_query {
+                            continue;
+                        }
+                    }
+                    self.two_nearest_ring_search(image, &mut two_nearest);
+                }
+            }
+            None => self.two_nearest_ring_search(query_point, &mut two_nearest),
+        }
+
+        let with_point = |sr: SearchResult| {
+            (
+                &self.point_objs[sr.point_object_index],
+                Distance::from_squared(sr.distance2_to_query),
+            )
+        };
+        (
+            two_nearest.first.map(with_point),
+            two_nearest.second.map(with_point),
+        )
+    }
+
+    /// Finds the point that is closest to the given query point, using
+    /// `tie_break` to choose between points that are equally close.
+    ///
+    /// `tie_break` is only called for points whose squared distances to the
+    /// query point equal the squared distance to the nearest point, and the
+    /// point that it orders first is returned, so a comparator like
+    /// `|a, b| b.priority.cmp(&a.priority)` prefers points with higher
+    /// priorities. Points that `tie_break` considers equal are ordered by their
+    /// indices.
+    pub fn nearest_neighbor_by<B>(
+        &self,
+        query_point: [f32; 3],
+        tie_break: B,
+    ) -> Option<(&T, Distance)>
+    where
+        B: Fn(&T, &T) -> Ordering,
+    {
+        let mut trace = SearchTrace::default();
+        let nearest = self.nearest_neighbor_search(query_point, &mut trace, &|_| true)?;
+        let mut tied = self
+            .neighbor_indices_within_radius2(query_point, nearest.distance2_to_query)
+            .into_iter()
+            .map(|(point_index, _)| point_index)
+            .collect_vec();
+        tied.sort_unstable();
+
+        let point_index = tied
+            .into_iter()
+            .min_by(|&i1, &i2| tie_break(&self.point_objs[i1], &self.point_objs[i2]))
+            .unwrap_or(nearest.point_object_index);
+        Some((
+            &self.point_objs[point_index],
+            Distance::from_squared(nearest.distance2_to_query),
+        ))
+    }
+
+    /// Finds the point that is closest to the given query point, out of the
+    /// points in the query cell and its 26 neighbors.
+    ///
+    /// No other cells are searched, so the cost of the query is predictable,
+    /// but the result is approximate: a point in a cell further away can be
+    /// closer than the point that's found, and `None` is returned if all of
+    /// the cells in the block are empty. In dense regions of the grid the
+    /// result is usually the nearest neighbor, while near sparse regions it
+    /// often isn't. Periodicity is ignored.
+    pub fn nearest_in_local_block(&self, query_point: [f32; 3]) -> Option<(&T, Distance)> {
+        let query_point = self.to_local(query_point);
+        let query_cell_offset = self.point_into_offset(query_point);
+        let mut trace = SearchTrace::default();
+        let mut block_offsets = neighbor_offsets();
+        block_offsets.push(Offset3::new(0, 0, 0));
+        self.nearest_in_cell_offsets(
+            query_point,
+            query_cell_offset,
+            block_offsets,
+            &mut trace,
+            &|_| true,
+        )
+        .map(|sr| {
+            // Stored positions may be approximate, so use the exact position of the point
+            // that's found.
+            let distance2 = if self.cell_point_positions.is_lossy() {
+                let position = self.to_local(self.point_position(sr.point_object_index));
+                dist2(query_point, position)
+            } else {
+                sr.distance2_to_query
+            };
+            (
+                &self.point_objs[sr.point_object_index],
+                Distance::from_squared(distance2),
+            )
+        })
+    }
+
+    /// Finds the point in the uniform grid that is closest to the given query
+    /// point, and also returns the tag of the source that the point came from.
+    ///
+    /// See `source_tag` for the tag that's returned.
+    pub fn nearest_neighbor_with_source(
+        &self,
+        query_point: [f32; 3],
+    ) -> Option<(&T, Distance, Option<u32>)> {
+        let mut trace = SearchTrace::default();
+        self.nearest_neighbor_search(query_point, &mut trace, &|_| true)
+            .map(|sr| {
+                (
+                    &self.point_objs[sr.point_object_index],
+                    Distance::from_squared(sr.distance2_to_query),
+                    self.source_tag(sr.point_object_index),
+                )
+            })
+    }
+
+    /// Finds the point in the uniform grid that is closest to the given query
+    /// point, and also returns the point's normal, if it has one.
+    ///
+    /// The normal is returned with its axes in the grid's order, like the
+    /// query point.
+    pub fn nearest_neighbor_with_normal(
+        &self,
+        query_point: [f32; 3],
+    ) -> Option<(&T, Distance, Option<[f32; 3]>)> {
+        let mut trace = SearchTrace::default();
+        self.nearest_neighbor_search(query_point, &mut trace, &|_| true)
+            .map(|sr| {
+                let point = &self.point_objs[sr.point_object_index];
+                let normal = point
+                    .normal()
+                    .map(|normal| self.options.grid_position(normal));
+                (point, Distance::from_squared(sr.distance2_to_query), normal)
+            })
+    }
+
+    /// Finds the point in the uniform grid that is closest to the given query
+    /// point, and also returns the vector from the query point to the point.
+    ///
+    /// Returns the point, the vector, and the point's distance to the query
+    /// point. The vector is the point's position minus the query point,
+    /// with its axes in the grid's order, like the query point. In a periodic
+    /// grid, the vector points to the nearest periodic image of the point.
+    pub fn nearest_neighbor_delta(
+        &self,
+        query_point: [f32; 3],
+    ) -> Option<(&T, [f32; 3], Distance)> {
+        let mut trace = SearchTrace::default();
+        self.nearest_neighbor_search(query_point, &mut trace, &|_| true)
+            .map(|sr| {
+                let position = self.point_position(sr.point_object_index);
+                let delta = [0, 1, 2].map(|axis| position[axis] - query_point[axis]);
+                let delta = match self.options.periodic_box {
+                    Some(periodic_box) => nearest_image_delta(delta, periodic_box),
+                    None => delta,
+                };
+                (
+                    &self.point_objs[sr.point_object_index],
+                    delta,
+                    Distance::from_squared(sr.distance2_to_query),
+                )
+            })
+    }
+
+    /// Finds the point in the uniform grid that is closest to the given query
+    /// point, and also returns the offset of the cell that contains the found
+    /// point.
+    ///
+    /// The offset is relative to the uniform grid's "origin cell" at
+    /// `(0, 0, 0)`.
+    pub fn nearest_neighbor_with_cell(
+        &self,
+        query_point: [f32; 3],
+    ) -> Option<(&T, Distance, Offset3)> {
+        let mut trace = SearchTrace::default();
+        self.nearest_neighbor_search(query_point, &mut trace, &|_| true)
+            .map(|sr| {
+                (
+                    &self.point_objs[sr.point_object_index],
+                    Distance::from_squared(sr.distance2_to_query),
+                    self.index1_into_offset(sr.cell_index1),
+                )
+            })
+    }
+
+    /// Counts the points in each ring of cells around the cell that contains
+    /// the query point, where a cell is in the `n`th ring if it's `n` cells
+    /// away from the query cell along at least one axis.
+    ///
+    /// Returns the counts for rings `0` through `max_rings`, where ring `0` is
+    /// the query cell itself. A search for the nearest point examines the
+    /// rings in order until it finds a point, so many empty rings close to the
+    /// query point explain a slow search, as reported by `rings_searched` in
+    /// `nearest_neighbor_traced`. The query point is wrapped into the periodic
+    /// box first if the grid is periodic, but the rings don't wrap around it.
+    pub fn ring_occupancy(&self, query_point: [f32; 3], max_rings: usize) -> Vec<usize> {
+        let query_point = self.to_local(query_point);
+        let query_point = match self.local_periodic_box() {
+            Some(periodic_box) => wrap_into_box(query_point, periodic_box),
+            None => query_point,
+        };
+        let query_cell_offset = self.point_into_offset(query_point);
+
+        // Rings past the largest reach are outside of the grid, so they're empty.
+        let max_reach = self.sorted_reach(query_cell_offset)[2];
+        (0..=max_rings)
+            .map(|ring| {
+                if ring as i64 > max_reach {
+                    return 0;
+                }
+                self.ring_cells(query_cell_offset, ring as i64)
+                    .map(|cell_index| self.cell_point_counts[cell_index])
+                    .sum()
+            })
+            .collect()
+    }
+
+    /// Finds the point in the uniform grid that is closest to the given query
+    /// point, along with details about how the search went.
+    ///
+    /// See `TracedNeighbor` for the details that are reported.
+    pub fn nearest_neighbor_traced(&self, query_point: [f32; 3]) -> Option<TracedNeighbor<'_, T>> {
+        let mut trace = SearchTrace::default();
+        self.nearest_neighbor_search(query_point, &mut trace, &|_| true)
+            .map(|sr| TracedNeighbor {
+                point: &self.point_objs[sr.point_object_index],
+                distance: Distance::from_squared(sr.distance2_to_query),
+                exact: trace.exact,
+                rings_searched: trace.rings_searched,
+            })
+    }
+
+    /// Finds the point in the uniform grid that is closest to the given query
+    /// point after the query point is transformed into the grid's frame.
+    ///
+    /// `inverse_transform` is an affine transform in row-major order that maps
+    /// points from the query point's frame into the frame of the grid's
+    /// points, so the query point `p` becomes `inverse_transform * [p, 1]`.
+    /// The bottom row of the matrix is ignored. This lets a grid over a fixed
+    /// point set be queried with points that move by a known transform,
+    /// without rebuilding the grid.
+    ///
+    /// The distance that's returned is measured in the grid's frame, which
+    /// equals the distance in the query point's frame when the transform is
+    /// rigid.
+    pub fn query_transformed(
+        &self,
+        query_point: [f32; 3],
+        inverse_transform: &[[f32; 4]; 4],
+    ) -> Option<(&T, Distance)> {
+        self.nearest_neighbor(transform_point(inverse_transform, query_point))
+    }
+
+    /// Finds the point in the uniform grid that is closest to the given query
+    /// point, and also returns the number of cells that the search examined.
+    ///
+    /// Every cell inside the grid that the search looks into is counted, even
+    /// if it's empty. For a periodic grid, the cells that are examined around
+    /// each image of the query point are all counted. The count doesn't depend
+    /// on timing, so it's suited to checking how well the grid is laid out for
+    /// a set of points.
+    pub fn nearest_neighbor_counted(
+        &self,
+        query_point: [f32; 3],
+    ) -> (Option<(&T, Distance)>, usize) {
+        let mut trace = SearchTrace::default();
+        let maybe_nearest = self
+            .nearest_neighbor_search(query_point, &mut trace, &|_| true)
+            .map(|sr| {
+                (
+                    &self.point_objs[sr.point_object_index],
+                    Distance::from_squared(sr.distance2_to_query),
+                )
+            });
+        (maybe_nearest, trace.cells_scanned)
+    }
+
+    /// Finds the point in the uniform grid that is closest to the given query
+    /// point, and also returns the offsets of the cells that the search
+    /// examined, in the order it examined them.
+    ///
+    /// The offsets are relative to the uniform grid's "origin cell" at
+    /// `(0, 0, 0)`. The query cell comes first, followed by the cells that
+    /// the search moved out to, until it could stop. Cells are recorded the
+    /// same way as they're counted by `nearest_neighbor_counted`, so the
+    /// number of offsets equals that count, and a periodic grid records the
+    /// cells around each image of the query point in turn.
+    pub fn nearest_neighbor_visited_cells(
+        &self,
+        query_point: [f32; 3],
+    ) -> (Option<(&T, Distance)>, Vec<Offset3>) {
+        let mut trace = SearchTrace {
+            visited_cells: Some(Vec::new()),
+            ..SearchTrace::default()
+        };
+        let maybe_nearest = self
+            .nearest_neighbor_search(query_point, &mut trace, &|_| true)
+            .map(|sr| {
+                (
+                    &self.point_objs[sr.point_object_index],
+                    Distance::from_squared(sr.distance2_to_query),
+                )
+            });
+        (maybe_nearest, trace.visited_cells.unwrap_or_default())
+    }
+
+    /// Finds the point in the uniform grid that is closest to the given query
+    /// point, examining at most `max_cells` cells.
+    ///
+    /// Cells are counted the same way as by `nearest_neighbor_counted`. If the
+    /// budget runs out before the search is done, then the nearest point found
+    /// so far is returned, which may not be the nearest neighbor, or `None` if
+    /// no point was found yet. The returned flag is true if the result is
+    /// guaranteed to be the nearest neighbor, which requires that the budget
+    /// didn't run out and that the result would be exact for
+    /// `nearest_neighbor_traced`.
+    pub fn nearest_neighbor_budgeted(
+        &self,
+        query_point: [f32; 3],
+        max_cells: usize,
+    ) -> (Option<(&T, Distance)>, bool) {
+        let mut trace = SearchTrace {
+            cell_budget: max_cells,
+            ..SearchTrace::default()
+        };
+        let maybe_nearest = self
+            .nearest_neighbor_search(query_point, &mut trace, &|_| true)
+            .map(|sr| {
+                (
+                    &self.point_objs[sr.point_object_index],
+                    Distance::from_squared(sr.distance2_to_query),
+                )
+            });
+        (maybe_nearest, trace.exact && !trace.is_over_budget)
+    }
+
+    /// Finds the point in the uniform grid that is closest to the given query
+    /// point, using a hint that remembers the cell of the previous query.
+    ///
+    /// Successive queries that are close together often land in the same
+    /// cell. When the query point is still inside the hinted cell, the search
+    /// starts from that cell without locating the query point in the grid.
+    /// Otherwise the query point is located as usual, and the hint is updated.
+    /// The result is the same as the result of `nearest_neighbor`.
+    ///
+    /// Periodic grids ignore the hint.
+    pub fn nearest_neighbor_cached(
+        &self,
+        query_point: [f32; 3],
+        hint: &mut QueryHint,
+    ) -> Option<(&T, Distance)> {
+        let mut trace = SearchTrace::default();
+        let maybe_nearest = match self.options.periodic_box {
+            Some(_) => self.nearest_neighbor_search(query_point, &mut trace, &|_| true),
+            None => {
+                let query_point = self.to_local(query_point);
+                let query_cell_offset = match hint.last_cell_offset {
+                    Some(offset) if self.is_point_in_cell(query_point, offset) => offset,
+                    _ => self.point_into_offset(query_point),
+                };
+                hint.last_cell_offset = Some(query_cell_offset);
+                self.nearest_neighbor_grid_search_from(
+                    query_point,
+                    query_cell_offset,
+                    &mut trace,
+                    &|_| true,
+                )
+            }
+        };
+        maybe_nearest.map(|sr| {
+            (
+                &self.point_objs[sr.point_object_index],
+                Distance::from_squared(sr.distance2_to_query),
+            )
+        })
+    }
+
+    /// For the cell that contains the query point and each of its 26 neighbors,
+    /// finds the point in that cell that is closest to the query point.
+    ///
+    /// Each result contains the offset of the cell, relative to the uniform
+    /// grid's "origin cell" at `(0, 0, 0)`, followed by the nearest point in
+    /// the cell and its distance to the query point. The query cell
+    /// comes first, and cells that are empty or outside the grid are skipped.
+    /// Periodicity is ignored.
+    pub fn nearest_per_neighbor_cell(&self, query_point: [f32; 3]) -> Vec<(Offset3, &T, Distance)> {
+        let query_point = self.to_local(query_point);
+        let query_cell_offset = self.point_into_offset(query_point);
+        std::iter::once(Offset3::new(0, 0, 0))
+            .chain(neighbor_offsets())
+            .map(|o| query_cell_offset + o)
+            .filter_map(|cell_offset| {
+                let cell_index = self.offset_into_index1(cell_offset)?;
+                nearest(query_point, self.cell_points(cell_index), cell_index).map(|sr| {
+                    (
+                        cell_offset,
+                        &self.point_objs[sr.point_object_index],
+                        Distance::from_squared(sr.distance2_to_query),
+                    )
+                })
+            })
+            .collect()
+    }
+
+    /// Returns true if one of the points in the uniform grid is at exactly the
+    /// given position.
+    ///
+    /// Only the cell that the position would be bucketed into is checked, so
+    /// this doesn't scan the whole grid. Coordinates are compared with `==`,
+    /// so a position that was computed differently from a point's position,
+    /// and differs from it by rounding, doesn't match, while `0.0` and `-0.0`
+    /// do match. Positions with a NaN coordinate never match. For a periodic
+    /// grid, the position is wrapped into the periodic box, so it matches
+    /// points at any of its periodic images.
+    pub fn contains_point(&self, point: [f32; 3]) -> bool {
+        let wrap = |position: [f32; 3]| match self.options.periodic_box {
+            Some(periodic_box) => wrap_into_box(position, periodic_box),
+            None => position,
+        };
+        let position = wrap(point);
+        self.offset_into_index1(self.point_into_offset(self.to_local(position)))
+            .is_some_and(|cell_index| {
+                self.cell_points(cell_index)
+                    .any(|(_, point_index)| wrap(self.point_position(point_index)) == position)
+            })
+    }
+
+    /// Returns true if there is at least one point in the uniform grid whose
+    /// distance to the query point is less than or equal to `epsilon`, which is
+    /// never the case if `epsilon` is negative.
+    ///
+    /// Only the cells that overlap the sphere of radius `epsilon` around the
+    /// query point are checked, and the search stops as soon as a point is
+    /// found.
+    pub fn has_neighbor_within(&self, query_point: [f32; 3], epsilon: f32) -> bool {
+        let query_point = self.to_local(query_point);
+        let epsilon2 = squared_radius(epsilon);
+        let has_neighbor_near = |query_point: [f32; 3]| {
+            self.cells_overlapping_sphere(query_point, epsilon2)
+                .any(|cell_index| {
+                    self.cell_points(cell_index)
+                        .any(|(pos, _)| dist2(query_point, pos) <= epsilon2)
+                })
+        };
+
+        match self.local_periodic_box() {
+            Some(periodic_box) => periodic_images(query_point, periodic_box)
+                .filter(|&image| box_dist2(image, periodic_box) <= epsilon2)
+                .any(has_neighbor_near),
+            None => has_neighbor_near(query_point),
+        }
+    }
+
+    /// Finds all points in the uniform grid that are within the given radius of
+    /// the query point.
+    ///
+    /// Returns each point along with its distance to the query point. The
+    /// points are not returned in any particular order. A negative radius
+    /// finds nothing, and points with a NaN coordinate are never found.
+    ///
+    /// Unlike the nearest-neighbor queries, this doesn't spiral outward from
+    /// the query cell. It only examines the cells that overlap the sphere of
+    /// the given radius around the query point, so it suits fixed-radius
+    /// queries such as those of SPH or of filtering a point cloud.
+    #[doc(alias = "within_radius")]
+    pub fn neighbors_within_radius(
+        &self,
+        query_point: [f32; 3],
+        radius: f32,
+    ) -> Vec<(&T, Distance)> {
+        self.neighbors_within_radius2(query_point, squared_radius(radius))
+    }
+
+    /// Finds all points in the uniform grid that are within the given radius of
+    /// the query point, like `neighbors_within_radius`, and writes them into
+    /// `neighbors`.
+    ///
+    /// `neighbors` is cleared first, and then holds the index into `points()`
+    /// of each point that's found, along with its distance to the query point.
+    /// Reusing the same vector for many queries saves allocating a vector for
+    /// each query once the vector has grown large enough.
+    #[doc(alias = "within_radius_into")]
+    pub fn neighbors_within_radius_into(
+        &self,
+        query_point: [f32; 3],
+        radius: f32,
+        neighbors: &mut Vec<(usize, Distance)>,
+    ) {
+        neighbors.clear();
+        let _ = self.visit_neighbors_within_radius2(
+            query_point,
+            squared_radius(radius),
+            |point_index, distance2| {
+                neighbors.push((point_index, Distance::from_squared(distance2)));
+                ControlFlow::Continue(())
+            },
+        );
+    }
+
+    /// Calls `visit` with each point in the uniform grid that is within the
+    /// given radius of the query point, along with its distance to the query
+    /// point.
+    ///
+    /// Each point is visited once, in no particular order, and none are visited
+    /// if the radius is negative. The traversal stops when `visit` returns
+    /// `ControlFlow::Break`. The points aren't collected
+    /// into a vector, so sums or histograms over the points can be accumulated
+    /// without allocating for each query.
+    #[doc(alias = "within_radius")]
+    pub fn for_each_within_radius<F>(&self, query_point: [f32; 3], radius: f32, mut visit: F)
+    where
+        F: FnMut(&T, Distance) -> ControlFlow<()>,
+    {
+        let _ = self.visit_neighbors_within_radius2(
+            query_point,
+            squared_radius(radius),
+            |point_index, distance2| {
+                visit(
+                    &self.point_objs[point_index],
+                    Distance::from_squared(distance2),
+                )
+            },
+        );
+    }
+
+    /// Finds all points in the uniform grid whose distance to the query point
+    /// under the given metric is at most `radius`.
+    ///
+    /// Returns each point along with its distance to the query point under the
+    /// metric. The points are not returned in any particular order.
+    pub fn neighbors_within_radius_by_metric<M>(
+        &self,
+        query_point: [f32; 3],
+        radius: f32,
+        metric: &M,
+    ) -> Vec<(&T, f32)>
+    where
+        M: Metric,
+    {
+        let query_point = self.to_local(query_point);
+        let mut neighbors = Vec::new();
+        let mut push_neighbor = |point_index, distance| neighbors.push((point_index, distance));
+        match self.local_periodic_box() {
+            Some(periodic_box) => {
+                for image in periodic_images(query_point, periodic_box).filter(|&image| {
+                    metric.distance(image, clamp_into_box(image, periodic_box)) <= radius
+                }) {
+                    self.visit_neighbors_within_metric_radius(
+                        image,
+                        radius,
+                        metric,
+                        &mut push_neighbor,
+                    );
+                }
+
+                // When the radius is large compared to the periodic box, a point can be near
+                // more than one image of the query point.
+                dedup_by_point_index(&mut neighbors);
+            }
+            None => self.visit_neighbors_within_metric_radius(
+                query_point,
+                radius,
+                metric,
+                push_neighbor,
+            ),
+        }
+        neighbors
+            .into_iter()
+            .map(|(point_index, distance)| (&self.point_objs[point_index], distance))
+            .collect()
+    }
+
+    /// Finds all points in the uniform grid whose squared distance to the query
+    /// point is at most `radius2`.
+    ///
+    /// Returns each point along with its distance to the query point. The
+    /// points are not returned in any particular order.
+    pub fn neighbors_within_radius2(
+        &self,
+        query_point: [f32; 3],
+        radius2: f32,
+    ) -> Vec<(&T, Distance)> {
+        self.neighbor_indices_within_radius2(query_point, radius2)
+            .into_iter()
+            .map(|(point_index, distance2)| {
+                (
+                    &self.point_objs[point_index],
+                    Distance::from_squared(distance2),
+                )
+            })
+            .collect()
+    }
+
+    /// Finds the point in the uniform grid that is nearest to each of the query
+    /// points.
+    ///
+    /// Returns one result for each query point, in the same order as
+    /// `query_points`, which is the same as `nearest_neighbor` returns for
+    /// that query point. With the `rayon` feature,
+    /// `nearest_neighbors_batch_parallel` searches the query points in
+    /// parallel.
+    pub fn nearest_neighbors_batch(
+        &self,
+        query_points: &[[f32; 3]],
+    ) -> Vec<Option<(&T, Distance)>> {
+        query_points
+            .iter()
+            .map(|&query_point| self.nearest_neighbor(query_point))
+            .collect()
+    }
+
+    /// Finds all points in the uniform grid that are within the given radius of
+    /// each of the query points.
+    ///
+    /// Returns one vector for each query point. Each vector contains the index
+    /// into `points()` of each point that's found, along with its distance to
+    /// the query point, and is empty if the radius is negative. With the
+    /// `rayon` feature, `neighbors_within_radius_batch_parallel` searches the
+    /// query points in parallel.
+    pub fn neighbors_within_radius_batch(
+        &self,
+        query_points: &[[f32; 3]],
+        radius: f32,
+    ) -> Vec<Vec<(usize, Distance)>> {
+        query_points
+            .iter()
+            .map(|&query_point| {
+                with_distances(
+                    self.neighbor_indices_within_radius2(query_point, squared_radius(radius)),
+                )
+            })
+            .collect()
+    }
+
+    /// Finds, for each point in the uniform grid, the `k` other points that
+    /// are nearest to it.
+    ///
+    /// Returns one vector for each point, in the same order as `points()`.
+    /// Each vector contains the index into `points()` of each neighbor that's
+    /// found, along with its distance to the point, sorted from
+    /// nearest to furthest. A point is never its own neighbor, but other points
+    /// at the same position are. If the grid has `k` or fewer points, then
+    /// each point's neighbors are all of the other points. A point with a NaN
+    /// coordinate has no neighbors and is never a neighbor.
+    ///
+    /// The points are searched one cell at a time, rather than in the order of
+    /// their indices, so that consecutive searches look into the same cells.
+    /// With the `rayon` feature, `knn_graph_parallel` searches them in
+    /// parallel.
+    pub fn knn_graph(&self, k: usize) -> Vec<Vec<(usize, Distance)>> {
+        let mut graph = vec![Vec::new(); self.point_objs.len()];
+        for point_index in self.point_indices_in_cell_order() {
+            graph[point_index] = self.k_nearest_to_point(point_index, k);
+        }
+        graph
+    }
+
+    /// Returns the indices of the points in the order of the cells that
+    /// they're bucketed into.
+    fn point_indices_in_cell_order(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..self.cell_point_positions.cell_count()).flat_map(|cell_index| {
+            self.cell_points(cell_index)
+                .map(|(_, point_index)| point_index)
+        })
+    }
+
+    /// Finds the `k` points nearest to the point at the given index, excluding
+    /// the point itself.
+    fn k_nearest_to_point(&self, point_index: usize, k: usize) -> Vec<(usize, Distance)> {
+        let query_point = self.point_position(point_index);
+        let other_count = self.point_objs.len() - 1;
+        let k = k.min(other_count);
+        if k == 0 || query_point.iter().any(|c| c.is_nan()) {
+            return vec![];
+        }
+
+        // Every point is within this distance of the query point, so a search with
+        // this radius finds all of the other points.
+        let grid_box = self.bounding_box();
+        let max_radius = dist2(grid_box.min, grid_box.max()).sqrt()
+            + box_dist2(query_point, (grid_box.min, grid_box.max())).sqrt();
+
+        // Search within spheres of growing radius until the sphere contains at least
+        // `k` other points, since the `k` nearest points are then all inside it.
+        let mut radius = self.cell_widths.into_iter().fold(f32::INFINITY, min_f32);
+        loop {
+            let mut neighbors = self
+                .neighbor_indices_within_radius2(query_point, radius * radius)
+                .into_iter()
+                .filter(|&(neighbor_index, distance2)| {
+                    // A point with a NaN coordinate is never a neighbor.
+                    neighbor_index != point_index && distance2.is_finite()
+                })
+                .collect_vec();
+            if neighbors.len() >= k || radius >= max_radius {
+                neighbors.sort_by(|(i1, d1), (i2, d2)| d1.total_cmp(d2).then(i1.cmp(i2)));
+                neighbors.truncate(k);
+                return with_distances(neighbors);
+            }
+            radius *= 2.0;
+        }
+    }
+
+    fn neighbor_indices_within_radius2(
+        &self,
+        query_point: [f32; 3],
+        radius2: f32,
+    ) -> Vec<(usize, f32)> {
+        let mut neighbors = Vec::new();
+        let _ =
+            self.visit_neighbors_within_radius2(query_point, radius2, |point_index, distance2| {
+                neighbors.push((point_index, distance2));
+                ControlFlow::Continue(())
+            });
+
+        // Each point is only visited once, but the results of the radius and k-nearest
+        // queries are built on this, so don't rely on that.
+        dedup_by_point_index(&mut neighbors);
+        neighbors
+    }
+
+    /// Calls `visit` with the index of each point whose squared distance to the
+    /// query point is at most `radius2`, along with that squared distance.
+    ///
+    /// Each point is visited once. The traversal stops when `visit` returns
+    /// `ControlFlow::Break`.
+    fn visit_neighbors_within_radius2<F>(
+        &self,
+        query_point: [f32; 3],
+        radius2: f32,
+        mut visit: F,
+    ) -> ControlFlow<()>
+    where
+        F: FnMut(usize, f32) -> ControlFlow<()>,
+    {
+        let query_point = self.to_local(query_point);
+        let mut images = [query_point; 27];
+        let mut image_count = 1;
+        if let Some(periodic_box) = self.local_periodic_box() {
+            image_count = 0;
+            for image in periodic_images(query_point, periodic_box)
+                .filter(|&image| box_dist2(image, periodic_box) <= radius2)
+            {
+                images[image_count] = image;
+                image_count += 1;
+            }
+        }
+        let images = &images[..image_count];
+
+        for (image_index, &image) in images.iter().enumerate() {
+            for cell_index in self.cells_overlapping_sphere(image, radius2) {
+                for (position, point_index) in self.cell_points(cell_index) {
+                    let position = self.exact_position(position, point_index);
+                    // A point with a NaN coordinate has a NaN distance, which is never within
+                    // the radius.
+                    let distance2 = dist2(image, position);
+                    if distance2.is_nan() || distance2 > radius2 {
+                        continue;
+                    }
+
+                    // When the radius is large compared to the periodic box, a point can be
+                    // near more than one image of the query point. It's only visited from the
+                    // first of them, with its distance to the nearest of them.
+                    if images[..image_index]
+                        .iter()
+                        .any(|&other| dist2(other, position) <= radius2)
+                    {
+                        continue;
+                    }
+                    let distance2 = images[image_index + 1..]
+                        .iter()
+                        .map(|&other| dist2(other, position))
+                        .fold(distance2, |d1, d2| if d2 < d1 { d2 } else { d1 });
+                    visit(point_index, distance2)?;
+                }
+            }
+        }
+        ControlFlow::Continue(())
+    }
+
+    /// Finds the point that is closest to the query point, out of the points
+    /// whose indices are accepted by `filter`.
+    fn nearest_neighbor_search<F>(
+        &self,
+        query_point: [f32; 3],
+        trace: &mut SearchTrace,
+        filter: &F,
+    ) -> Option<SearchResult>
+    where
+        F: Fn(usize) -> bool,
+    {
+        let query_point = self.to_local(query_point);
+        match self.local_periodic_box() {
+            Some(periodic_box) => {
+                self.nearest_neighbor_periodic_search(query_point, periodic_box, |image| {
+                    self.nearest_neighbor_grid_search(image, trace, filter)
+                })
+            }
+            None => self.nearest_neighbor_grid_search(query_point, trace, filter),
+        }
+    }
+
+    /// Finds the point whose nearest periodic image is closest to the query
+    /// point.
+    ///
+    /// The distance from an image of the query point to a point equals the
+    /// distance from the query point to an image of that point, so this
+    /// searches the grid around the images of the query point. Images that
+    /// are further from the periodic box than the nearest point found so far
+    /// are skipped.
+    ///
+    /// `search_image` finds the nearest point to an image of the query point
+    /// in the (non-periodic) grid.
+    fn nearest_neighbor_periodic_search<S>(
+        &self,
+        query_point: [f32; 3],
+        periodic_box: ([f32; 3], [f32; 3]),
+        mut search_image: S,
+    ) -> Option<SearchResult>
+    where
+        S: FnMut([f32; 3]) -> Option<SearchResult>,
+    {
+        let mut maybe_nearest_so_far: Option<SearchResult> = None;
+        for image in periodic_images(query_point, periodic_box) {
+            if let Some(nearest_so_far) = &maybe_nearest_so_far {
+                if box_dist2(image, periodic_box) >= nearest_so_far.distance2_to_query {
+                    continue;
+                }
+            }
+            if let Some(nearest) = search_image(image) {
+                let is_new_nearest = match &maybe_nearest_so_far {
+                    None => true,
+                    Some(nearest_so_far) => {
+                        nearest.distance2_to_query < nearest_so_far.distance2_to_query
+                    }
+                };
+                if is_new_nearest {
+                    maybe_nearest_so_far = Some(nearest);
+                }
+            }
+        }
+        maybe_nearest_so_far
+    }
+
+    fn nearest_neighbor_grid_search<F>(
+        &self,
+        query_point: [f32; 3],
+        trace: &mut SearchTrace,
+        filter: &F,
+    ) -> Option<SearchResult>
+    where
+        F: Fn(usize) -> bool,
+    {
+        let query_cell_offset = self.point_into_offset(query_point);
+        self.nearest_neighbor_grid_search_from(query_point, query_cell_offset, trace, filter)
+    }
+
+    /// Searches the grid outward from the given query cell, which must be the
+    /// cell that contains the query point.
+    fn nearest_neighbor_grid_search_from<F>(
+        &self,
+        query_point: [f32; 3],
+        query_cell_offset: Offset3,
+        trace: &mut SearchTrace,
+        filter: &F,
+    ) -> Option<SearchResult>
+    where
+        F: Fn(usize) -> bool,
+    {
+        match self.nearest_neighbor_in_query_cell(query_point, query_cell_offset, trace, filter) {
+            // Any point closer than the one found would have to be in the query cell or one
+            // of its neighbors, and those have all been checked.
+            Some(nearest)
+                if self.is_sphere_within_cells(
+                    query_point,
+                    query_cell_offset,
+                    1,
+                    nearest.distance2_to_query,
+                ) =>
+            {
+                Some(nearest)
+            }
+            // The spiral cells measure distances in whole cells, which only works when the
+            // cells are cubes, so other grids keep searching one ring at a time. If a
+            // point was found, then the first ring has been searched too.
+            maybe_nearest_so_far if !self.has_cubic_cells() => {
+                let start_ring = if maybe_nearest_so_far.is_some() { 2 } else { 1 };
+                self.nearest_neighbor_traced_ring_search(
+                    query_point,
+                    query_cell_offset,
+                    start_ring,
+                    maybe_nearest_so_far,
+                    trace,
+                    filter,
+                )
+            }
+            maybe_nearest_so_far => self
+                .nearest_neighbor_spiral_search(
+                    query_point,
+                    query_cell_offset,
+                    maybe_nearest_so_far,
+                    trace,
+                    filter,
+                )
+                .or_else(|| self.nearest_neighbor_brute_force(query_point, trace, filter)),
+        }
+        .map(|mut nearest| {
+            // Stored positions may be approximate, so use the exact position of the point
+            // that's found.
+            if self.cell_point_positions.is_lossy() {
+                trace.exact = false;
+                let position = self.to_local(self.point_position(nearest.point_object_index));
+                nearest.distance2_to_query = dist2(query_point, position);
+            }
+            nearest
+        })
+    }
+
+    /// Searches the grid outward from the query cell, one ring of cells at a
+    /// time starting at `start_ring`, for the nearest point whose index is
+    /// accepted by `filter`.
+    ///
+    /// The rings closer than `start_ring` must have been searched already,
+    /// with the nearest point in them passed in as `maybe_nearest_so_far`.
+    /// Every cell that's examined is recorded in `trace`, like the cells of
+    /// the spiral search, and the search stops early if the trace's cell
+    /// budget runs out.
+    fn nearest_neighbor_traced_ring_search<F>(
+        &self,
+        query_point: [f32; 3],
+        query_cell_offset: Offset3,
+        start_ring: i64,
+        mut maybe_nearest_so_far: Option<SearchResult>,
+        trace: &mut SearchTrace,
+        filter: &F,
+    ) -> Option<SearchResult>
+    where
+        F: Fn(usize) -> bool,
+    {
+        // Every cell in the grid is in one of the rings from the first ring that
+        // reaches the grid up to the largest reach.
+        let start_ring = start_ring.max(self.first_ring(query_cell_offset));
+        let max_ring = self.sorted_reach(query_cell_offset)[2];
+
+        for ring in start_ring..=max_ring {
+            for cell_offset in self.ring_cell_offsets(query_cell_offset, ring) {
+                if !trace.scan_cell(cell_offset, ring as usize) {
+                    return maybe_nearest_so_far;
+                }
+                let Some(cell_index) = self.offset_into_index1(cell_offset) else {
+                    continue;
+                };
+                let maybe_nearest_in_cell = nearest(
+                    query_point,
+                    self.cell_points(cell_index)
+                        .filter(|&(_, point_index)| filter(point_index)),
+                    cell_index,
+                );
+                if let Some(nearest_in_cell) = maybe_nearest_in_cell {
+                    let is_new_nearest = match &maybe_nearest_so_far {
+                        None => true,
+                        Some(nearest_so_far) => {
+                            nearest_in_cell.distance2_to_query < nearest_so_far.distance2_to_query
+                        }
+                    };
+                    if is_new_nearest {
+                        maybe_nearest_so_far = Some(nearest_in_cell);
+                    }
+                }
+            }
+
+            // Any point closer than the one found would have to be in one of the rings
+            // that have been searched.
+            if let Some(nearest_so_far) = &maybe_nearest_so_far {
+                if self.is_sphere_within_cells(
+                    query_point,
+                    query_cell_offset,
+                    ring,
+                    nearest_so_far.distance2_to_query,
+                ) {
+                    break;
+                }
+            }
+        }
+        maybe_nearest_so_far
+    }
+
+    /// Searches the grid outward from the query cell, one ring of cells at a
+    /// time, for the nearest point whose squared distance to the query point
+    /// is at most `max_distance2`, which may be infinite.
+    fn nearest_neighbor_ring_search(
+        &self,
+        query_point: [f32; 3],
+        max_distance2: f32,
+    ) -> Option<SearchResult> {
+        let query_cell_offset = self.point_into_offset(query_point);
+
+        // Every cell in the grid is in one of the rings from the first ring that
+        // reaches the grid up to the largest reach.
+        let first_ring = self.first_ring(query_cell_offset);
+        let max_ring = self.sorted_reach(query_cell_offset)[2];
+
+        let mut maybe_nearest_so_far: Option<SearchResult> = None;
+        for ring in first_ring..=max_ring {
+            for cell_index in self.ring_cells(query_cell_offset, ring) {
+                if let Some(nearest_in_cell) =
+                    nearest(query_point, self.cell_points(cell_index), cell_index)
+                {
+                    let is_new_nearest = match &maybe_nearest_so_far {
+                        None => true,
+                        Some(nearest_so_far) => {
+                            nearest_in_cell.distance2_to_query < nearest_so_far.distance2_to_query
+                        }
+                    };
+                    if is_new_nearest {
+                        maybe_nearest_so_far = Some(nearest_in_cell);
+                    }
+                }
+            }
+
+            // Any point closer than the one found, or within the maximum distance, would
+            // have to be in one of the rings that have been searched.
+            let reach2 = maybe_nearest_so_far
+                .as_ref()
+                .map_or(max_distance2, |nearest_so_far| {
+                    nearest_so_far.distance2_to_query.min(max_distance2)
+                });
+            if self.is_sphere_within_cells(query_point, query_cell_offset, ring, reach2) {
+                break;
+            }
+        }
+
+        maybe_nearest_so_far
+            .map(|mut nearest| {
+                // Stored positions may be approximate, so use the exact position of the
+                // point that's found.
+                if self.cell_point_positions.is_lossy() {
+                    let position = self.to_local(self.point_position(nearest.point_object_index));
+                    nearest.distance2_to_query = dist2(query_point, position);
+                }
+                nearest
+            })
+            .filter(|nearest| nearest.distance2_to_query <= max_distance2)
+    }
+
+    /// Searches the grid outward from the query cell, one ring of cells at a
+    /// time, for the nearest point under the metric.
+    ///
+    /// Returns the index of the point along with its distance to the query
+    /// point under the metric.
+    fn nearest_neighbor_metric_ring_search<M>(
+        &self,
+        query_point: [f32; 3],
+        metric: &M,
+    ) -> Option<(usize, f32)>
+    where
+        M: Metric,
+    {
+        let query_cell_offset = self.point_into_offset(query_point);
+
+        // Every cell in the grid is in one of the rings from the first ring that
+        // reaches the grid up to the largest reach.
+        let first_ring = self.first_ring(query_cell_offset);
+        let max_ring = self.sorted_reach(query_cell_offset)[2];
+
+        let mut maybe_nearest_so_far: Option<(usize, f32)> = None;
+        for ring in first_ring..=max_ring {
+            for cell_index in self.ring_cells(query_cell_offset, ring) {
+                for (position, point_index) in self.cell_points(cell_index) {
+                    let distance =
+                        metric.distance(query_point, self.exact_position(position, point_index));
+                    let is_new_nearest = match maybe_nearest_so_far {
+                        None => !distance.is_nan(),
+                        Some((_, distance_so_far)) => distance < distance_so_far,
+                    };
+                    if is_new_nearest {
+                        maybe_nearest_so_far = Some((point_index, distance));
+                    }
+                }
+            }
+
+            // Any point closer than the one found would have to be in one of the rings
+            // that have been searched.
+            if let Some((_, distance_so_far)) = maybe_nearest_so_far {
+                if self.metric_distance_outside_cells(query_point, query_cell_offset, ring, metric)
+                    > distance_so_far
+                {
+                    break;
+                }
+            }
+        }
+        maybe_nearest_so_far
+    }
+
+    /// Calls `visit` with the index of each point whose distance to the query
+    /// point under the metric is at most `radius`, along with that distance.
+    fn visit_neighbors_within_metric_radius<M, V>(
+        &self,
+        query_point: [f32; 3],
+        radius: f32,
+        metric: &M,
+        mut visit: V,
+    ) where
+        M: Metric,
+        V: FnMut(usize, f32),
+    {
+        let query_cell_offset = self.point_into_offset(query_point);
+
+        // Every cell in the grid is in one of the rings from the first ring that
+        // reaches the grid up to the largest reach.
+        let first_ring = self.first_ring(query_cell_offset);
+        let max_ring = self.sorted_reach(query_cell_offset)[2];
+
+        for ring in first_ring..=max_ring {
+            for cell_index in self.ring_cells(query_cell_offset, ring) {
+                for (position, point_index) in self.cell_points(cell_index) {
+                    let distance =
+                        metric.distance(query_point, self.exact_position(position, point_index));
+                    if distance <= radius {
+                        visit(point_index, distance);
+                    }
+                }
+            }
+
+            // Any point within the radius would have to be in one of the rings that have
+            // been searched.
+            if self.metric_distance_outside_cells(query_point, query_cell_offset, ring, metric)
+                > radius
+            {
+                break;
+            }
+        }
+    }
+
+    /// Searches the grid outward from the query cell, one ring of cells at a
+    /// time, for points that are closer than the two points found so far.
+    ///
+    /// The search can only stop once the sphere that reaches the second point
+    /// lies within the rings that have been searched, so it usually searches
+    /// one or two more rings than a search for the nearest point alone.
+    fn two_nearest_ring_search(&self, query_point: [f32; 3], two_nearest: &mut TwoNearest) {
+        let query_cell_offset = self.point_into_offset(query_point);
+
+        // Every cell in the grid is in one of the rings from the first ring that
+        // reaches the grid up to the largest reach.
+        let first_ring = self.first_ring(query_cell_offset);
+        let max_ring = self.sorted_reach(query_cell_offset)[2];
+
+        for ring in first_ring..=max_ring {
+            for cell_index in self.ring_cells(query_cell_offset, ring) {
+                for (position, point_index) in self.cell_points(cell_index) {
+                    two_nearest.offer(SearchResult {
+                        point_object_index: point_index,
+                        cell_index1: cell_index,
+                        distance2_to_query: dist2(query_point, position),
+                    });
+                }
+            }
+
+            // Any point closer than the second point would have to be in one of the rings
+            // that have been searched.
+            if let Some(second) = &two_nearest.second {
+                if self.is_sphere_within_cells(
+                    query_point,
+                    query_cell_offset,
+                    ring,
+                    second.distance2_to_query,
+                ) {
+                    break;
+                }
+            }
+        }
+
+        // Stored positions may be approximate, so use the exact positions of the points
+        // that are found.
+        if self.cell_point_positions.is_lossy() {
+            let found = [two_nearest.first.take(), two_nearest.second.take()];
+            for mut sr in found.into_iter().flatten() {
+                let position = self.to_local(self.point_position(sr.point_object_index));
+                sr.distance2_to_query = dist2(query_point, position);
+                two_nearest.offer(sr);
+            }
+        }
+    }
+
+    fn nearest_neighbor_in_query_cell<F>(
+        &self,
+        query_point: [f32; 3],
+        query_cell_offset: Offset3,
+        trace: &mut SearchTrace,
+        filter: &F,
+    ) -> Option<SearchResult>
+    where
+        F: Fn(usize) -> bool,
+    {
+        self.offset_into_index1(query_cell_offset)
+            .filter(|_| trace.scan_cell(query_cell_offset, 0))
+            .filter(|&query_cell_index| self.cell_point_counts[query_cell_index] > 0)
+            .and_then(|query_cell_index| {
+                // The cell isn't empty, but none of its points may be accepted by the filter.
+                let nearest_in_query_cell = nearest(
+                    query_point,
+                    self.cell_points(query_cell_index)
+                        .filter(|&(_, point_index)| filter(point_index)),
+                    query_cell_index,
+                )?;
+
+                if self.is_sphere_within_cells(
+                    query_point,
+                    query_cell_offset,
+                    0,
+                    nearest_in_query_cell.distance2_to_query,
+                ) {
+                    // The neighbor is closer than any of the cell walls, so no need to search in
+                    // other cells.
+                    return Some(nearest_in_query_cell);
+                }
+
+                // Check the neighboring cells for points that might be closer.
+                match self.nearest_in_cell_offsets(
+                    query_point,
+                    query_cell_offset,
+                    neighbor_offsets(),
+                    trace,
+                    filter,
+                ) {
+                    Some(nearest_in_neighbor_cells)
+                        if nearest_in_neighbor_cells.distance2_to_query
+                            < nearest_in_query_cell.distance2_to_query =>
+                    {
+                        Some(nearest_in_neighbor_cells)
+                    }
+                    _ => Some(nearest_in_query_cell),
+                }
+            })
+    }
+
+    /// Searches outward from the query cell using the spiral cells.
+    ///
+    /// If a nearest point has already been found in the query cell or its
+    /// neighbors, it's passed in as `maybe_nearest_so_far`, and the search
+    /// only needs to continue up to the query cell's stop cell.
+    fn nearest_neighbor_spiral_search<F>(
+        &self,
+        query_point: [f32; 3],
+        query_cell_offset: Offset3,
+        mut maybe_nearest_so_far: Option<SearchResult>,
+        trace: &mut SearchTrace,
+        filter: &F,
+    ) -> Option<SearchResult>
+    where
+        F: Fn(usize) -> bool,
+    {
+        // Use the sprial cells to spiral out and check points in each batch of cells
+        // that are equidistanct from the center cell until...
+        // - a first point is found in some cell, and then that cell's stop cell is
+        //   reached
+        // - or all spiral cells are exhausted
+        let mut maybe_stop_cell_index1: Option<usize> = maybe_nearest_so_far
+            .as_ref()
+            .and_then(|_| self.spiral_cells.first())
+            .map(|spiral_cell| spiral_cell.stop_cell_index1);
+
+        let reach = self.sorted_reach(query_cell_offset);
+
+        // Skip the first spiral cell, which is always (0, 0, 0), since that cell is
+        // checked before attempting spiral search.
+        for (spiral_cell_index1, spiral_cell) in self.spiral_cells.iter().enumerate().skip(1) {
+            if trace.is_over_budget {
+                break;
+            }
+
+            // Terminate after the stop cell is checked.
+            if let Some(stop_cell_index1) = maybe_stop_cell_index1 {
+                if spiral_cell_index1 > stop_cell_index1 {
+                    break;
+                }
+            }
+
+            // Near the boundary of the grid, many spiral cells only have variations that
+            // are outside of the grid, so don't bother generating them.
+            if !can_reach(spiral_cell.offset, reach) {
+                continue;
+            }
+
+            // Look for the nearest point in the next batch of cells that are equidistant
+            // from the center cell.
+            let maybe_nearest_in_spiral_cell = self.nearest_in_cell_offsets(
+                query_point,
+                query_cell_offset,
+                spiral_cells::offset_variations(spiral_cell.offset),
+                trace,
+                filter,
+            );
+
+            if let Some(nearest_in_spiral_cell) = maybe_nearest_in_spiral_cell {
+                // A point has been found, so we don't need to search past the stop cell.
+                if maybe_stop_cell_index1.is_none() {
+                    maybe_stop_cell_index1 = Some(spiral_cell.stop_cell_index1);
+                }
+
+                // Check if the point that's found is the new nearest neighbor.
+                let is_new_nearest = match &maybe_nearest_so_far {
+                    None => true,
+                    Some(nearest_so_far) => {
+                        nearest_in_spiral_cell.distance2_to_query
+                            < nearest_so_far.distance2_to_query
+                    }
+                };
+
+                // Update the nearest neighbor.
+                if is_new_nearest {
+                    maybe_nearest_so_far = Some(nearest_in_spiral_cell)
+                }
+            }
+        }
+
+        // If the search ran to the end of the spiral cells and some cells of the grid are
+        // further from the query cell than the spiral cells extend, then a closer point
+        // could be in one of those cells.
+        let ran_to_end = match maybe_stop_cell_index1 {
+            Some(stop_cell_index1) => stop_cell_index1 + 1 >= self.spiral_cells.len(),
+            None => true,
+        };
+        if maybe_nearest_so_far.is_some() && ran_to_end && reach[2] >= self.spiral_width {
+            trace.exact = false;
+        }
+
+        maybe_nearest_so_far
+    }
+
+    fn nearest_neighbor_brute_force<F>(
+        &self,
+        query_point: [f32; 3],
+        trace: &mut SearchTrace,
+        filter: &F,
+    ) -> Option<SearchResult>
+    where
+        F: Fn(usize) -> bool,
+    {
+        let query_cell_offset = self.point_into_offset(query_point);
+        let (x_width, y_width, _) = self.grid_dimensions;
+        (0..self.cell_point_positions.cell_count())
+            .take_while(|&cell_index1| {
+                let cell_offset = Offset3::from_grid_index1(cell_index1, x_width, y_width);
+                trace.scan_cell(cell_offset, rings_between(query_cell_offset, cell_offset))
+            })
+            .filter_map(|cell_index1| {
+                nearest(
+                    query_point,
+                    self.cell_points(cell_index1)
+                        .filter(|&(_, point_index)| filter(point_index)),
+                    cell_index1,
+                )
+            })
+            .min_by(|sr1, sr2| sr1.distance2_to_query.total_cmp(&sr2.distance2_to_query))
+    }
+
+    /// Returns true if the sphere around the query point with the given
+    /// squared radius lies entirely within the block of cells that are at most
+    /// `rings` cells away from the query cell along every axis.
+    ///
+    /// If the sphere lies within the block, then no point outside of the block
+    /// can be closer to the query point than the sphere's radius. Each axis is
+    /// checked in turn so that the check stops as soon as a wall of the block
+    /// is found to be too close.
+    fn is_sphere_within_cells(
+        &self,
+        query_point: [f32; 3],
+        query_cell_offset: Offset3,
+        rings: i64,
+        radius2: f32,
+    ) -> bool {
+        let cell_offset = [
+            query_cell_offset.x,
+            query_cell_offset.y,
+            query_cell_offset.z,
+        ];
+        (0..3).all(|axis| {
+            let block_min = (cell_offset[axis] - rings) as f32 * self.cell_widths[axis];
+            let block_max = (cell_offset[axis] + rings + 1) as f32 * self.cell_widths[axis];
+            let dist_to_wall =
+                min_f32(query_point[axis] - block_min, block_max - query_point[axis]);
+            dist_to_wall >= 0.0 && dist_to_wall * dist_to_wall > radius2
+        })
+    }
+
+    /// Returns the smallest distance under the metric from the query point to
+    /// any point outside of the block of cells that are at most `rings` cells
+    /// away from the query cell along every axis.
+    ///
+    /// A point outside of the block is at least as far from the query point,
+    /// along some axis, as the nearest wall of the block on that axis, so it's
+    /// at least as far under the metric as that wall is along that axis.
+    fn metric_distance_outside_cells<M>(
+        &self,
+        query_point: [f32; 3],
+        query_cell_offset: Offset3,
+        rings: i64,
+        metric: &M,
+    ) -> f32
+    where
+        M: Metric,
+    {
+        let cell_offset = [
+            query_cell_offset.x,
+            query_cell_offset.y,
+            query_cell_offset.z,
+        ];
+        (0..3)
+            .map(|axis| {
+                let block_min = (cell_offset[axis] - rings) as f32 * self.cell_widths[axis];
+                let block_max = (cell_offset[axis] + rings + 1) as f32 * self.cell_widths[axis];
+                let dist_to_wall =
+                    min_f32(query_point[axis] - block_min, block_max - query_point[axis]);
+                metric.axis_distance(axis, max_f32(dist_to_wall, 0.0))
+            })
+            .fold(f32::INFINITY, min_f32)
+    }
+
+    /// Returns the indices of the non-empty cells that overlap the sphere with
+    /// the given center and squared radius.
+    fn cells_overlapping_sphere(
+        &self,
+        center: [f32; 3],
+        radius2: f32,
+    ) -> impl Iterator<Item = usize> + '_ {
+        let radius = radius2.sqrt();
+        let min_offset =
+            self.point_into_offset([center[0] - radius, center[1] - radius, center[2] - radius]);
+        let max_offset =
+            self.point_into_offset([center[0] + radius, center[1] + radius, center[2] + radius]);
+
+        // Clamp the range of offsets covered by the sphere's bounding box to the
+        // cells that actually exist.
+        let (x_width, y_width, z_width) = self.grid_dimensions;
+        let x_range = min_offset.x.max(0)..=max_offset.x.min(x_width as i64 - 1);
+        let y_range = min_offset.y.max(0)..=max_offset.y.min(y_width as i64 - 1);
+        let z_range = min_offset.z.max(0)..=max_offset.z.min(z_width as i64 - 1);
+
+        z_range
+            .flat_map(move |z| {
+                let x_range = x_range.clone();
+                y_range
+                    .clone()
+                    .flat_map(move |y| x_range.clone().map(move |x| Offset3::new(x, y, z)))
+            })
+            .filter(move |&offset| self.cell_dist2(center, offset) <= radius2)
+            .filter_map(|offset| self.offset_into_index1(offset))
+            .filter(|&cell_index| self.cell_point_counts[cell_index] > 0)
+    }
+
+    /// Returns the centroid of the positions of the points in the cell.
+    ///
+    /// Positions with a NaN coordinate are left out. If the cell has no other
+    /// points, then the centroid's coordinates are NaN.
+    fn cell_centroid(&self, cell_index: usize) -> [f32; 3] {
+        let mut sum = [0.0; 3];
+        let mut count = 0;
+        for (position, _) in self.cell_points(cell_index) {
+            if position.iter().any(|c| c.is_nan()) {
+                continue;
+            }
+            for axis in 0..3 {
+                sum[axis] += position[axis];
+            }
+            count += 1;
+        }
+        sum.map(|s| s / count as f32)
+    }
+
+    /// Returns the indices of the non-empty cells that are exactly `ring` cells
+    /// away from the center cell along at least one axis.
+    fn ring_cells(
+        &self,
+        center_cell_offset: Offset3,
+        ring: i64,
+    ) -> impl Iterator<Item = usize> + '_ {
+        self.ring_cell_offsets(center_cell_offset, ring)
+            .filter_map(|offset| self.offset_into_index1(offset))
+            .filter(|&cell_index| self.cell_point_counts[cell_index] > 0)
+    }
+
+    /// Returns the offsets of the cells inside the uniform grid that are
+    /// exactly `ring` cells away from the center cell along at least one axis,
+    /// including empty cells.
+    fn ring_cell_offsets(
+        &self,
+        center_cell_offset: Offset3,
+        ring: i64,
+    ) -> impl Iterator<Item = Offset3> + '_ {
+        let Offset3 { x, y, z } = center_cell_offset;
+        let (x_width, y_width, z_width) = self.grid_dimensions;
+
+        // Clamp the range of offsets covered by the ring to the cells that actually
+        // exist.
+        let clamped = |center: i64, width: usize| {
+            (center - ring).max(0)..=(center + ring).min(width as i64 - 1)
+        };
+        let x_range = clamped(x, x_width);
+        let y_range = clamped(y, y_width);
+        let z_range = clamped(z, z_width);
+
+        z_range
+            .flat_map(move |cz| {
+                let x_range = x_range.clone();
+                y_range.clone().flat_map(move |cy| {
+                    // On the faces of the ring that are perpendicular to the y and z axes,
+                    // every cell along the x axis is in the ring. Elsewhere, only the cells
+                    // at each end are.
+                    let on_face = (cz - z).abs() == ring || (cy - y).abs() == ring;
+                    let xs = if on_face {
+                        Either::Left(x_range.clone())
+                    } else {
+                        Either::Right([x - ring, x + ring].into_iter().dedup())
+                    };
+                    xs.map(move |cx| Offset3::new(cx, cy, cz))
+                })
+            })
+            .filter(|&offset| self.offset_into_index1(offset).is_some())
+    }
+
+    /// Returns true if the point would be bucketed into the cell, using the
+    /// same boundary rule as `point_into_offset`.
+    fn is_point_in_cell(&self, point: [f32; 3], cell_offset: Offset3) -> bool {
+        self.point_into_offset(point) == cell_offset
+    }
+
+    /// Returns the squared distance between the point and the closest point
+    /// in the space covered by the cell.
+    ///
+    /// The 3-dimensional offset, `cell_offset`, is relative to the uniform
+    /// grid's "origin cell" at `(0, 0, 0)`. This returns `0.0` if the point
+    /// is inside the cell.
+    fn cell_dist2(&self, point: [f32; 3], cell_offset: Offset3) -> f32 {
+        let cell_offset = [cell_offset.x, cell_offset.y, cell_offset.z];
+        (0..3)
+            .map(|axis| {
+                let cell_min = cell_offset[axis] as f32 * self.cell_widths[axis];
+                let cell_max = cell_min + self.cell_widths[axis];
+                let d = max_f32(0.0, max_f32(cell_min - point[axis], point[axis] - cell_max));
+                d * d
+            })
+            .sum()
+    }
+
+    /// Returns the positions and point indices of the points in a cell.
+    fn cell_points(&self, cell_index: usize) -> impl Iterator<Item = ([f32; 3], usize)> + '_ {
+        self.cell_point_positions.cell(
+            cell_index,
+            move || self.cell_min(cell_index),
+            move |point_index| self.bucketed_position(point_index),
+        )
+    }
+
+    /// Returns the position at which the point with the given index is
+    /// bucketed, relative to the minimum corner of the uniform grid.
+    fn bucketed_position(&self, point_index: usize) -> [f32; 3] {
+        let position = self.point_position(point_index);
+        let position = match self.options.periodic_box {
+            Some(periodic_box) => wrap_into_box(position, periodic_box),
+            None => position,
+        };
+        self.to_local(position)
+    }
+
+    /// Returns the index of the cell that the point with the given index is
+    /// bucketed into.
+    fn point_cell_index(&self, point_index: usize) -> usize {
+        point_into_index1(
+            self.bucketed_position(point_index),
+            self.cell_widths,
+            self.grid_dimensions,
+            self.options.bucket_boundary,
+        )
+        .expect("every point is bucketed into a cell")
+    }
+
+    /// Returns the exact position of a point in a cell, relative to the minimum
+    /// corner of the uniform grid, given the position that's stored for it.
+    ///
+    /// Stored positions may be approximate, in which case the exact position
+    /// is read from the grid's array of positions.
+    fn exact_position(&self, stored_position: [f32; 3], point_index: usize) -> [f32; 3] {
+        if self.cell_point_positions.is_lossy() {
+            self.to_local(self.point_position(point_index))
+        } else {
+            stored_position
+        }
+    }
+
+    /// Returns the position of the point at the given index, with its axes in
+    /// the grid's order.
+    fn point_position(&self, point_index: usize) -> [f32; 3] {
+        self.options.grid_position(self.positions[point_index])
+    }
+
+    /// Returns the position of a point relative to the minimum corner of the
+    /// uniform grid, which is how positions are stored and searched.
+    fn to_local(&self, point: [f32; 3]) -> [f32; 3] {
+        to_local(point, self.min_position)
+    }
+
+    /// Returns the periodic box, if the grid is periodic, relative to the
+    /// minimum corner of the uniform grid.
+    fn local_periodic_box(&self) -> Option<([f32; 3], [f32; 3])> {
+        self.options
+            .periodic_box
+            .map(|(box_min, box_max)| (self.to_local(box_min), self.to_local(box_max)))
+    }
+
+    /// Returns the minimum corner of the space covered by a cell, relative to
+    /// the minimum corner of the uniform grid.
+    fn cell_min(&self, cell_index: usize) -> [f32; 3] {
+        cell_min(cell_index, self.cell_widths, self.grid_dimensions)
+    }
+
+    /// Returns, for each axis, the largest absolute offset from the query cell
+    /// that can reach a cell inside the uniform grid, sorted in ascending
+    /// order.
+    ///
+    /// A grid without points has nothing to reach, so its reach is -1 along
+    /// every axis. Its bounding box is empty, so its query cell offsets are
+    /// meaningless, and can be as large as `MAX_CELL_OFFSET`.
+    fn sorted_reach(&self, query_cell_offset: Offset3) -> [i64; 3] {
+        if self.point_objs.is_empty() {
+            return [-1; 3];
+        }
+        let (x_width, y_width, z_width) = self.grid_dimensions;
+        let axis_reach = |offset: i64, width: usize| {
+            offset
+                .saturating_abs()
+                .max((width as i64 - 1).saturating_sub(offset).saturating_abs())
+        };
+        let mut reach = [
+            axis_reach(query_cell_offset.x, x_width),
+            axis_reach(query_cell_offset.y, y_width),
+            axis_reach(query_cell_offset.z, z_width),
+        ];
+        reach.sort_unstable();
+        reach
+    }
+
+    /// Returns the 3-dimensional offset of the cell that the point is bucketed
+    /// into when it's added to the grid.
+    ///
+    /// Unlike `point_into_offset`, this keeps a point on one of the maximum
+    /// faces of the uniform grid in the last cell along that axis.
+    fn bucket_into_offset(&self, point: [f32; 3]) -> Offset3 {
+        bucket_offset(
+            point,
+            self.cell_widths,
+            self.grid_dimensions,
+            self.options.bucket_boundary,
+        )
+    }
+
+    /// Returns the first ring around the query cell that reaches a cell inside
+    /// the uniform grid, which is `0` if the query cell is inside the grid.
+    ///
+    /// The rings closer to the query cell than this are outside of the grid,
+    /// so they're empty. A query cell far outside of the grid would otherwise
+    /// make a search walk through a huge number of them.
+    fn first_ring(&self, query_cell_offset: Offset3) -> i64 {
+        let (x_width, y_width, z_width) = self.grid_dimensions;
+        let axis_gap = |offset: i64, width: usize| {
+            (-offset)
+                .max(offset.saturating_sub(width as i64 - 1))
+                .max(0)
+        };
+        axis_gap(query_cell_offset.x, x_width)
+            .max(axis_gap(query_cell_offset.y, y_width))
+            .max(axis_gap(query_cell_offset.z, z_width))
+    }
+
+    /// Returns the 3-dimensional offset of the cell in which the point would be
+    /// bucketed.
+    ///
+    /// The 3-dimensional offset is relative to the uniform grid's "origin cell"
+    /// at `(0, 0, 0)`. The uniform grid has a finite width in each
+    /// dimension, so the offset may refer to a "cell" that doesn't actually
+    /// exist. This will happen if the given point lies outside the region
+    /// of space that is covered by the uniform grid.
+    fn point_into_offset(&self, point: [f32; 3]) -> Offset3 {
+        point_into_offset(point, self.cell_widths, self.options.bucket_boundary)
+    }
+
+    /// Converts the 3-dimensional offset of a cell in the uniform grid into an
+    /// index into the 1-dimensional vector that stores the cells of the uniform
+    /// grid.
+    ///
+    /// The 3-dimensional offset is relative to the uniform grid's "origin cell"
+    /// at `(0, 0, 0)`.
+    ///
+    /// This returns `None` if the offset refers to a "cell" that doesn't exist
+    /// because it is outside the finite bounds of the uniform grid.
+    fn offset_into_index1(&self, offset_from_origin: Offset3) -> Option<usize> {
+        offset_from_origin.into_grid_index1(self.grid_dimensions)
+    }
+
+    /// Converts an index into the 1-dimensional vector that stores the cells
+    /// of the uniform grid into the 3-dimensional offset of the cell.
+    ///
+    /// The 3-dimensional offset is relative to the uniform grid's "origin cell"
+    /// at `(0, 0, 0)`.
+    fn index1_into_offset(&self, index1: usize) -> Offset3 {
+        Offset3::from_grid_index1(index1, self.grid_dimensions.0, self.grid_dimensions.1)
+    }
+
+    /// Checks each of the cells that are identified by the offsets from the
+    /// center cell, and return the point in those cells that is nearest to the
+    /// query point.
+    ///
+    /// Points whose indices aren't accepted by `filter` are skipped.
+    fn nearest_in_cell_offsets<F>(
+        &self,
+        query_point: [f32; 3],
+        center_cell_offset: Offset3,
+        cell_offsets: Vec<Offset3>,
+        trace: &mut SearchTrace,
+        filter: &F,
+    ) -> Option<SearchResult>
+    where
+        F: Fn(usize) -> bool,
+    {
+        let mut min_point: Option<SearchResult> = None;
+        for o in cell_offsets {
+            if let Some(cell_idx) = self.offset_into_index1(center_cell_offset + o) {
+                if !trace.scan_cell(
+                    center_cell_offset + o,
+                    rings_between(Offset3::new(0, 0, 0), o),
+                ) {
+                    break;
+                }
+                let count = &self.cell_point_counts[cell_idx];
+                if *count > 0 {
+                    for (pos, pt_idx) in self.cell_points(cell_idx) {
+                        let d2 = dist2(query_point, pos);
+                        if !filter(pt_idx) || d2.is_nan() {
+                            continue;
+                        }
+                        if let Some(sr) = &min_point {
+                            if d2 < sr.distance2_to_query {
+                                min_point = Some(SearchResult {
+                                    point_object_index: pt_idx,
+                                    cell_index1: cell_idx,
+                                    distance2_to_query: d2,
+                                })
+                            }
+                        } else {
+                            min_point = Some(SearchResult {
+                                point_object_index: pt_idx,
+                                cell_index1: cell_idx,
+                                distance2_to_query: d2,
+                            })
+                        }
+                    }
+                }
+            }
+        }
+        min_point
+
+        // let points = cell_offsets
+        //     .iter()
+        //     .filter_map(|o| self.offset_into_index1(center_cell_offset + o))
+        //     .filter(|&i| self.cell_point_counts[i] > 0)
+        //     .flat_map(|i| &self.cell_point_positions[i]);
+        // nearest(query_point, points)
+    }
+}
+
+impl UniformGrid<[f32; 3]> {
+    /// Constructs a uniform grid of bare positions, whose cells don't keep
+    /// copies of the positions.
+    ///
+    /// Since the points are their positions, the copies that cells normally
+    /// keep would store every position twice. See
+    /// `UniformGridBuilder::cache_positions` for the trade-off.
+    pub fn new_positions_only(
+        positions: Vec<[f32; 3]>,
+        scale: f32,
+        spiral_cells: Vec<SpiralCell>,
+    ) -> Self {
+        let options = GridOptions {
+            scale,
+            cache_positions: false,
+            ..GridOptions::default()
+        };
+        Self::from_options(positions, options, spiral_cells)
+    }
+
+    /// Constructs a uniform grid from a flat buffer of interleaved
+    /// coordinates, such as an array shared from Python or read back from a
+    /// GPU.
+    ///
+    /// The buffer holds the x, y, and z coordinates of the first position,
+    /// followed by those of the second position, and so on. The positions are
+    /// copied into the grid, so the caller doesn't need to collect them into
+    /// a `Vec<[f32; 3]>` first.
+    pub fn from_interleaved(
+        buffer: &[f32],
+        scale: f32,
+        spiral_cells: Vec<SpiralCell>,
+    ) -> Result<Self, BufferError> {
+        if !buffer.len().is_multiple_of(3) {
+            return Err(BufferError::Length(buffer.len()));
+        }
+        let positions = buffer
+            .chunks_exact(3)
+            .map(|xyz| [xyz[0], xyz[1], xyz[2]])
+            .collect();
+        let options = GridOptions {
+            scale,
+            ..GridOptions::default()
+        };
+        Self::try_from_options(positions, options, spiral_cells).map_err(BufferError::TooLarge)
+    }
+
+    /// Finds the position in the uniform grid that is closest to the given
+    /// query point.
+    ///
+    /// Returns the index of the position in `points()`, the position itself,
+    /// and its distance to the query point.
+    pub fn nearest_position(&self, query_point: [f32; 3]) -> Option<(usize, [f32; 3], Distance)> {
+        let mut trace = SearchTrace::default();
+        self.nearest_neighbor_search(query_point, &mut trace, &|_| true)
+            .map(|sr| {
+                (
+                    sr.point_object_index,
+                    self.point_objs[sr.point_object_index],
+                    Distance::from_squared(sr.distance2_to_query),
+                )
+            })
+    }
+
+    /// Finds all positions in the uniform grid that are within the given
+    /// radius of the query point.
+    ///
+    /// Returns the index of each position in `points()`, the position itself,
+    /// and its distance to the query point. The positions are not
+    /// returned in any particular order. A negative radius finds nothing.
+    pub fn positions_within_radius(
+        &self,
+        query_point: [f32; 3],
+        radius: f32,
+    ) -> Vec<(usize, [f32; 3], Distance)> {
+        self.neighbor_indices_within_radius2(query_point, squared_radius(radius))
+            .into_iter()
+            .map(|(point_index, distance2)| {
+                (
+                    point_index,
+                    self.point_objs[point_index],
+                    Distance::from_squared(distance2),
+                )
+            })
+            .collect()
+    }
+}
+
+#[cfg(feature = "rayon")]
+impl<T> UniformGrid<T>
+where
+    T: PointObject + Sync,
+{
+    /// Constructs a uniform grid like `new`, but finds the bounding box of the
+    /// points, counts the points in each cell, and fills the cells in
+    /// parallel.
+    ///
+    /// The grid is the same as the one that `new` constructs from the same
+    /// points, with the points of each cell in the same order, so queries
+    /// return the same results. Panics if the grid would have too many cells;
+    /// `UniformGridBuilder::build_parallel` constructs a grid with other
+    /// options in parallel.
+    pub fn new_parallel(points: Vec<T>, scale: f32, spiral_cells: Vec<SpiralCell>) -> Self {
+        let options = GridOptions {
+            scale,
+            ..GridOptions::default()
+        };
+        Self::try_from_options_with(points, options, spiral_cells, &ParallelPasses)
+            .unwrap_or_else(|error| panic!("{}", error))
+    }
+
+    /// Finds the point in the uniform grid that is nearest to each of the query
+    /// points, like `nearest_neighbors_batch`, but searches the query points
+    /// in parallel.
+    pub fn nearest_neighbors_batch_parallel(
+        &self,
+        query_points: &[[f32; 3]],
+    ) -> Vec<Option<(&T, Distance)>> {
+        use rayon::prelude::*;
+
+        query_points
+            .par_iter()
+            .map(|&query_point| self.nearest_neighbor(query_point))
+            .collect()
+    }
+
+    /// Finds all points in the uniform grid that are within the given radius of
+    /// each of the query points, like `neighbors_within_radius_batch`, but
+    /// searches the query points in parallel.
+    pub fn neighbors_within_radius_batch_parallel(
+        &self,
+        query_points: &[[f32; 3]],
+        radius: f32,
+    ) -> Vec<Vec<(usize, Distance)>> {
+        use rayon::prelude::*;
+
+        query_points
+            .par_iter()
+            .map(|&query_point| {
+                with_distances(
+                    self.neighbor_indices_within_radius2(query_point, squared_radius(radius)),
+                )
+            })
+            .collect()
+    }
+
+    /// Finds, for each point in the uniform grid, the `k` other points that
+    /// are nearest to it, like `knn_graph`, but searches the points in
+    /// parallel.
+    pub fn knn_graph_parallel(&self, k: usize) -> Vec<Vec<(usize, Distance)>> {
+        use rayon::prelude::*;
+
+        let point_indices = self.point_indices_in_cell_order().collect_vec();
+        let neighbors: Vec<_> = point_indices
+            .par_iter()
+            .map(|&point_index| self.k_nearest_to_point(point_index, k))
+            .collect();
+        let mut graph = vec![Vec::new(); self.point_objs.len()];
+        for (point_index, neighbors) in point_indices.into_iter().zip(neighbors) {
+            graph[point_index] = neighbors;
+        }
+        graph
+    }
+}
+
+/// The largest number of cells that a uniform grid can have.
+///
+/// The cells alone would take tens of gigabytes at this size, before any points
+/// are stored, so a grid with more cells is almost certainly the result of a
+/// scale or a minimum number of cells that's far too large.
+const MAX_CELL_COUNT: usize = 1 << 31;
+
+/// The largest offset, along any axis, of a cell that a point is bucketed
+/// into. It's far larger than the width of any grid, which is limited by
+/// `MAX_CELL_COUNT`.
+const MAX_CELL_OFFSET: i64 = 1 << 40;
+
+/// Returns the number of cells in a grid with the given dimensions, or `None`
+/// if the grid would have more than `MAX_CELL_COUNT` cells.
+fn checked_cell_count((x_width, y_width, z_width): (usize, usize, usize)) -> Option<usize> {
+    x_width
+        .checked_mul(y_width)
+        .and_then(|count| count.checked_mul(z_width))
+        .filter(|&count| count <= MAX_CELL_COUNT)
+}
+
+/// Error returned when a uniform grid can't be constructed because it would
+/// have too many cells.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GridTooLarge {
+    /// The number of cells along each dimension of the grid that was
+    /// requested.
+    pub dimensions: (usize, usize, usize),
+}
+
+impl fmt::Display for GridTooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (x_width, y_width, z_width) = self.dimensions;
+        write!(
+            f,
+            "a grid of {} by {} by {} cells has more than the maximum of {} cells",
+            x_width, y_width, z_width, MAX_CELL_COUNT
+        )
+    }
+}
+
+impl std::error::Error for GridTooLarge {}
+
+/// Error returned when a uniform grid can't be constructed from a flat buffer
+/// of coordinates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BufferError {
+    /// The length of the buffer, which isn't a multiple of 3.
+    Length(usize),
+    /// The grid would have too many cells.
+    TooLarge(GridTooLarge),
+}
+
+impl fmt::Display for BufferError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BufferError::Length(len) => write!(
+                f,
+                "a buffer of {} coordinates doesn't hold a whole number of positions",
+                len
+            ),
+            BufferError::TooLarge(error) => error.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for BufferError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            BufferError::Length(_) => None,
+            BufferError::TooLarge(error) => Some(error),
+        }
+    }
+}
+
+/// A phase of the construction of a uniform grid, as reported by
+/// `UniformGrid::new_with_progress`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BuildPhase {
+    /// Computing the bounding box of the points.
+    BoundingBox,
+
+    /// Laying out the cells of the grid and counting the points that are
+    /// bucketed into each cell.
+    Counting,
+
+    /// Allocating the storage for the points in each cell.
+    Allocation,
+
+    /// Bucketing the points into their cells.
+    Fill,
+}
+
+/// Where a point would be bucketed in a uniform grid, as returned by
+/// `UniformGrid::debug_locate`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CellLocation {
+    /// The offset of the cell from the grid's "origin cell" at `(0, 0, 0)`,
+    /// which may refer to a cell outside of the grid.
+    pub offset: Offset3,
+
+    /// The index of the cell in the grid's flat vector of cells, or `None` if
+    /// the offset is outside of the grid.
+    pub index: Option<usize>,
+
+    /// Whether the cell is inside the grid.
+    pub in_bounds: bool,
+}
+
+/// The parts of a uniform grid that are returned by
+/// `UniformGrid::into_parts`.
+#[derive(Debug, Clone)]
+pub struct GridParts<T> {
+    /// The points, in the same order as they were in the grid.
+    pub points: Vec<T>,
+
+    /// The spiral cells that the grid used to search outward from a query
+    /// cell.
+    pub spiral_cells: Vec<SpiralCell>,
+
+    /// The minimum corner of the grid's "origin cell" at `(0, 0, 0)`.
+    pub min_position: [f32; 3],
+
+    /// The width of each of the grid's cells along each axis.
+    pub cell_widths: [f32; 3],
+
+    /// The number of cells along each dimension of the grid.
+    pub dimensions: (usize, usize, usize),
+}
+
+/// Remembers the cell of the previous query that was passed to
+/// `UniformGrid::nearest_neighbor_cached`.
+///
+/// A hint should only be used with the grid that it was first used with.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct QueryHint {
+    last_cell_offset: Option<Offset3>,
+}
+
+impl QueryHint {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+/// The result of a nearest-neighbor query, along with details about how the
+/// search went.
+#[derive(Debug, Clone, Copy)]
+pub struct TracedNeighbor<'a, T> {
+    /// The point that was found.
+    pub point: &'a T,
+
+    /// The distance between the found point and the query point.
+    pub distance: Distance,
+
+    /// Whether the found point is guaranteed to be the nearest neighbor.
+    ///
+    /// This is `false` if the spiral cells don't extend far enough to reach
+    /// every cell of the grid from the query cell, or if positions are stored
+    /// at reduced precision.
+    pub exact: bool,
+
+    /// The number of rings of cells around the query cell that the search
+    /// reached, where a cell is in the `n`th ring if it's `n` cells away from
+    /// the query cell along at least one axis.
+    ///
+    /// A search that only examines the query cell reaches 0 rings. Values that
+    /// are consistently 0 or 1 mean that the cells are about the right size
+    /// for the points, and larger values mean that they're too small.
+    pub rings_searched: usize,
+}
+
+/// Details about how a search went, which are accumulated as the search
+/// progresses.
+struct SearchTrace {
+    exact: bool,
+
+    /// The number of cells inside the grid that were examined, including
+    /// empty cells.
+    cells_scanned: usize,
+
+    /// The maximum number of cells that the search may examine.
+    cell_budget: usize,
+
+    /// Whether the search stopped early because it used up its cell budget.
+    is_over_budget: bool,
+
+    /// The largest number of rings between the query cell and a cell that
+    /// was examined.
+    rings_searched: usize,
+
+    /// The offsets of the cells that were examined, in the order they were
+    /// examined, if they're being recorded.
+    visited_cells: Option<Vec<Offset3>>,
+}
+
+impl SearchTrace {
+    /// Counts a cell that's about to be examined, which has the given offset
+    /// and is the given number of rings away from the query cell. Returns
+    /// false, without counting the cell, if the cell budget has been used up,
+    /// in which case the cell must not be examined.
+    fn scan_cell(&mut self, cell_offset: Offset3, ring: usize) -> bool {
+        if self.cells_scanned >= self.cell_budget {
+            self.is_over_budget = true;
+            return false;
+        }
+        self.cells_scanned += 1;
+        self.rings_searched = self.rings_searched.max(ring);
+        if let Some(visited_cells) = &mut self.visited_cells {
+            visited_cells.push(cell_offset);
+        }
+        true
+    }
+}
+
+impl Default for SearchTrace {
+    fn default() -> Self {
+        Self {
+            exact: true,
+            cells_scanned: 0,
+            cell_budget: usize::MAX,
+            is_over_budget: false,
+            rings_searched: 0,
+            visited_cells: None,
+        }
+    }
+}
+
+struct SearchResult {
+    pub point_object_index: usize,
+    /// Index of the cell that contains the point.
+    pub cell_index1: usize,
+    pub distance2_to_query: f32,
+}
+
+/// The two points that are closest to a query point, out of the points that
+/// have been offered so far.
+#[derive(Default)]
+struct TwoNearest {
+    first: Option<SearchResult>,
+    second: Option<SearchResult>,
+}
+
+impl TwoNearest {
+    /// Keeps the point if it's closer than either of the two points so far.
+    ///
+    /// The same point may be offered more than once, such as from different
+    /// periodic images of the query point, in which case only its smallest
+    /// distance is kept.
+    fn offer(&mut self, candidate: SearchResult) {
+        // A point whose distance is NaN is never one of the nearest points.
+        if candidate.distance2_to_query.is_nan() {
+            return;
+        }
+
+        let is_closer = |other: &Option<SearchResult>| match other {
+            None => true,
+            Some(other) => {
+                (candidate.distance2_to_query, candidate.point_object_index)
+                    < (other.distance2_to_query, other.point_object_index)
+            }
+        };
+        let is_first = matches!(
+            &self.first,
+            Some(first) if first.point_object_index == candidate.point_object_index
+        );
+
+        if is_first {
+            if is_closer(&self.first) {
+                self.first = Some(candidate);
+            }
+        } else if is_closer(&self.first) {
+            // The old first point becomes the second point, which also replaces the
+            // candidate's own distance if it was the second point.
+            self.second = self.first.replace(candidate);
+        } else if is_closer(&self.second) {
+            self.second = Some(candidate);
+        }
+    }
+}
+
+fn neighbor_offsets() -> Vec<Offset3> {
+    vec![
+        Offset3::new(-1, -1, -1),
+        Offset3::new(0, -1, -1),
+        Offset3::new(1, -1, -1),
+        Offset3::new(-1, 0, -1),
+        Offset3::new(0, 0, -1),
+        Offset3::new(1, 0, -1),
+        Offset3::new(-1, 1, -1),
+        Offset3::new(0, 1, -1),
+        Offset3::new(1, 1, -1),
+        Offset3::new(-1, -1, 0),
+        Offset3::new(0, -1, 0),
+        Offset3::new(1, -1, 0),
+        Offset3::new(-1, 0, 0),
+        Offset3::new(1, 0, 0),
+        Offset3::new(-1, 1, 0),
+        Offset3::new(0, 1, 0),
+        Offset3::new(1, 1, 0),
+        Offset3::new(-1, -1, 1),
+        Offset3::new(0, -1, 1),
+        Offset3::new(1, -1, 1),
+        Offset3::new(-1, 0, 1),
+        Offset3::new(0, 0, 1),
+        Offset3::new(1, 0, 1),
+        Offset3::new(-1, 1, 1),
+        Offset3::new(0, 1, 1),
+        Offset3::new(1, 1, 1),
+    ]
+}
+
+/// Returns the width of cube-shaped cells such that a grid covering the given
+/// extents contains roughly `cell_count` cells.
+///
+/// An axis whose extent is smaller than the cell width only needs a single
+/// cell, so it doesn't contribute to the cell count. Such axes are excluded,
+/// smallest first, until the cell width is no larger than the smallest
+/// remaining extent.
+fn cell_width_for_cell_count(extents: [f32; 3], cell_count: f32) -> f32 {
+    let mut extents = extents.into_iter().filter(|&e| e > 0.0).collect_vec();
+    extents.sort_by(|e1, e2| e1.partial_cmp(e2).unwrap());
+
+    for i in 0..extents.len() {
+        let remaining = &extents[i..];
+        let volume: f32 = remaining.iter().product();
+        let cell_width = (volume / cell_count).powf(1.0 / remaining.len() as f32);
+        if cell_width <= remaining[0] {
+            return cell_width;
+        }
+    }
+
+    // Every axis fits within a single cell.
+    extents.last().copied().unwrap_or(1.0)
+}
+
+/// Returns a cell width for which the mean number of points in each non-empty
+/// cell is close to `target_occupancy`.
+///
+/// The mean occupancy grows as cells get wider, so the cell width is found
+/// with a bisection search between a width that puts every point in a single
+/// cell and a width that gives the grid about eight cells for each point. The
+/// grid never gets more cells than that, however low the target is.
+fn cell_width_for_occupancy(
+    positions: &[[f32; 3]],
+    extents: [f32; 3],
+    target_occupancy: f32,
+    boundary: BucketBoundary,
+) -> f32 {
+    let widest = extents.into_iter().fold(0.0, max_f32);
+    if positions.is_empty() || widest <= 0.0 {
+        return cell_width_for_cell_count(extents, 1.0);
+    }
+
+    let mean_occupancy = |cell_width: f32| {
+        let mut offsets = positions
+            .iter()
+            .map(|&position| point_into_offset(position, [cell_width; 3], boundary))
+            .map(|offset| (offset.x, offset.y, offset.z))
+            .collect_vec();
+        offsets.sort_unstable();
+        offsets.dedup();
+        positions.len() as f32 / offsets.len() as f32
+    };
+
+    let mut narrow = cell_width_for_cell_count(extents, 8.0 * positions.len() as f32);
+    let mut wide = widest;
+    for _ in 0..24 {
+        let cell_width = (narrow * wide).sqrt();
+        if mean_occupancy(cell_width) < target_occupancy {
+            narrow = cell_width;
+        } else {
+            wide = cell_width;
+        }
+    }
+    wide
+}
+
+/// The target occupancy that `UniformGrid::new_auto_scale` sizes cells for.
+const AUTO_SCALE_OCCUPANCY: f32 = 1.5;
+
+/// The tolerance, in radians, within which a point is considered to be inside
+/// a cone even though its direction is outside the cone's half angle.
+///
+/// This lets a cone with a half angle of `0.0` select points on its axis
+/// despite rounding in the computed angles.
+const CONE_ANGLE_EPSILON: f32 = 1e-6;
+
+/// Returns the angle, in radians, between the two vectors, which must not be
+/// zero.
+fn angle_between(v1: [f32; 3], v2: [f32; 3]) -> f32 {
+    let dot = v1[0] * v2[0] + v1[1] * v2[1] + v1[2] * v2[2];
+    let cross = [
+        v1[1] * v2[2] - v1[2] * v2[1],
+        v1[2] * v2[0] - v1[0] * v2[2],
+        v1[0] * v2[1] - v1[1] * v2[0],
+    ];
+    let cross_length = (cross[0] * cross[0] + cross[1] * cross[1] + cross[2] * cross[2]).sqrt();
+    cross_length.atan2(dot)
+}
+
+/// The relative tolerance within which the widths that are used to lay out a
+/// grid are treated as equal.
+///
+/// This keeps the layout of a grid from depending on float noise in the
+/// bounding box. Snapping widths to within the tolerance can leave points on a
+/// maximum face of the bounding box just past the last cell, so those points
+/// are clamped into the grid when they're bucketed.
+const WIDTH_EPSILON: f32 = 1e-5;
+
+/// Returns the number of cells of the given width that are needed to cover
+/// the extent along a single axis.
+///
+/// An extent that's within `WIDTH_EPSILON` of a whole number of cells gets
+/// that number of cells, rather than an extra cell that's almost entirely
+/// outside the extent.
+fn axis_cell_count(extent: f32, cell_width: f32) -> usize {
+    (extent / cell_width * (1.0 - WIDTH_EPSILON))
+        .ceil()
+        .max(1.0) as usize
+}
+
+/// Returns the extents with each extent that's within `WIDTH_EPSILON` of a
+/// larger extent replaced by the larger extent.
+fn equalize_nearly_equal_extents(extents: [f32; 3]) -> [f32; 3] {
+    extents.map(|extent| {
+        extents
+            .into_iter()
+            .filter(|&other| other >= extent && other - extent <= WIDTH_EPSILON * other)
+            .fold(extent, max_f32)
+    })
+}
+
+/// Returns true if some variation of the spiral cell's offset, as generated
+/// by `spiral_cells::offset_variations`, can reach a cell inside the grid.
+///
+/// Each variation's components are a permutation of the absolute values of
+/// the spiral cell's components, so some variation fits within the reach of
+/// each axis only if the sorted components fit within the sorted reach.
+fn can_reach(spiral_cell_offset: Offset3, sorted_reach: [i64; 3]) -> bool {
+    let mut components = [
+        spiral_cell_offset.x.abs(),
+        spiral_cell_offset.y.abs(),
+        spiral_cell_offset.z.abs(),
+    ];
+    components.sort_unstable();
+    components
+        .iter()
+        .zip(sorted_reach.iter())
+        .all(|(component, reach)| component <= reach)
+}
+
+/// Returns the position at which a point at the given position is bucketed,
+/// relative to the minimum corner of the grid.
+///
+/// The position's axes are put in the grid's order, and points in a periodic
+/// grid are wrapped into the periodic box.
+fn bucketed_position(
+    position: [f32; 3],
+    options: &GridOptions,
+    min_position: [f32; 3],
+) -> [f32; 3] {
+    let position = options.grid_position(position);
+    let position = match options.periodic_box {
+        Some(periodic_box) => wrap_into_box(position, periodic_box),
+        None => position,
+    };
+    to_local(position, min_position)
+}
+
+/// Where the cells of a uniform grid lie, which decides the cell that each
+/// point is bucketed into.
+pub(crate) struct CellLayout {
+    /// The minimum position in space that is covered by the uniform grid.
+    min_position: [f32; 3],
+
+    /// The width in space that is covered by each cell along each axis.
+    cell_widths: [f32; 3],
+
+    /// The number of cells in each dimension of the uniform grid.
+    grid_dimensions: (usize, usize, usize),
+
+    /// The total number of cells in the uniform grid.
+    cell_count: usize,
+}
+
+impl CellLayout {
+    /// Returns the position at which a point at the given position is
+    /// bucketed, relative to the minimum corner of the grid, and the index of
+    /// the cell that it's bucketed into.
+    fn bucket(&self, position: [f32; 3], options: &GridOptions) -> ([f32; 3], usize) {
+        let position = bucketed_position(position, options, self.min_position);
+        let cell_index = point_into_index1(
+            position,
+            self.cell_widths,
+            self.grid_dimensions,
+            options.bucket_boundary,
+        )
+        .expect("every point is bucketed into a cell");
+        (position, cell_index)
+    }
+
+    fn cell_min(&self, cell_index: usize) -> [f32; 3] {
+        cell_min(cell_index, self.cell_widths, self.grid_dimensions)
+    }
+}
+
+/// The passes over the points that construct a uniform grid.
+pub(crate) trait BuildPasses<T> {
+    /// Returns the bounding box of the points' positions, with their axes in
+    /// the grid's order.
+    fn bounding_box(&self, points: &[T], options: &GridOptions) -> BoundingBox;
+
+    /// Counts the points that are bucketed into each cell and fills the
+    /// cells, and returns the count and the storage of the cells.
+    ///
+    /// `end_phase` is called at the end of the counting and allocation phases.
+    fn fill_cells(
+        &self,
+        points: &[T],
+        options: &GridOptions,
+        layout: &CellLayout,
+        end_phase: &mut dyn FnMut(BuildPhase),
+    ) -> (Vec<usize>, CellPositions);
+}
+
+/// Makes the passes over the points one point at a time.
+pub(crate) struct SerialPasses;
+
+impl<T> BuildPasses<T> for SerialPasses
+where
+    T: PointObject,
+{
+    fn bounding_box(&self, points: &[T], options: &GridOptions) -> BoundingBox {
+        BoundingBox::from_positions(points.iter().map(|p| options.grid_position(p.position())))
+    }
+
+    fn fill_cells(
+        &self,
+        points: &[T],
+        options: &GridOptions,
+        layout: &CellLayout,
+        end_phase: &mut dyn FnMut(BuildPhase),
+    ) -> (Vec<usize>, CellPositions) {
+        let mut cell_point_counts: Vec<usize> = vec![0; layout.cell_count];
+        let mut point_cell_indices = Vec::new();
+        if options.sorted_fill {
+            point_cell_indices.reserve_exact(points.len());
+        }
+        for point in points {
+            let (_, cell_index) = layout.bucket(point.position(), options);
+            cell_point_counts[cell_index] += 1;
+            if options.sorted_fill {
+                point_cell_indices.push(cell_index);
+            }
+        }
+        end_phase(BuildPhase::Counting);
+
+        let mut cell_point_positions = CellPositions::with_capacities(&cell_point_counts, options);
+        end_phase(BuildPhase::Allocation);
+
+        if options.sorted_fill {
+            // Filling the cells one at a time, rather than in the order of the points,
+            // writes to each cell's storage in a single run.
+            for point_index in point_indices_by_cell(&point_cell_indices, &cell_point_counts) {
+                let cell_index = point_cell_indices[point_index];
+                let (position, _) = layout.bucket(points[point_index].position(), options);
+                cell_point_positions.push(
+                    cell_index,
+                    || layout.cell_min(cell_index),
+                    position,
+                    point_index,
+                );
+            }
+        } else {
+            for (point_index, point) in points.iter().enumerate() {
+                let (position, cell_index) = layout.bucket(point.position(), options);
+                cell_point_positions.push(
+                    cell_index,
+                    || layout.cell_min(cell_index),
+                    position,
+                    point_index,
+                );
+            }
+        }
+        (cell_point_counts, cell_point_positions)
+    }
+}
+
+/// Makes the passes over the points in parallel.
+///
+/// The points in each cell are counted with an atomic counter for each cell.
+/// Rather than pushing each point into its cell, the pairs of each point's
+/// cell index and point index are sorted in parallel, which puts the points in
+/// the order that they're stored in, and then the storage is filled in
+/// parallel. The points in each cell end up in the order of their indices, as
+/// they do with `SerialPasses`, so both build the same grid.
+#[cfg(feature = "rayon")]
+pub(crate) struct ParallelPasses;
+
+#[cfg(feature = "rayon")]
+impl<T> BuildPasses<T> for ParallelPasses
+where
+    T: PointObject + Sync,
+{
+    fn bounding_box(&self, points: &[T], options: &GridOptions) -> BoundingBox {
+        use rayon::prelude::*;
+
+        let (min, max) = points
+            .par_iter()
+            .filter(|p| !p.position().iter().any(|c| c.is_nan()))
+            .map(|p| {
+                let position = options.grid_position(p.position());
+                (position, position)
+            })
+            .reduce(
+                || ([f32::INFINITY; 3], [f32::NEG_INFINITY; 3]),
+                |(min1, max1), (min2, max2)| {
+                    (
+                        [0, 1, 2].map(|axis| min_f32(min1[axis], min2[axis])),
+                        [0, 1, 2].map(|axis| max_f32(max1[axis], max2[axis])),
+                    )
+                },
+            );
+        BoundingBox {
+            min,
+            x_width: max[0] - min[0],
+            y_width: max[1] - min[1],
+            z_width: max[2] - min[2],
+        }
+    }
+
+    fn fill_cells(
+        &self,
+        points: &[T],
+        options: &GridOptions,
+        layout: &CellLayout,
+        end_phase: &mut dyn FnMut(BuildPhase),
+    ) -> (Vec<usize>, CellPositions) {
+        use rayon::prelude::*;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let mut cell_points: Vec<(usize, usize)> = points
+            .par_iter()
+            .enumerate()
+            .map(|(point_index, point)| (layout.bucket(point.position(), options).1, point_index))
+            .collect();
+        let counters: Vec<AtomicUsize> = (0..layout.cell_count)
+            .into_par_iter()
+            .map(|_| AtomicUsize::new(0))
+            .collect();
+        cell_points.par_iter().for_each(|&(cell_index, _)| {
+            counters[cell_index].fetch_add(1, Ordering::Relaxed);
+        });
+        let cell_point_counts: Vec<usize> = counters
+            .into_par_iter()
+            .map(AtomicUsize::into_inner)
+            .collect();
+        end_phase(BuildPhase::Counting);
+
+        // The storage is allocated as it's filled.
+        end_phase(BuildPhase::Allocation);
+
+        // The pairs are unique, since each point has its own index, so an unstable sort
+        // orders them the same way every time.
+        cell_points.par_sort_unstable();
+        let cell_point_positions = CellPositions::par_from_cell_order(
+            &cell_point_counts,
+            options,
+            &cell_points,
+            |point_index| layout.bucket(points[point_index].position(), options).0,
+            |cell_index| layout.cell_min(cell_index),
+        );
+        (cell_point_counts, cell_point_positions)
+    }
+}
+
+/// Returns the indices of the points sorted by the cells that they're
+/// bucketed into, with the points in each cell kept in index order.
+///
+/// `point_cell_indices` is the index of the cell that each point is bucketed
+/// into, and `cell_point_counts` is the number of points in each cell. The
+/// points are sorted with a counting sort.
+fn point_indices_by_cell(point_cell_indices: &[usize], cell_point_counts: &[usize]) -> Vec<usize> {
+    let mut cell_starts = Vec::with_capacity(cell_point_counts.len());
+    let mut start = 0;
+    for &count in cell_point_counts {
+        cell_starts.push(start);
+        start += count;
+    }
+
+    let mut point_indices = vec![0; point_cell_indices.len()];
+    for (point_index, &cell_index) in point_cell_indices.iter().enumerate() {
+        point_indices[cell_starts[cell_index]] = point_index;
+        cell_starts[cell_index] += 1;
+    }
+    point_indices
+}
+
+/// Returns the number of rings between two cells, which is the largest
+/// difference between their offsets along any axis.
+fn rings_between(cell_offset1: Offset3, cell_offset2: Offset3) -> usize {
+    let axis_rings = |c1: i64, c2: i64| c1.abs_diff(c2) as usize;
+    axis_rings(cell_offset1.x, cell_offset2.x)
+        .max(axis_rings(cell_offset1.y, cell_offset2.y))
+        .max(axis_rings(cell_offset1.z, cell_offset2.z))
+}
+
+/// Returns the number of cells, along each axis, that the spiral cells extend
+/// outward from the center cell, including the center cell itself.
+fn spiral_width(spiral_cells: &[SpiralCell]) -> i64 {
+    spiral_cells
+        .iter()
+        .map(|sc| {
+            sc.offset
+                .x
+                .abs()
+                .max(sc.offset.y.abs())
+                .max(sc.offset.z.abs())
+                + 1
+        })
+        .max()
+        .unwrap_or(0)
+}
+
+/// Returns the 3-dimensional offset of the cell in which a point, given
+/// relative to the minimum corner of the grid, would be bucketed.
+///
+/// Each component is clamped to `MAX_CELL_OFFSET`, so that adding offsets to
+/// it can't overflow, even for a point at infinity or a query on a grid
+/// without points, whose minimum corner is at infinity.
+fn point_into_offset(point: [f32; 3], cell_widths: [f32; 3], boundary: BucketBoundary) -> Offset3 {
+    let bucket = |axis: usize| {
+        boundary
+            .bucket(point[axis] / cell_widths[axis])
+            .clamp(-MAX_CELL_OFFSET, MAX_CELL_OFFSET)
+    };
+    Offset3::new(bucket(0), bucket(1), bucket(2))
+}
+
+/// Returns the minimum corner of the space covered by the cell with the given
+/// 1-dimensional index, relative to the minimum corner of the grid.
+fn cell_min(
+    cell_index: usize,
+    cell_widths: [f32; 3],
+    grid_size: (usize, usize, usize),
+) -> [f32; 3] {
+    let offset = Offset3::from_grid_index1(cell_index, grid_size.0, grid_size.1);
+    [
+        offset.x as f32 * cell_widths[0],
+        offset.y as f32 * cell_widths[1],
+        offset.z as f32 * cell_widths[2],
+    ]
+}
+
+/// Returns the index of the cell into which a point, given relative to the
+/// minimum corner of the grid, is bucketed when it's added to the grid, or
+/// `None` if the point is outside of the grid.
+///
+/// A point with a NaN coordinate is left out of the bounding box and can't be
+/// found by any query, so it's kept in the first cell.
+fn point_into_index1(
+    point: [f32; 3],
+    cell_widths: [f32; 3],
+    grid_size: (usize, usize, usize),
+    boundary: BucketBoundary,
+) -> Option<usize> {
+    if point.iter().any(|c| c.is_nan()) {
+        return Some(0);
+    }
+    bucket_offset(point, cell_widths, grid_size, boundary).into_grid_index1(grid_size)
+}
+
+/// Returns the 3-dimensional offset of the cell into which a point, given
+/// relative to the minimum corner of the grid, is bucketed when it's added to
+/// the grid.
+///
+/// This is the cell given by `point_into_offset`, except that a point on a
+/// maximum face of the grid, which rounding can put just past the face, is
+/// clamped into the last cell along that axis instead of being left outside of
+/// the grid.
+fn bucket_offset(
+    point: [f32; 3],
+    cell_widths: [f32; 3],
+    grid_size: (usize, usize, usize),
+    boundary: BucketBoundary,
+) -> Offset3 {
+    let offset = point_into_offset(point, cell_widths, boundary);
+    let clamp = |component: i64, axis: usize, width: usize| {
+        let is_on_max_face = component == width as i64
+            && point[axis] / cell_widths[axis] <= width as f32 / (1.0 - WIDTH_EPSILON);
+        if is_on_max_face {
+            component - 1
+        } else {
+            component
+        }
+    };
+    Offset3::new(
+        clamp(offset.x, 0, grid_size.0),
+        clamp(offset.y, 1, grid_size.1),
+        clamp(offset.z, 2, grid_size.2),
+    )
+}
+
+/// Applies an affine transform, given in row-major order, to a point.
+fn transform_point(transform: &[[f32; 4]; 4], point: [f32; 3]) -> [f32; 3] {
+    [0, 1, 2].map(|axis| {
+        let row = transform[axis];
+        row[0] * point[0] + row[1] * point[1] + row[2] * point[2] + row[3]
+    })
+}
+
+/// Returns the position of a point relative to the minimum corner of a grid.
+fn to_local(point: [f32; 3], min_position: [f32; 3]) -> [f32; 3] {
+    [
+        point[0] - min_position[0],
+        point[1] - min_position[1],
+        point[2] - min_position[2],
+    ]
+}
+
+#[cfg(not(feature = "simd"))]
+fn nearest<I>(query_point: [f32; 3], points: I, cell_index1: usize) -> Option<SearchResult>
+where
+    I: IntoIterator<Item = ([f32; 3], usize)>,
+{
+    points
+        .into_iter()
+        .map(|(p, p_obj_idx)| SearchResult {
+            point_object_index: p_obj_idx,
+            cell_index1,
+            distance2_to_query: dist2(query_point, p),
+        })
+        // A point whose distance is NaN, because its position or the query point has a
+        // NaN coordinate, is never the nearest point.
+        .filter(|sr| !sr.distance2_to_query.is_nan())
+        .min_by(|sr1, sr2| sr1.distance2_to_query.total_cmp(&sr2.distance2_to_query))
+}
+
+/// Finds the point that is closest to the query point, computing the squared
+/// distances of several points at once.
+///
+/// The distances are computed with the same operations in the same order as
+/// `dist2`, and ties are broken the same way, so this finds the same point
+/// with the same distance as the scalar version. Gathering the points into
+/// batches isn't free, so this is only faster than the scalar version for
+/// large cells on targets with 8-wide vectors, such as x86-64 with AVX.
+#[cfg(feature = "simd")]
+fn nearest<I>(query_point: [f32; 3], points: I, cell_index1: usize) -> Option<SearchResult>
+where
+    I: IntoIterator<Item = ([f32; 3], usize)>,
+{
+    let mut batch = Batch::new(query_point);
+    let mut maybe_nearest: Option<(usize, f32)> = None;
+    for (position, point_index) in points {
+        if batch.push(position, point_index) {
+            batch.scan(&mut maybe_nearest);
+        }
+    }
+    batch.scan(&mut maybe_nearest);
+
+    maybe_nearest.map(|(point_object_index, distance2_to_query)| SearchResult {
+        point_object_index,
+        cell_index1,
+        distance2_to_query,
+    })
+}
+
+/// Returns the minimum and maximum corners of the box that contains the given
+/// positions, with their axes in the grid's order, along with the box with the
+/// given corners, if there is one.
+///
+/// Positions with a NaN coordinate are left out. Returns `None` if there are
+/// no other positions and no corners are given.
+fn data_corners(
+    positions: &[[f32; 3]],
+    options: &GridOptions,
+    corners: Option<([f32; 3], [f32; 3])>,
+) -> Option<([f32; 3], [f32; 3])> {
+    positions
+        .iter()
+        .filter(|position| !position.iter().any(|c| c.is_nan()))
+        .map(|&position| options.grid_position(position))
+        .map(|position| (position, position))
+        .chain(corners)
+        .reduce(|(min1, max1), (min2, max2)| {
+            (
+                [0, 1, 2].map(|axis| min_f32(min1[axis], min2[axis])),
+                [0, 1, 2].map(|axis| max_f32(max1[axis], max2[axis])),
+            )
+        })
+}
+
+/// Returns the tag of the source range that contains the given point index.
+fn source_tag(source_ranges: &[(u32, Range<usize>)], point_index: usize) -> Option<u32> {
+    let i = source_ranges.partition_point(|(_, range)| range.end <= point_index);
+    source_ranges
+        .get(i)
+        .filter(|(_, range)| range.contains(&point_index))
+        .map(|&(tag, _)| tag)
+}
+
+/// Updates the source ranges for the removal of the point at `point_index`
+/// when the point at `last_index`, the last index, is moved into its place.
+///
+/// The range that contains `point_index` is split around it, so that the moved
+/// point keeps its own tag.
+fn swap_remove_source(
+    source_ranges: &mut Vec<(u32, Range<usize>)>,
+    point_index: usize,
+    last_index: usize,
+) {
+    let moved_tag = source_tag(source_ranges, last_index);
+    for (_, range) in source_ranges.iter_mut() {
+        range.end = range.end.min(last_index);
+    }
+    source_ranges.retain(|(_, range)| !range.is_empty());
+    if point_index == last_index || source_tag(source_ranges, point_index) == moved_tag {
+        return;
+    }
+
+    let i = source_ranges.partition_point(|(_, range)| range.end <= point_index);
+    let mut replacement = vec![];
+    if let Some((tag, range)) = source_ranges
+        .get(i)
+        .filter(|(_, range)| range.contains(&point_index))
+        .cloned()
+    {
+        source_ranges.remove(i);
+        replacement.push((tag, range.start..point_index));
+        replacement.push((tag, point_index + 1..range.end));
+    }
+    replacement.extend(moved_tag.map(|tag| (tag, point_index..point_index + 1)));
+    replacement.retain(|(_, range)| !range.is_empty());
+    replacement.sort_by_key(|(_, range)| range.start);
+    source_ranges.splice(i..i, replacement);
+}
+
+/// Returns the shortest vector that's equivalent to the given vector between
+/// two points in a periodic box, which is the vector from the first point to
+/// the nearest periodic image of the second.
+fn nearest_image_delta(delta: [f32; 3], periodic_box: ([f32; 3], [f32; 3])) -> [f32; 3] {
+    let (box_min, box_max) = periodic_box;
+    [0, 1, 2].map(|axis| {
+        let box_width = box_max[axis] - box_min[axis];
+        delta[axis] - box_width * (delta[axis] / box_width).round()
+    })
+}
+
+/// Wraps the position into the periodic box with the given minimum and
+/// maximum corners.
+fn wrap_into_box(position: [f32; 3], periodic_box: ([f32; 3], [f32; 3])) -> [f32; 3] {
+    let (box_min, box_max) = periodic_box;
+    [0, 1, 2].map(|axis| {
+        let box_width = box_max[axis] - box_min[axis];
+        let relative = (position[axis] - box_min[axis]).rem_euclid(box_width);
+        // Rounding can leave a position that's just below the box at the box's width.
+        if relative < box_width {
+            box_min[axis] + relative
+        } else {
+            box_min[axis]
+        }
+    })
+}
+
+/// Returns the query point wrapped into the periodic box, followed by its 26
+/// images in the neighboring copies of the box.
+fn periodic_images(
+    query_point: [f32; 3],
+    periodic_box: ([f32; 3], [f32; 3]),
+) -> impl Iterator<Item = [f32; 3]> {
+    let (box_min, box_max) = periodic_box;
+    let wrapped = wrap_into_box(query_point, periodic_box);
+    std::iter::once(Offset3::new(0, 0, 0))
+        .chain(neighbor_offsets())
+        .map(move |shift| {
+            let shift = [shift.x, shift.y, shift.z];
+            [0, 1, 2]
+                .map(|axis| wrapped[axis] + shift[axis] as f32 * (box_max[axis] - box_min[axis]))
+        })
+}
+
+/// Returns the squared distance between the point and the closest point in
+/// the box with the given minimum and maximum corners.
+fn box_dist2(point: [f32; 3], bounds: ([f32; 3], [f32; 3])) -> f32 {
+    let (box_min, box_max) = bounds;
+    (0..3)
+        .map(|axis| {
+            let d = max_f32(
+                0.0,
+                max_f32(box_min[axis] - point[axis], point[axis] - box_max[axis]),
+            );
+            d * d
+        })
+        .sum()
+}
+
+/// Returns the point in the box with the given minimum and maximum corners
+/// that is closest to the given point.
+fn clamp_into_box(point: [f32; 3], bounds: ([f32; 3], [f32; 3])) -> [f32; 3] {
+    let (box_min, box_max) = bounds;
+    [0, 1, 2].map(|axis| min_f32(max_f32(point[axis], box_min[axis]), box_max[axis]))
+}
+
+/// Returns the Morton code, or Z-order curve index, of the position within the
+/// box with the given minimum corner and extents.
+///
+/// Each coordinate is quantized to 21 bits, and the bits of the three
+/// quantized coordinates are interleaved.
+fn morton_code(position: [f32; 3], min: [f32; 3], extents: [f32; 3]) -> u64 {
+    const MAX_QUANTIZED: f32 = ((1 << 21) - 1) as f32;
+    let quantized = |axis: usize| {
+        let t = if extents[axis] > 0.0 {
+            (position[axis] - min[axis]) / extents[axis]
+        } else {
+            0.0
+        };
+        (t.clamp(0.0, 1.0) * MAX_QUANTIZED) as u64
+    };
+    spread_bits(quantized(0)) | (spread_bits(quantized(1)) << 1) | (spread_bits(quantized(2)) << 2)
+}
+
+/// Spreads the lowest 21 bits of the value so that there are two zero bits
+/// between each of them.
+fn spread_bits(v: u64) -> u64 {
+    let mut v = v & 0x1f_ffff;
+    v = (v | (v << 32)) & 0x001f_0000_0000_ffff;
+    v = (v | (v << 16)) & 0x001f_0000_ff00_00ff;
+    v = (v | (v << 8)) & 0x100f_00f0_0f00_f00f;
+    v = (v | (v << 4)) & 0x10c3_0c30_c30c_30c3;
+    v = (v | (v << 2)) & 0x1249_2492_4924_9249;
+    v
+}
+
+/// Returns the square of a radius to search within.
+///
+/// A negative radius gives a negative square, which no squared distance is
+/// within, so a search with a negative radius finds nothing rather than
+/// searching within the radius's absolute value.
+fn squared_radius(radius: f32) -> f32 {
+    if radius < 0.0 {
+        -1.0
+    } else {
+        radius * radius
+    }
+}
+
+/// Pairs the index of each point with its distance, given its squared
+/// distance.
+fn with_distances(neighbors: Vec<(usize, f32)>) -> Vec<(usize, Distance)> {
+    neighbors
+        .into_iter()
+        .map(|(point_index, distance2)| (point_index, Distance::from_squared(distance2)))
+        .collect()
+}
+
+/// Removes all but one copy of each point from the list of points and their
+/// squared distances to a query point, keeping the copy with the smallest
+/// distance.
+///
+/// The points are left sorted by their indices.
+pub(crate) fn dedup_by_point_index(neighbors: &mut Vec<(usize, f32)>) {
+    neighbors.sort_by(|(i1, d1), (i2, d2)| i1.cmp(i2).then(d1.total_cmp(d2)));
+    neighbors.dedup_by_key(|(point_index, _)| *point_index);
+}
+
+pub(crate) fn dist2(p: [f32; 3], q: [f32; 3]) -> f32 {
+    let x = q[0] - p[0];
+    let y = q[1] - p[1];
+    let z = q[2] - p[2];
+    x * x + y * y + z * z
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{metric::Manhattan, offset3::Axis, UniformGridBuilder};
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    /// Returns `count` points scattered uniformly within the unit cube, which
+    /// are the same for the same seed.
+    fn random_points(count: usize, seed: u64) -> Vec<[f32; 3]> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        (0..count).map(|_| rng.gen()).collect()
+    }
+
+    /// Returns the squared distance from the query point to the nearest of the
+    /// points, found by checking every point.
+    fn brute_force_nearest2(points: &[[f32; 3]], query_point: [f32; 3]) -> f32 {
+        points
+            .iter()
+            .map(|&p| dist2(query_point, p))
+            .fold(f32::INFINITY, f32::min)
+    }
+
+    #[test]
+    fn queries_on_empty_grid_find_nothing() {
+        let grids = [
+            UniformGrid::<[f32; 3]>::new(vec![], 1.0, spiral_cells::spiral_cells(10)),
+            UniformGridBuilder::new().build(vec![]),
+        ];
+        for grid in &grids {
+            for query_point in [[0.0; 3], [1.0e30, -1.0e30, 5.0]] {
+                assert!(grid.nearest_neighbor(query_point).is_none());
+                assert!(grid.nearest_neighbor_no_table(query_point).is_none());
+                assert!(grid.nearest_neighbor_within(query_point, 1.0).is_none());
+                assert!(grid
+                    .nearest_neighbor_by_metric(query_point, &Manhattan)
+                    .is_none());
+                assert!(grid
+                    .neighbors_within_radius_by_metric(query_point, 1.0, &Manhattan)
+                    .is_empty());
+                assert!(grid.nearest_neighbors_tied(query_point, 0.1).is_empty());
+                assert_eq!(grid.two_nearest(query_point), (None, None));
+                assert_eq!(grid.ring_occupancy(query_point, 3), vec![0; 4]);
+                assert!(grid.nearest_neighbor_traced(query_point).is_none());
+                assert!(grid.nearest_neighbor_counted(query_point).0.is_none());
+                assert!(grid.nearest_neighbor_budgeted(query_point, 10).0.is_none());
+                assert!(grid.nearest_in_local_block(query_point).is_none());
+                assert!(grid.nearest_per_neighbor_cell(query_point).is_empty());
+                assert!(grid.neighbors_within_radius(query_point, 1.0).is_empty());
+                assert!(!grid.has_neighbor_within(query_point, 1.0));
+                assert!(grid.nearest_position(query_point).is_none());
+            }
+            assert!(grid.knn_graph(3).is_empty());
+        }
+    }
+
+    #[test]
+    fn nearest_skips_point_with_nan_position() {
+        let points = vec![[f32::NAN, 0.0, 0.0], [1.0, 1.0, 1.0], [4.0, 4.0, 4.0]];
+        let grids = [
+            UniformGrid::new(points.clone(), 1.0, spiral_cells::spiral_cells(10)),
+            UniformGridBuilder::new().build(points.clone()),
+            #[cfg(feature = "rayon")]
+            UniformGridBuilder::new().build_parallel(points),
+        ];
+        for grid in &grids {
+            assert!(grid.validate().is_ok());
+            let (nearest, distance) = grid.nearest_neighbor([0.0; 3]).unwrap();
+            assert_eq!(*nearest, [1.0, 1.0, 1.0]);
+            assert_eq!(distance.squared(), 3.0);
+            let (nearest, _) = grid.nearest_neighbor_within([4.5; 3], 1.0).unwrap();
+            assert_eq!(*nearest, [4.0, 4.0, 4.0]);
+        }
+    }
+
+    #[test]
+    fn negative_radius_finds_nothing() {
+        let points = vec![[0.0; 3], [0.5, 0.0, 0.0], [3.0, 3.0, 3.0]];
+        let grids = [
+            UniformGridBuilder::new().build(points.clone()),
+            UniformGridBuilder::new()
+                .periodic([-1.0; 3], [4.0; 3])
+                .build(points),
+        ];
+        for grid in &grids {
+            assert_eq!(grid.neighbors_within_radius([0.0; 3], 1.0).len(), 2);
+            assert!(grid.neighbors_within_radius([0.0; 3], -1.0).is_empty());
+            assert!(grid.positions_within_radius([0.0; 3], -1.0).is_empty());
+            assert!(!grid.has_neighbor_within([0.0; 3], -1.0));
+
+            let mut neighbors = vec![(7, Distance::from_squared(1.0))];
+            grid.neighbors_within_radius_into([0.0; 3], -1.0, &mut neighbors);
+            assert!(neighbors.is_empty());
+
+            let mut visited = 0;
+            grid.for_each_within_radius([0.0; 3], -1.0, |_, _| {
+                visited += 1;
+                ControlFlow::Continue(())
+            });
+            assert_eq!(visited, 0);
+
+            let batch = grid.neighbors_within_radius_batch(&[[0.0; 3], [3.0; 3]], -1.0);
+            assert!(batch.iter().all(Vec::is_empty));
+
+            assert!(grid.nearest_neighbor_within([0.0; 3], 1.0).is_some());
+            assert!(grid.nearest_neighbor_within([0.0; 3], -1.0).is_none());
+        }
+    }
+
+    #[test]
+    fn world_to_voxel_rejects_non_finite_points() {
+        let grid = UniformGridBuilder::new().build(vec![[0.0; 3], [4.0; 3]]);
+        assert_eq!(grid.world_to_voxel([0.0; 3]), Some((0, 0, 0)));
+        assert_eq!(grid.world_to_voxel([f32::NAN, 0.0, 0.0]), None);
+        assert_eq!(grid.world_to_voxel([0.0, f32::INFINITY, 0.0]), None);
+        assert_eq!(grid.world_to_voxel([0.0, 0.0, f32::NEG_INFINITY]), None);
+    }
+
+    #[test]
+    fn validate_flags_corrupted_grid() {
+        let points = (0..64)
+            .map(|i| [(i % 4) as f32, (i / 4 % 4) as f32, (i / 16) as f32])
+            .collect_vec();
+        let grid = UniformGridBuilder::new().build(points);
+        assert_eq!(grid.validate(), Ok(()));
+        let cell_index = grid.point_cell_index(0);
+
+        let mut corrupted = grid.clone();
+        corrupted.cell_point_counts[cell_index] += 1;
+        assert!(matches!(
+            corrupted.validate(),
+            Err(ValidationError::PointCount { cell_index: i, .. }) if i == cell_index
+        ));
+
+        let mut corrupted = grid.clone();
+        corrupted.positions[0] = [3.0; 3];
+        assert_eq!(
+            corrupted.validate(),
+            Err(ValidationError::WrongCell {
+                point_index: 0,
+                cell_index
+            })
+        );
+
+        let mut corrupted = grid;
+        corrupted.positions[0] = [0.0, 0.0, 1.0e-3];
+        assert_eq!(
+            corrupted.validate(),
+            Err(ValidationError::PositionMismatch(0))
+        );
+    }
+
+    #[test]
+    fn dedup_keeps_nearest_copy_of_each_point() {
+        let mut neighbors = vec![(3, 4.0), (1, 2.0), (3, 1.0), (1, 2.0), (0, 9.0)];
+        dedup_by_point_index(&mut neighbors);
+        assert_eq!(neighbors, vec![(0, 9.0), (1, 2.0), (3, 1.0)]);
+    }
+
+    #[test]
+    fn queries_with_overlapping_images_return_unique_indices() {
+        // The radius is wider than the periodic box, so the cells around every image
+        // of the query point overlap and each point is within the radius of several
+        // images.
+        let points = (0..27)
+            .map(|i| [(i % 3) as f32, (i / 3 % 3) as f32, (i / 9) as f32])
+            .collect_vec();
+        let grid = UniformGridBuilder::new()
+            .periodic([0.0; 3], [3.0; 3])
+            .build(points);
+        let is_unique = |indices: Vec<usize>| indices.iter().all_unique();
+
+        let neighbors = grid.positions_within_radius([0.5; 3], 4.0);
+        assert_eq!(neighbors.len(), 27);
+        assert!(is_unique(neighbors.iter().map(|&(i, _, _)| i).collect()));
+
+        let mut neighbors = vec![];
+        grid.neighbors_within_radius_into([0.5; 3], 4.0, &mut neighbors);
+        assert_eq!(neighbors.len(), 27);
+        assert!(is_unique(neighbors.iter().map(|&(i, _)| i).collect()));
+
+        let batch = grid.neighbors_within_radius_batch(&[[0.5; 3], [2.9; 3]], 4.0);
+        for neighbors in batch {
+            assert_eq!(neighbors.len(), 27);
+            assert!(is_unique(neighbors.iter().map(|&(i, _)| i).collect()));
+        }
+
+        let neighbors = grid.neighbors_within_radius_by_metric([0.5; 3], 4.0, &Manhattan);
+        assert!(is_unique(
+            neighbors
+                .iter()
+                .map(|&(p, _)| grid
+                    .points()
+                    .iter()
+                    .position(|q| std::ptr::eq(q, p))
+                    .unwrap())
+                .collect()
+        ));
+
+        for neighbors in grid.knn_graph(26) {
+            assert_eq!(neighbors.len(), 26);
+            assert!(is_unique(neighbors.iter().map(|&(i, _)| i).collect()));
+        }
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn parallel_batches_match_serial_batches() {
+        let points = (0..1000)
+            .map(|i| {
+                [
+                    (i % 10) as f32,
+                    (i / 10 % 10) as f32 * 1.5,
+                    (i / 100) as f32 * 0.5,
+                ]
+            })
+            .collect_vec();
+        let grid = UniformGridBuilder::new().build(points);
+        let query_points = [[0.2, 0.3, 0.4], [4.5, 7.5, 2.25], [20.0, -3.0, 1.0]];
+
+        assert_eq!(
+            grid.nearest_neighbors_batch_parallel(&query_points),
+            grid.nearest_neighbors_batch(&query_points)
+        );
+        assert_eq!(
+            grid.neighbors_within_radius_batch_parallel(&query_points, 1.6),
+            grid.neighbors_within_radius_batch(&query_points, 1.6)
+        );
+        assert_eq!(grid.knn_graph_parallel(6), grid.knn_graph(6));
+    }
+
+    #[test]
+    fn nearest_neighbor_with_cell_returns_cell_of_found_point() {
+        let points = random_points(2000, 592);
+        let grid = UniformGrid::new(points.clone(), 1.0, spiral_cells::spiral_cells(10));
+        for query_point in random_points(200, 5920) {
+            let (found, distance, cell_offset) =
+                grid.nearest_neighbor_with_cell(query_point).unwrap();
+            assert_eq!(cell_offset, grid.debug_locate(found.position()).offset);
+            assert_eq!(
+                distance.squared(),
+                brute_force_nearest2(&points, query_point)
+            );
+        }
+    }
+
+    #[test]
+    fn extend_matches_grid_built_from_all_points() {
+        let points = random_points(1000, 593);
+        let inside = random_points(500, 5930);
+        let outside = inside
+            .iter()
+            .map(|p| p.map(|c| c * 3.0 - 1.0))
+            .collect_vec();
+
+        for batch in [inside, outside] {
+            let all_points = points.iter().chain(&batch).copied().collect_vec();
+            let mut grid = UniformGrid::new(points.clone(), 1.0, spiral_cells::spiral_cells(10));
+            grid.extend(batch);
+            let expected =
+                UniformGrid::new(all_points.clone(), 1.0, spiral_cells::spiral_cells(20));
+
+            assert_eq!(grid.points(), &all_points[..]);
+            assert_eq!(grid.validate(), Ok(()));
+            for query_point in random_points(200, 5931) {
+                let query_point = query_point.map(|c| c * 4.0 - 1.5);
+                assert_eq!(
+                    grid.nearest_neighbor_no_table(query_point),
+                    expected.nearest_neighbor_no_table(query_point)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn merged_disjoint_grids_find_points_in_both_regions() {
+        let left = random_points(500, 595);
+        let right = random_points(500, 5950)
+            .into_iter()
+            .map(|p| [p[0] + 3.0, p[1], p[2]])
+            .collect_vec();
+        let all_points = left.iter().chain(&right).copied().collect_vec();
+        let grid = UniformGridBuilder::new()
+            .build(left)
+            .merge(UniformGridBuilder::new().build(right));
+
+        assert_eq!(grid.points(), &all_points[..]);
+        assert_eq!(grid.validate(), Ok(()));
+        for query_point in random_points(200, 5951) {
+            // Spread the queries over both regions and the gap between them.
+            let query_point = [query_point[0] * 4.0, query_point[1], query_point[2]];
+            let (_, distance) = grid.nearest_neighbor_no_table(query_point).unwrap();
+            assert_eq!(
+                distance.squared(),
+                brute_force_nearest2(&all_points, query_point)
+            );
+        }
+    }
+
+    #[test]
+    fn nearest_neighbor_near_cell_wall_looks_into_neighbor_cells() {
+        let points = random_points(4000, 597);
+        let grid = UniformGrid::new(points.clone(), 0.5, spiral_cells::spiral_cells(10));
+        let bb = grid.bounding_box();
+        let cell_width = bb.x_width / grid.dimensions().0 as f32;
+
+        // Queries just inside the wall between two cells along x, which often have
+        // their nearest point on the other side of the wall.
+        let mut across_wall_count = 0;
+        for (i, query_point) in random_points(500, 5970).into_iter().enumerate() {
+            let wall = (i % (grid.dimensions().0 - 1) + 1) as f32;
+            let query_point = [
+                bb.min[0] + wall * cell_width - 1.0e-3,
+                query_point[1],
+                query_point[2],
+            ];
+            let (nearest, distance) = grid.nearest_neighbor(query_point).unwrap();
+            assert_eq!(
+                distance.squared(),
+                brute_force_nearest2(&points, query_point)
+            );
+            if grid.debug_locate(*nearest).offset != grid.debug_locate(query_point).offset {
+                across_wall_count += 1;
+            }
+        }
+        assert!(across_wall_count > 0);
+    }
+
+    #[test]
+    fn has_neighbor_within_finds_coincident_points_only() {
+        let points = random_points(1000, 598);
+        let grid = UniformGridBuilder::new().build(points.clone());
+        for &point in &points[..100] {
+            assert!(grid.has_neighbor_within(point, 0.0));
+            assert!(grid.has_neighbor_within(point, 1.0e-6));
+        }
+        assert!(!grid.has_neighbor_within([0.5, 0.5, 3.0], 1.0e-3));
+        assert!(!grid.has_neighbor_within([-2.0, 0.5, 0.5], 1.0));
+    }
+
+    #[test]
+    fn long_thin_cloud_gets_cells_only_along_its_length() {
+        let points = random_points(1000, 599)
+            .into_iter()
+            .map(|p| [p[0] * 100.0, p[1], p[2]])
+            .collect_vec();
+        let grid = UniformGrid::new(points.clone(), 1.0, spiral_cells::spiral_cells(10));
+        let (x_width, y_width, z_width) = grid.dimensions();
+        let cell_count = x_width * y_width * z_width;
+
+        // A grid of cubes that's as wide as the cloud along every axis would have
+        // `x_width` cubed cells, almost all of them empty. Rounding up the number of
+        // cells along the short axes can add up to about as many cells again.
+        assert!(x_width > 10 * y_width.max(z_width));
+        assert!(cell_count <= 2 * points.len());
+        assert!(cell_count * 1000 < x_width.pow(3));
+
+        for query_point in random_points(100, 5990) {
+            let query_point = [query_point[0] * 100.0, query_point[1], query_point[2]];
+            let (nearest, _) = grid.nearest_neighbor(query_point).unwrap();
+            assert_eq!(
+                dist2(query_point, *nearest),
+                brute_force_nearest2(&points, query_point)
+            );
+        }
+    }
+
+    #[test]
+    fn points_are_in_construction_order() {
+        let points = random_points(1000, 600);
+        let mut grid = UniformGrid::new(points.clone(), 1.0, spiral_cells::spiral_cells(10));
+        assert_eq!(grid.points().len(), grid.len());
+        assert_eq!(grid.points(), &points[..]);
+
+        grid.insert([0.5; 3]);
+        assert_eq!(grid.points().len(), grid.len());
+        assert_eq!(grid.points()[1000], [0.5; 3]);
+    }
+
+    #[test]
+    fn nearest_per_neighbor_cell_finds_nearest_point_of_each_cell() {
+        let points = random_points(500, 601);
+        let grid = UniformGrid::new(points.clone(), 1.0, spiral_cells::spiral_cells(10));
+        let cell_of = |p: [f32; 3]| grid.debug_locate(p).offset;
+
+        for query_point in random_points(20, 6010) {
+            let query_cell = cell_of(query_point);
+            let results = grid.nearest_per_neighbor_cell(query_point);
+
+            let occupied_cells = points
+                .iter()
+                .map(|&p| cell_of(p))
+                .filter(|&cell| rings_between(cell, query_cell) <= 1)
+                .unique()
+                .collect_vec();
+            assert_eq!(results.len(), occupied_cells.len());
+            if occupied_cells.contains(&query_cell) {
+                assert_eq!(results[0].0, query_cell);
+            }
+            for (cell_offset, nearest, distance) in results {
+                let points_in_cell = points
+                    .iter()
+                    .copied()
+                    .filter(|&p| cell_of(p) == cell_offset)
+                    .collect_vec();
+                assert_eq!(cell_of(*nearest), cell_offset);
+                assert_eq!(dist2(query_point, *nearest), distance.squared());
+                assert_eq!(
+                    distance.squared(),
+                    brute_force_nearest2(&points_in_cell, query_point)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn canonical_order_gives_same_grid_for_shuffled_points() {
+        use rand::seq::SliceRandom;
+
+        let mut points = random_points(1000, 602);
+        // Duplicates are ordered by their coordinates' bits, so they're
+        // indistinguishable in the grid.
+        points.extend_from_within(..50);
+        let mut shuffled = points.clone();
+        shuffled.shuffle(&mut StdRng::seed_from_u64(6020));
+        assert_ne!(points, shuffled);
+
+        let build = |points: Vec<[f32; 3]>| {
+            UniformGridBuilder::new()
+                .canonical_order(true)
+                .build(points)
+        };
+        let (grid, shuffled_grid) = (build(points), build(shuffled));
+        let bits = |points: &[[f32; 3]]| points.iter().map(|p| p.map(f32::to_bits)).collect_vec();
+        assert_eq!(bits(grid.points()), bits(shuffled_grid.points()));
+        assert_eq!(grid.dimensions(), shuffled_grid.dimensions());
+        for cell_index in 0..grid.cell_point_counts.len() {
+            let cell = |grid: &UniformGrid<[f32; 3]>| {
+                grid.cell_points(cell_index)
+                    .map(|(position, point_index)| (position.map(f32::to_bits), point_index))
+                    .collect_vec()
+            };
+            assert_eq!(cell(&grid), cell(&shuffled_grid));
+        }
+    }
+
+    #[test]
+    fn spiral_search_near_boundary_skips_cells_outside_grid() {
+        let points = random_points(5000, 603);
+        // A large scale leaves most cells empty, so most queries need a spiral search.
+        let grid = UniformGrid::new(points.clone(), 2.0, spiral_cells::spiral_cells(40));
+        assert!(grid.spiral_covers_grid());
+        let bb = grid.bounding_box();
+
+        for (i, query_point) in random_points(300, 6030).into_iter().enumerate() {
+            // Queries that lie just inside one of the faces of the grid.
+            let mut query_point = query_point;
+            let axis = i % 3;
+            query_point[axis] = if i % 2 == 0 {
+                bb.min[axis] + 1.0e-3
+            } else {
+                bb.max()[axis] - 1.0e-3
+            };
+            let traced = grid.nearest_neighbor_traced(query_point).unwrap();
+            assert!(traced.exact);
+            assert_eq!(
+                dist2(query_point, *traced.point),
+                brute_force_nearest2(&points, query_point)
+            );
+
+            // Only the cells inside the grid are examined, which are a little more
+            // than half of the cells in the rings around a cell on a face of the grid.
+            // The query cell's neighbors can be examined a second time by the spiral
+            // search, so distinct cells are counted.
+            let (_, visited_cells) = grid.nearest_neighbor_visited_cells(query_point);
+            let rings = traced.rings_searched;
+            assert!(visited_cells
+                .iter()
+                .all(|&cell| grid.offset_into_index1(cell).is_some()));
+            assert!(visited_cells.iter().unique().count() <= (rings + 1) * (2 * rings + 1).pow(2));
+        }
+    }
+
+    #[cfg(feature = "half")]
+    #[test]
+    fn half_precision_positions_match_full_precision_within_tolerance() {
+        let points = random_points(5000, 604);
+        let grid = UniformGridBuilder::new().build(points.clone());
+        let half_grid = UniformGridBuilder::new()
+            .half_precision_positions(true)
+            .build(points);
+        let cell_width = half_grid.cell_widths[0];
+
+        for query_point in random_points(500, 6040) {
+            let (_, distance) = grid.nearest_neighbor(query_point).unwrap();
+            let (found, half_distance) = half_grid.nearest_neighbor(query_point).unwrap();
+            assert_eq!(half_distance.squared(), dist2(query_point, *found));
+            assert!(half_distance >= distance);
+            assert!(half_distance.linear() - distance.linear() <= cell_width / 1000.0);
+        }
+    }
+
+    #[test]
+    fn periodic_grid_finds_neighbor_across_opposite_face() {
+        let points = vec![[0.1, 5.0, 5.0], [9.9, 5.0, 5.0], [5.0, 5.0, 5.0]];
+        let periodic = UniformGridBuilder::new()
+            .periodic([0.0; 3], [10.0; 3])
+            .build(points.clone());
+        let default = UniformGridBuilder::new().build(points);
+
+        let (nearest, distance) = periodic.nearest_neighbor_of(0).unwrap();
+        assert_eq!(*nearest, [9.9, 5.0, 5.0]);
+        assert!((distance.linear() - 0.2).abs() < 1.0e-4);
+        let (nearest, distance) = default.nearest_neighbor_of(0).unwrap();
+        assert_eq!(*nearest, [5.0, 5.0, 5.0]);
+        assert!((distance.linear() - 4.9).abs() < 1.0e-4);
+
+        let (nearest, _) = periodic.nearest_neighbor([0.0, 5.0, 5.0]).unwrap();
+        assert_eq!(*nearest, [0.1, 5.0, 5.0]);
+        let (nearest, _) = periodic.nearest_neighbor([-0.05, 5.0, 5.0]).unwrap();
+        assert_eq!(*nearest, [9.9, 5.0, 5.0]);
+    }
+
+    #[test]
+    fn traced_neighbor_reports_whether_result_is_exact() {
+        let mut points = random_points(1000, 607);
+        points.push([10.0; 3]);
+        let query_point = [9.0; 3];
+
+        let grid = UniformGridBuilder::new().build(points.clone());
+        let traced = grid.nearest_neighbor_traced(query_point).unwrap();
+        assert!(traced.exact);
+        assert_eq!(*traced.point, [10.0; 3]);
+
+        // The spiral cells don't reach from the query cell to the other points, so
+        // the result can't be guaranteed to be the nearest neighbor.
+        let grid = UniformGrid::new(points, 1.0, spiral_cells::spiral_cells(2));
+        let traced = grid.nearest_neighbor_traced(query_point).unwrap();
+        assert!(!traced.exact);
+    }
+
+    #[test]
+    fn radius_batch_matches_single_queries() {
+        let points = random_points(2000, 608);
+        let grid = UniformGridBuilder::new().build(points);
+        let query_points = random_points(100, 6080);
+        let radius = 0.1;
+
+        let batch = grid.neighbors_within_radius_batch(&query_points, radius);
+        assert_eq!(batch.len(), query_points.len());
+        assert!(batch.iter().map(Vec::len).sum::<usize>() > query_points.len());
+        for (&query_point, neighbors) in query_points.iter().zip(batch) {
+            let mut expected = grid
+                .neighbors_within_radius(query_point, radius)
+                .into_iter()
+                .map(|(p, d)| {
+                    (
+                        grid.points()
+                            .iter()
+                            .position(|q| std::ptr::eq(q, p))
+                            .unwrap(),
+                        d,
+                    )
+                })
+                .collect_vec();
+            expected.sort_by_key(|&(point_index, _)| point_index);
+            assert_eq!(neighbors, expected);
+        }
+    }
+
+    #[test]
+    fn cached_nearest_matches_uncached_along_a_path() {
+        let grid = UniformGridBuilder::new().build(random_points(2000, 610));
+        let mut hint = QueryHint::new();
+        for step in 0..500 {
+            let t = step as f32 / 500.0;
+            let query_point = [t, 0.5 + 0.3 * (8.0 * t).sin(), 0.5];
+            let cached = grid.nearest_neighbor_cached(query_point, &mut hint);
+            let uncached = grid.nearest_neighbor(query_point);
+            assert_eq!(cached.map(|(p, d)| (*p, d)), uncached.map(|(p, d)| (*p, d)));
+        }
+        assert!(hint.last_cell_offset.is_some());
+    }
+
+    #[test]
+    fn squared_radius_query_matches_radius_query() {
+        let grid = UniformGridBuilder::new().build(random_points(2000, 612));
+        for query_point in random_points(50, 6120) {
+            for radius in [0.0, 0.05, 0.13, 0.4] {
+                let by_radius = grid.neighbors_within_radius(query_point, radius);
+                let by_radius2 = grid.neighbors_within_radius2(query_point, radius * radius);
+                assert_eq!(
+                    by_radius.iter().map(|&(p, d)| (*p, d)).collect_vec(),
+                    by_radius2.iter().map(|&(p, d)| (*p, d)).collect_vec()
+                );
+            }
+        }
+    }
+
+    /// A point that's in one of several categories.
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    struct CategorizedPoint {
+        position: [f32; 3],
+        category: u32,
+    }
+
+    impl PointObject for CategorizedPoint {
+        fn position(&self) -> [f32; 3] {
+            self.position
+        }
+
+        fn category(&self) -> u32 {
+            self.category
+        }
+    }
+
+    #[test]
+    fn nearest_in_category_skips_closer_points_of_other_categories() {
+        let points = random_points(3000, 614)
+            .into_iter()
+            .enumerate()
+            .map(|(i, position)| CategorizedPoint {
+                position,
+                category: (i % 3) as u32,
+            })
+            .collect_vec();
+        let grid = UniformGridBuilder::new().build(points.clone());
+        for query_point in random_points(100, 6140) {
+            for category in 0..3 {
+                let (found, distance) = grid
+                    .nearest_neighbor_in_category(query_point, category)
+                    .unwrap();
+                assert_eq!(found.category, category);
+                let in_category = points
+                    .iter()
+                    .filter(|p| p.category == category)
+                    .map(|p| p.position)
+                    .collect_vec();
+                assert_eq!(
+                    dist2(query_point, found.position),
+                    brute_force_nearest2(&in_category, query_point)
+                );
+                assert!(distance >= grid.nearest_neighbor(query_point).unwrap().1);
+            }
+        }
+        assert!(grid.nearest_neighbor_in_category([0.5; 3], 3).is_none());
+
+        let grid = UniformGridBuilder::new().build(vec![
+            CategorizedPoint {
+                position: [0.1, 0.0, 0.0],
+                category: 1,
+            },
+            CategorizedPoint {
+                position: [2.0, 0.0, 0.0],
+                category: 0,
+            },
+        ]);
+        let (found, _) = grid.nearest_neighbor_in_category([0.0; 3], 0).unwrap();
+        assert_eq!(found.position, [2.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn knn_graph_of_lattice_links_axis_adjacent_points() {
+        let side = 5;
+        let points = itertools::iproduct!(0..side, 0..side, 0..side)
+            .map(|(x, y, z)| [x as f32, y as f32, z as f32])
+            .collect_vec();
+        let grid = UniformGridBuilder::new().build(points.clone());
+        let graph = grid.knn_graph(6);
+        assert_eq!(graph.len(), points.len());
+        for (point_index, neighbors) in graph.iter().enumerate() {
+            let p = grid.points()[point_index];
+            if p.iter().any(|&c| c == 0.0 || c == (side - 1) as f32) {
+                continue;
+            }
+            assert_eq!(neighbors.len(), 6);
+            for &(neighbor_index, distance) in neighbors {
+                let q = grid.points()[neighbor_index];
+                assert_eq!(dist2(p, q), 1.0);
+                assert_eq!(distance.squared(), 1.0);
+            }
+            assert!(neighbors.iter().map(|&(i, _)| i).all_unique());
+        }
+    }
+
+    #[test]
+    fn decimate_picks_one_point_from_each_occupied_cell() {
+        let grid = UniformGridBuilder::new().build(random_points(3000, 617));
+        let representatives = grid.decimate();
+        let occupied_count = grid
+            .cell_point_counts
+            .iter()
+            .filter(|&&count| count > 0)
+            .count();
+        assert_eq!(representatives.len(), occupied_count);
+        assert!(representatives
+            .iter()
+            .map(|&point_index| grid.point_cell_index(point_index))
+            .all_unique());
+    }
+
+    #[test]
+    fn memory_bytes_grows_with_points() {
+        let mut grid = UniformGridBuilder::new().build(random_points(100, 619));
+        let small_bytes = grid.memory_bytes();
+        assert!(small_bytes >= 100 * std::mem::size_of::<[f32; 3]>());
+        grid.extend(random_points(10_000, 6190));
+        assert!(grid.memory_bytes() > small_bytes);
+    }
+
+    #[test]
+    fn nearest_without_table_matches_nearest_with_table() {
+        let points = random_points(3000, 620);
+        let grid = UniformGridBuilder::new().build(points.clone());
+        let mut query_points = random_points(500, 6200);
+        query_points.extend([[-0.5; 3], [1.5, 0.5, -2.0], [0.5, 0.5, 30.0]]);
+        for query_point in query_points {
+            let (with_table, _) = grid.nearest_neighbor(query_point).unwrap();
+            let (without_table, _) = grid.nearest_neighbor_no_table(query_point).unwrap();
+            assert_eq!(
+                dist2(query_point, *without_table),
+                dist2(query_point, *with_table)
+            );
+            assert_eq!(
+                dist2(query_point, *without_table),
+                brute_force_nearest2(&points, query_point)
+            );
+        }
+    }
+
+    #[test]
+    fn nearly_equal_widths_give_same_dimensions() {
+        let corners = |x_max: f32, y_max: f32| {
+            let mut points = random_points(50, 621);
+            points.extend([[0.0; 3], [x_max, y_max, 1.0]]);
+            points
+        };
+        let wider = 1.0 + 1e-7;
+        assert_ne!(wider, 1.0);
+        let grids = [
+            UniformGridBuilder::new().build(corners(1.0, 1.0)),
+            UniformGridBuilder::new().build(corners(wider, 1.0)),
+            UniformGridBuilder::new().build(corners(1.0, wider)),
+            UniformGridBuilder::new().build(corners(1.0, wider)),
+        ];
+        for grid in &grids {
+            assert_eq!(grid.dimensions(), grids[0].dimensions());
+            assert_eq!(grid.validate(), Ok(()));
+        }
+        assert_eq!(grids[1].cell_widths, grids[2].cell_widths);
+        assert_eq!(grids[2].cell_widths, grids[3].cell_widths);
+    }
+
+    #[test]
+    fn spiral_radius_grid_never_falls_back_to_brute_force() {
+        // Two far-apart clusters leave most cells empty, so most queries have to
+        // spiral a long way out.
+        let mut points = random_points(200, 622);
+        points.extend(
+            random_points(200, 6220)
+                .into_iter()
+                .map(|p| p.map(|c| c + 9.0)),
+        );
+        let grid = UniformGrid::with_spiral_radius(points.clone(), 6);
+        let (x_width, y_width, z_width) = grid.dimensions();
+        assert!(x_width <= 6 && y_width <= 6 && z_width <= 6);
+        for query_point in random_points(300, 6221) {
+            let query_point = query_point.map(|c| c * 10.0);
+            let local_query_point = grid.to_local(query_point);
+            let query_cell_offset = grid.point_into_offset(local_query_point);
+            let mut trace = SearchTrace::default();
+            let nearest_in_query_cell = grid.nearest_neighbor_in_query_cell(
+                local_query_point,
+                query_cell_offset,
+                &mut trace,
+                &|_| true,
+            );
+            let nearest = grid
+                .nearest_neighbor_spiral_search(
+                    local_query_point,
+                    query_cell_offset,
+                    nearest_in_query_cell,
+                    &mut trace,
+                    &|_| true,
+                )
+                .unwrap();
+            assert!(trace.exact);
+            assert_eq!(
+                dist2(query_point, points[nearest.point_object_index]),
+                brute_force_nearest2(&points, query_point)
+            );
+        }
+    }
+
+    #[test]
+    fn into_points_returns_input_points() {
+        let points = random_points(500, 623);
+        let grid = UniformGridBuilder::new().build(points.clone());
+        assert_eq!(grid.into_points(), points);
+
+        let grid = UniformGridBuilder::new().build(points.clone());
+        let dimensions = grid.dimensions();
+        let parts = grid.into_parts();
+        assert_eq!(parts.points, points);
+        assert_eq!(parts.dimensions, dimensions);
+    }
+
+    #[test]
+    fn nearest_in_cone_skips_closer_points_outside_cone() {
+        let points = vec![
+            [-0.5, 0.0, 0.0],
+            [1.0, 0.8, 0.0],
+            [2.0, 0.5, 0.0],
+            [3.0, 0.0, 0.0],
+            [0.0, 0.0, 0.0],
+        ];
+        let grid = UniformGridBuilder::new().build(points);
+        let half_angle = 30.0f32.to_radians();
+        let (found, distance) = grid
+            .nearest_in_cone([0.0; 3], [1.0, 0.0, 0.0], half_angle)
+            .unwrap();
+        assert_eq!(*found, [2.0, 0.5, 0.0]);
+        assert_eq!(distance.squared(), 4.25);
+        let (found, _) = grid
+            .nearest_in_cone([0.0; 3], [2.0, 0.0, 0.0], 0.0)
+            .unwrap();
+        assert_eq!(*found, [3.0, 0.0, 0.0]);
+        assert!(grid
+            .nearest_in_cone([0.0; 3], [0.0, 0.0, 1.0], half_angle)
+            .is_none());
+        assert!(grid
+            .nearest_in_cone([0.0; 3], [0.0; 3], half_angle)
+            .is_none());
+    }
+
+    #[test]
+    fn grid_rebuilt_from_exported_index_gives_same_results() {
+        let points = random_points(2000, 625);
+        let grid = UniformGridBuilder::new().build(points.clone());
+        let bytes = bincode::serialize(&grid.export_index()).unwrap();
+        let index: GridIndex = bincode::deserialize(&bytes).unwrap();
+        assert_eq!(index.point_count(), points.len());
+        let rebuilt = UniformGrid::from_index(index, points.clone()).unwrap();
+        assert_eq!(rebuilt.validate(), Ok(()));
+        for query_point in random_points(200, 6250) {
+            assert_eq!(
+                rebuilt.nearest_neighbor(query_point),
+                grid.nearest_neighbor(query_point)
+            );
+            assert_eq!(
+                rebuilt.neighbors_within_radius(query_point, 0.1),
+                grid.neighbors_within_radius(query_point, 0.1)
+            );
+        }
+
+        let mut missing_point = points;
+        missing_point.pop();
+        assert!(UniformGrid::from_index(grid.export_index(), missing_point).is_err());
+    }
+
+    #[test]
+    fn neighbor_cells_stay_inside_grid() {
+        let grid = UniformGridBuilder::new()
+            .min_dimensions((4, 4, 4))
+            .build(random_points(100, 626));
+        let (x_width, y_width, z_width) = grid.dimensions();
+        let corner = Offset3::new(0, 0, 0);
+        assert_eq!(grid.neighbor_cells(corner).count(), 7);
+        let far_corner = Offset3::new(x_width as i64 - 1, y_width as i64 - 1, z_width as i64 - 1);
+        assert_eq!(grid.neighbor_cells(far_corner).count(), 7);
+        let interior = Offset3::new(1, 2, 1);
+        let neighbors = grid.neighbor_cells(interior).collect_vec();
+        assert_eq!(neighbors.len(), 26);
+        assert!(neighbors.iter().all_unique());
+        assert!(!neighbors.contains(&interior));
+    }
+
+    #[test]
+    fn nearest_is_exact_for_large_coordinates() {
+        // Coordinates around 1e7 are only representable to the nearest 1.0, and their
+        // squares are far beyond what an `f32` can hold exactly, so distances have to
+        // be computed from differences of coordinates.
+        let base = [1.0e7, -2.0e7, 3.0e7];
+        let mut rng = StdRng::seed_from_u64(627);
+        let points = (0..1000)
+            .map(|_| {
+                let offset: [f32; 3] = [
+                    rng.gen_range(0..200),
+                    rng.gen_range(0..200),
+                    rng.gen_range(0..200),
+                ]
+                .map(|c: i32| c as f32);
+                [
+                    base[0] + offset[0],
+                    base[1] + offset[1],
+                    base[2] + offset[2],
+                ]
+            })
+            .collect_vec();
+        let grid = UniformGridBuilder::new().build(points.clone());
+        for query_point in random_points(200, 6270) {
+            let query_point = [
+                base[0] + (query_point[0] * 200.0).round(),
+                base[1] + (query_point[1] * 200.0).round(),
+                base[2] + (query_point[2] * 200.0).round(),
+            ];
+            let (found, distance) = grid.nearest_neighbor(query_point).unwrap();
+            let expected2 = brute_force_nearest2(&points, query_point);
+            assert_eq!(dist2(query_point, *found), expected2);
+            assert_eq!(distance.squared(), expected2);
+        }
+    }
+
+    #[test]
+    fn interior_query_on_dense_points_scans_few_cells() {
+        // With a few points in each cell, the nearest neighbor of a query point is
+        // almost always within the query cell or one of its neighbors.
+        let grid = UniformGridBuilder::new()
+            .target_occupancy(4.0)
+            .build(random_points(20_000, 628));
+        for query_point in random_points(100, 6280) {
+            let query_point = query_point.map(|c| 0.2 + 0.6 * c);
+            let (nearest, cells_scanned) = grid.nearest_neighbor_counted(query_point);
+            assert!(nearest.is_some());
+            assert!(cells_scanned <= 27, "{cells_scanned} cells scanned");
+        }
+    }
+
+    #[test]
+    fn positions_only_grid_uses_less_memory() {
+        let points = random_points(5000, 631);
+        let generic = UniformGrid::new(points.clone(), 1.0, spiral_cells::spiral_cells(10));
+        let positions_only =
+            UniformGrid::new_positions_only(points.clone(), 1.0, spiral_cells::spiral_cells(10));
+        assert!(positions_only.memory_bytes() < generic.memory_bytes());
+        for query_point in random_points(100, 6310) {
+            assert_eq!(
+                positions_only.nearest_neighbor(query_point),
+                generic.nearest_neighbor(query_point)
+            );
+        }
+    }
+
+    #[test]
+    fn nearest_neighbor_masked_skips_inactive_points() {
+        let points = random_points(2000, 632);
+        let grid = UniformGridBuilder::new().build(points.clone());
+        for query_point in random_points(50, 6320) {
+            let mut mask = vec![true; points.len()];
+            let (nearest, _) = grid.nearest_neighbor_masked(query_point, &mask).unwrap();
+            let nearest_index = points.iter().position(|p| p == nearest).unwrap();
+            mask[nearest_index] = false;
+            let (next_nearest, _) = grid.nearest_neighbor_masked(query_point, &mask).unwrap();
+            assert_ne!(next_nearest, nearest);
+            let active_points = points
+                .iter()
+                .zip(&mask)
+                .filter(|&(_, &is_active)| is_active)
+                .map(|(&p, _)| p)
+                .collect_vec();
+            assert_eq!(
+                dist2(query_point, *next_nearest),
+                brute_force_nearest2(&active_points, query_point)
+            );
+        }
+        assert!(grid
+            .nearest_neighbor_masked([0.5; 3], &vec![false; points.len()])
+            .is_none());
+    }
+
+    #[test]
+    fn transformed_query_finds_point_in_grid_frame() {
+        let points = random_points(1000, 633);
+        let grid = UniformGridBuilder::new().build(points.clone());
+        // The points are moved by a quarter turn about the z axis followed by a
+        // translation of 10 along the x axis, which maps `[x, y, z]` to
+        // `[10 - y, x, z]`. The inverse maps `[x, y, z]` to `[y, 10 - x, z]`.
+        let inverse_transform = [
+            [0.0, 1.0, 0.0, 0.0],
+            [-1.0, 0.0, 0.0, 10.0],
+            [0.0, 0.0, 1.0, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ];
+        for &point in points.iter().step_by(50) {
+            let moved_point = [10.0 - point[1], point[0], point[2]];
+            let (found, distance) = grid
+                .query_transformed(moved_point, &inverse_transform)
+                .unwrap();
+            assert_eq!(*found, point);
+            assert!(distance.linear() < 1e-5);
+        }
+        let (found, _) = grid
+            .query_transformed([10.0, 0.0, 0.0], &inverse_transform)
+            .unwrap();
+        assert_eq!(
+            dist2([0.0; 3], *found),
+            brute_force_nearest2(&points, [0.0; 3])
+        );
+    }
+
+    #[test]
+    fn occupancy_volume_counts_every_point() {
+        let grid = UniformGridBuilder::new()
+            .min_dimensions((3, 4, 5))
+            .build(random_points(1000, 634));
+        let (counts, dimensions) = grid.occupancy_volume();
+        assert_eq!(dimensions, grid.dimensions());
+        assert_eq!(counts.len(), dimensions.0 * dimensions.1 * dimensions.2);
+        assert_eq!(counts.iter().sum::<usize>(), grid.len());
+        for point_index in 0..grid.len() {
+            let cell_offset = grid.point_into_offset(grid.to_local(grid.positions()[point_index]));
+            let cell_index = cell_offset.into_grid_index1(dimensions).unwrap();
+            assert!(counts[cell_index] > 0);
+        }
+    }
+
+    #[test]
+    fn nearest_neighbors_tied_returns_every_corner_of_cube() {
+        let mut points = cube_corners(1.0);
+        points.extend(cube_corners(3.0));
+        let grid = UniformGridBuilder::new().build(points);
+        let tied = grid.nearest_neighbors_tied([0.0; 3], 1e-4);
+        assert_eq!(tied.len(), 8);
+        assert!(tied
+            .iter()
+            .all(|(p, d)| p.iter().all(|c| c.abs() == 1.0) && d.squared() == 3.0));
+        assert!(tied.iter().map(|(p, _)| p.map(f32::to_bits)).all_unique());
+        assert_eq!(grid.nearest_neighbors_tied([0.0; 3], 0.0).len(), 8);
+        assert!(grid.nearest_neighbors_tied([0.0; 3], -2.0).is_empty());
+    }
+
+    /// Returns the corners of the cube that's centered at the origin with
+    /// corners `half_width` away from the origin along each axis.
+    fn cube_corners(half_width: f32) -> Vec<[f32; 3]> {
+        itertools::iproduct!([-1.0, 1.0], [-1.0, 1.0], [-1.0, 1.0])
+            .map(|(x, y, z)| [x * half_width, y * half_width, z * half_width])
+            .collect()
+    }
+
+    #[test]
+    fn try_new_rejects_spiral_too_small_for_grid() {
+        let points = random_points(5000, 636);
+        let grid = UniformGrid::new(points.clone(), 1.0, spiral_cells::spiral_cells(2));
+        let (x_width, y_width, z_width) = grid.dimensions();
+        let grid_width = x_width.max(y_width).max(z_width);
+        assert!(!grid.spiral_covers_grid());
+        match UniformGrid::try_new(points.clone(), 1.0, spiral_cells::spiral_cells(2)) {
+            Err(SpiralTooSmall {
+                spiral_width,
+                grid_width: found_grid_width,
+            }) => {
+                assert!(spiral_width < grid_width);
+                assert_eq!(found_grid_width, grid_width);
+            }
+            Ok(_) => panic!("spiral cells that are too small were accepted"),
+        }
+        let grid =
+            UniformGrid::try_new(points, 1.0, spiral_cells::spiral_cells(grid_width)).unwrap();
+        assert!(grid.spiral_covers_grid());
+    }
+
+    #[test]
+    fn contains_point_matches_exact_positions_only() {
+        let points = random_points(1000, 637);
+        let grid = UniformGridBuilder::new().build(points.clone());
+        for &point in &points {
+            assert!(grid.contains_point(point));
+            let nudged = [point[0], f32::from_bits(point[1].to_bits() + 1), point[2]];
+            assert!(!grid.contains_point(nudged));
+        }
+        assert!(!grid.contains_point([f32::NAN, 0.5, 0.5]));
+        assert!(!grid.contains_point([5.0; 3]));
+
+        let grid = UniformGridBuilder::new().build(vec![[0.0; 3], [1.0; 3]]);
+        assert!(grid.contains_point([-0.0, 0.0, -0.0]));
+    }
+
+    #[test]
+    fn nearest_neighbor_with_source_returns_tag_of_source() {
+        let left = random_points(500, 638);
+        let right = random_points(500, 6380)
+            .into_iter()
+            .map(|p| [p[0] + 1.5, p[1], p[2]])
+            .collect_vec();
+        let grid = UniformGrid::from_tagged_sources(
+            vec![(7, left.clone()), (3, right.clone())],
+            1.0,
+            spiral_cells::spiral_cells(10),
+        );
+        assert_eq!(grid.len(), 1000);
+        assert_eq!(grid.source_tag(0), Some(7));
+        assert_eq!(grid.source_tag(499), Some(7));
+        assert_eq!(grid.source_tag(500), Some(3));
+        assert_eq!(grid.source_tag(1000), None);
+        for query_point in random_points(100, 6381) {
+            let query_point = [query_point[0] * 2.5, query_point[1], query_point[2]];
+            let (found, _, tag) = grid.nearest_neighbor_with_source(query_point).unwrap();
+            let expected_tag = if left.contains(found) {
+                7
+            } else {
+                assert!(right.contains(found));
+                3
+            };
+            assert_eq!(tag, Some(expected_tag));
+        }
+    }
+
+    #[test]
+    fn debug_locate_keeps_point_on_max_face_in_bounds() {
+        let mut points = random_points(1000, 640);
+        points.extend([[0.0; 3], [1.0; 3]]);
+        let grid = UniformGridBuilder::new().build(points);
+        let location = grid.debug_locate([1.0; 3]);
+        assert!(location.in_bounds);
+        assert_eq!(
+            location.index,
+            location.offset.into_grid_index1(grid.dimensions())
+        );
+        assert_eq!(location.index, Some(grid.point_cell_index(1001)));
+        assert!(grid.contains_point([1.0; 3]));
+
+        let location = grid.debug_locate([0.0; 3]);
+        assert_eq!(location.offset, Offset3::new(0, 0, 0));
+        assert_eq!(location.index, Some(0));
+
+        let location = grid.debug_locate([0.5, 0.5, 3.0]);
+        assert!(!location.in_bounds);
+        assert_eq!(location.index, None);
+    }
+
+    #[test]
+    fn nearest_in_local_block_usually_matches_exact_in_dense_regions() {
+        let grid = UniformGridBuilder::new().build(random_points(20_000, 641));
+        let query_points = random_points(500, 6410)
+            .into_iter()
+            .map(|p| p.map(|c| 0.1 + 0.8 * c))
+            .collect_vec();
+        let matching_count = query_points
+            .iter()
+            .filter(|&&query_point| {
+                let (approximate, approximate_distance) =
+                    grid.nearest_in_local_block(query_point).unwrap();
+                let (exact, exact_distance) = grid.nearest_neighbor(query_point).unwrap();
+                assert!(approximate_distance >= exact_distance);
+                approximate == exact
+            })
+            .count();
+        assert!(matching_count * 100 >= query_points.len() * 95);
+
+        // Near sparse regions the block can be empty even though the grid isn't.
+        let mut points = random_points(1000, 6411);
+        points.push([20.0; 3]);
+        let grid = UniformGridBuilder::new().build(points);
+        assert!(grid.nearest_in_local_block([19.0, 19.0, 0.5]).is_none());
+        assert!(grid.nearest_neighbor([19.0, 19.0, 0.5]).is_some());
+    }
+
+    #[test]
+    fn voxel_coordinates_round_trip() {
+        let grid = UniformGridBuilder::new().build(random_points(1000, 642));
+        let (x_width, y_width, z_width) = grid.dimensions();
+        for (x, y, z) in itertools::iproduct!(0..x_width, 0..y_width, 0..z_width) {
+            let voxel = (x as u32, y as u32, z as u32);
+            assert_eq!(
+                grid.world_to_voxel(grid.voxel_to_world_center(voxel)),
+                Some(voxel)
+            );
+        }
+        let outside = (x_width as u32, 0, 0);
+        assert_eq!(
+            grid.world_to_voxel(grid.voxel_to_world_center(outside)),
+            None
+        );
+        assert_eq!(grid.world_to_voxel([-1.0, 0.5, 0.5]), None);
+
+        let grid = UniformGridBuilder::new()
+            .scale(0.01)
+            .min_dimensions((4, 4, 4))
+            .boundary_epsilon(1.0)
+            .build(vec![[0.0; 3], [4.0; 3]]);
+        assert_eq!(grid.cell_widths, [1.0; 3]);
+        assert_eq!(grid.world_to_voxel([4.0; 3]), Some((3, 3, 3)));
+        assert_eq!(grid.world_to_voxel([4.0, 0.5, 0.5]), Some((3, 0, 0)));
+        assert_eq!(grid.debug_locate([4.0; 3]).offset, Offset3::new(3, 3, 3));
+        assert_eq!(
+            grid.debug_locate([4.0; 3]).index,
+            Some(grid.point_cell_index(1))
+        );
+    }
+
+    #[test]
+    fn budgeted_query_with_tiny_budget_is_best_effort() {
+        let points = random_points(5000, 644);
+        let grid = UniformGridBuilder::new().build(points.clone());
+        let mut best_effort_count = 0;
+        for &query_point in points.iter().take(100) {
+            let query_point = query_point.map(|c| c + 0.001);
+            let nearest = grid.nearest_neighbor(query_point);
+
+            // Only the query cell fits in the budget. A point that's found there is
+            // only guaranteed to be the nearest if it's closer than every wall of
+            // the cell.
+            let (found, is_exact) = grid.nearest_neighbor_budgeted(query_point, 1);
+            if is_exact {
+                assert_eq!(found, nearest);
+            } else if let Some((point, distance)) = found {
+                assert!(points.contains(point));
+                assert_eq!(distance.squared(), dist2(query_point, *point));
+                assert!(distance >= nearest.unwrap().1);
+                best_effort_count += 1;
+            }
+
+            let (found, is_exact) = grid.nearest_neighbor_budgeted(query_point, usize::MAX);
+            assert!(is_exact);
+            assert_eq!(found, nearest);
+        }
+        assert!(best_effort_count > 0);
+        assert_eq!(grid.nearest_neighbor_budgeted([0.5; 3], 0), (None, false));
+    }
+
+    #[test]
+    fn boundary_rule_decides_cell_of_lattice_points() {
+        let lattice = itertools::iproduct!(0..=4, 0..=4, 0..=4)
+            .map(|(x, y, z)| [x as f32, y as f32, z as f32])
+            .collect_vec();
+        let build = |boundary| {
+            UniformGridBuilder::new()
+                .scale(0.01)
+                .min_dimensions((4, 4, 4))
+                .boundary_epsilon(1.0)
+                .bucket_boundary(boundary)
+                .build(lattice.clone())
+        };
+        for (boundary, expected_cell) in [
+            (BucketBoundary::Upper, [0, 1, 2, 3, 3]),
+            (BucketBoundary::Lower, [0, 0, 1, 2, 3]),
+            (BucketBoundary::HalfToEven, [0, 0, 2, 2, 3]),
+        ] {
+            let grid = build(boundary);
+            assert_eq!(grid.dimensions(), (4, 4, 4));
+            assert_eq!(grid.cell_widths, [1.0; 3]);
+            for (coordinate, &expected_cell) in expected_cell.iter().enumerate() {
+                let location = grid.debug_locate([coordinate as f32, 0.5, 3.5]);
+                assert_eq!(
+                    location.offset,
+                    Offset3::new(expected_cell, 0, 3),
+                    "{boundary:?} at {coordinate}"
+                );
+            }
+            assert_eq!(grid.validate(), Ok(()));
+        }
+    }
+
+    #[test]
+    fn find_outward_returns_closest_point_beyond_threshold() {
+        let points = random_points(3000, 646);
+        let grid = UniformGridBuilder::new().build(points.clone());
+        let threshold = 0.1;
+        for query_point in random_points(50, 6460) {
+            let (found, distance) = grid
+                .find_outward(query_point, |_, distance| distance.linear() > threshold)
+                .unwrap();
+            assert!(distance.linear() > threshold);
+            let beyond_threshold = points
+                .iter()
+                .copied()
+                .filter(|&p| dist2(query_point, p).sqrt() > threshold)
+                .collect_vec();
+            assert_eq!(
+                dist2(query_point, *found),
+                brute_force_nearest2(&beyond_threshold, query_point)
+            );
+        }
+        assert!(grid.find_outward([0.5; 3], |_, _| false).is_none());
+    }
+
+    #[test]
+    fn grid_with_precomputed_bounds_matches_normal_grid() {
+        let points = random_points(3000, 647);
+        let bb = BoundingBox::new(&points);
+        let with_bounds =
+            UniformGrid::new_with_bounds(points.clone(), bb, 1.0, spiral_cells::spiral_cells(10));
+        let normal = UniformGrid::new(points, 1.0, spiral_cells::spiral_cells(10));
+        assert_eq!(with_bounds.dimensions(), normal.dimensions());
+        for query_point in random_points(200, 6470) {
+            assert_eq!(
+                with_bounds.nearest_neighbor(query_point),
+                normal.nearest_neighbor(query_point)
+            );
+            assert_eq!(
+                with_bounds.neighbors_within_radius(query_point, 0.1),
+                normal.neighbors_within_radius(query_point, 0.1)
+            );
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "point 2 lies outside of the bounding box")]
+    fn grid_with_bounds_that_miss_a_point_panics() {
+        let points = vec![[0.0; 3], [1.0; 3], [2.0; 3]];
+        let bb = BoundingBox::new(&points[..2]);
+        let _ = UniformGrid::new_with_bounds(points, bb, 1.0, spiral_cells::spiral_cells(2));
+    }
+
+    #[test]
+    fn positions_match_positions_of_points() {
+        let mut grid = UniformGridBuilder::new().build(random_points(500, 648));
+        grid.extend(
+            random_points(100, 6480)
+                .into_iter()
+                .map(|p| p.map(|c| c * 3.0)),
+        );
+        grid.insert([-1.0, 2.0, 0.5]);
+        assert_eq!(grid.positions().len(), grid.points().len());
+        for (position, point) in grid.positions().iter().zip(grid.points()) {
+            assert_eq!(*position, point.position());
+        }
+
+        let grid = UniformGridBuilder::new().build(vec![
+            CategorizedPoint {
+                position: [0.0, 1.0, 2.0],
+                category: 0,
+            },
+            CategorizedPoint {
+                position: [3.0, 4.0, 5.0],
+                category: 1,
+            },
+        ]);
+        assert_eq!(grid.positions(), &[[0.0, 1.0, 2.0], [3.0, 4.0, 5.0]]);
+    }
+
+    #[test]
+    fn original_is_unchanged_by_inserts_into_clone() {
+        let points = random_points(1000, 649);
+        let original = UniformGridBuilder::new().build(points.clone());
+        let mut clone = original.clone();
+        clone.insert([0.5; 3]);
+        clone.insert([4.0; 3]);
+
+        assert_eq!(original.len(), points.len());
+        assert_eq!(original.points(), &points[..]);
+        assert_eq!(original.validate(), Ok(()));
+        assert_eq!(clone.len(), points.len() + 2);
+        assert_eq!(clone.validate(), Ok(()));
+
+        let (found, distance) = clone.nearest_neighbor([4.1; 3]).unwrap();
+        assert_eq!(*found, [4.0; 3]);
+        assert!(distance.linear() < 0.2);
+        let (found, _) = clone.nearest_neighbor([0.5; 3]).unwrap();
+        assert_eq!(*found, [0.5; 3]);
+        let (found, _) = original.nearest_neighbor([4.1; 3]).unwrap();
+        assert_eq!(
+            dist2([4.1; 3], *found),
+            brute_force_nearest2(&points, [4.1; 3])
+        );
+    }
+
+    #[test]
+    fn spiral_visit_visits_every_point_once() {
+        let points = random_points(2000, 651);
+        let grid = UniformGrid::new(points.clone(), 1.0, spiral_cells::spiral_cells(3));
+        let (x_width, y_width, z_width) = grid.dimensions();
+        for query_cell_offset in [
+            Offset3::new(0, 0, 0),
+            Offset3::new(x_width as i64 / 2, y_width as i64 / 2, z_width as i64 / 2),
+            Offset3::new(x_width as i64 - 1, 0, z_width as i64 - 1),
+            Offset3::new(-5, 40, 2),
+            Offset3::new(1 << 40, 0, 0),
+            Offset3::new(-(1 << 40), 1, 1 << 40),
+        ] {
+            let mut visit_counts = vec![0; points.len()];
+            grid.spiral_visit(query_cell_offset, |group| {
+                for &(position, point_index) in group {
+                    visit_counts[point_index] += 1;
+                    assert_eq!(position, points[point_index]);
+                }
+                ControlFlow::Continue(())
+            });
+            assert!(visit_counts.iter().all(|&count| count == 1));
+        }
+
+        let mut group_count = 0;
+        grid.spiral_visit(Offset3::new(0, 0, 0), |_| {
+            group_count += 1;
+            ControlFlow::Break(())
+        });
+        assert_eq!(group_count, 1);
+    }
+
+    #[test]
+    fn zero_scale_still_answers_queries() {
+        let points = random_points(500, 652);
+        let grids = [
+            UniformGrid::new(points.clone(), 0.0, spiral_cells::spiral_cells(2)),
+            UniformGridBuilder::new().scale(0.0).build(points.clone()),
+            UniformGridBuilder::new().scale(-3.0).build(points.clone()),
+        ];
+        for grid in &grids {
+            let (x_width, y_width, z_width) = grid.dimensions();
+            assert!(x_width >= 1 && y_width >= 1 && z_width >= 1);
+            assert_eq!(grid.validate(), Ok(()));
+            for query_point in random_points(50, 6520) {
+                let (found, _) = grid.nearest_neighbor(query_point).unwrap();
+                assert_eq!(
+                    dist2(query_point, *found),
+                    brute_force_nearest2(&points, query_point)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn nearest_neighbor_by_prefers_comparator_among_ties() {
+        // The categories serve as priorities.
+        let points = vec![
+            CategorizedPoint {
+                position: [-1.0, 0.0, 0.0],
+                category: 1,
+            },
+            CategorizedPoint {
+                position: [1.0, 0.0, 0.0],
+                category: 5,
+            },
+            CategorizedPoint {
+                position: [0.0, 3.0, 0.0],
+                category: 9,
+            },
+        ];
+        let grid = UniformGridBuilder::new().build(points);
+        let (found, distance) = grid
+            .nearest_neighbor_by([0.0; 3], |a, b| b.category.cmp(&a.category))
+            .unwrap();
+        assert_eq!(found.category, 5);
+        assert_eq!(distance.squared(), 1.0);
+        let (found, _) = grid
+            .nearest_neighbor_by([0.0; 3], |a, b| a.category.cmp(&b.category))
+            .unwrap();
+        assert_eq!(found.category, 1);
+        let (found, _) = grid
+            .nearest_neighbor_by([0.0; 3], |_, _| Ordering::Equal)
+            .unwrap();
+        assert_eq!(found.category, 1);
+        let (found, _) = grid
+            .nearest_neighbor_by([0.9, 0.0, 0.0], |a, b| a.category.cmp(&b.category))
+            .unwrap();
+        assert_eq!(found.category, 5);
+    }
+
+    #[test]
+    fn compact_after_removals_keeps_survivors_queryable() {
+        let mut grid = UniformGridBuilder::new().build(random_points(10, 658));
+        for point in random_points(1000, 6580) {
+            grid.insert(point);
+        }
+        let mut rng = StdRng::seed_from_u64(6581);
+        for _ in 0..505 {
+            grid.remove(rng.gen_range(0..grid.len()));
+        }
+        let survivors = grid.points().to_vec();
+        let bytes_before = grid.memory_bytes();
+        grid.compact();
+
+        assert_eq!(grid.len(), 505);
+        assert_eq!(grid.points(), &survivors[..]);
+        assert!(grid.memory_bytes() < bytes_before);
+        assert_eq!(grid.validate(), Ok(()));
+        for (point_index, &point) in survivors.iter().enumerate() {
+            assert!(grid.contains_point(point));
+            let (found, distance) = grid.nearest_neighbor(point).unwrap();
+            assert_eq!(distance.squared(), 0.0);
+            assert_eq!(*found, point);
+            assert_eq!(grid.positions()[point_index], point);
+        }
+
+        grid.insert([0.25; 3]);
+        assert_eq!(grid.validate(), Ok(()));
+        assert!(grid.contains_point([0.25; 3]));
+    }
+
+    #[test]
+    fn two_pass_construction_consumes_points_twice() {
+        let points = random_points(1000, 655);
+        let pass_count = std::cell::Cell::new(0);
+        let grid = UniformGrid::from_iter_two_pass(
+            || {
+                pass_count.set(pass_count.get() + 1);
+                points.iter().copied()
+            },
+            1.0,
+            spiral_cells::spiral_cells(10),
+        );
+        assert_eq!(pass_count.get(), 2);
+        assert_eq!(grid.points(), &points[..]);
+        assert_eq!(grid.validate(), Ok(()));
+        let collected = UniformGrid::new(points, 1.0, spiral_cells::spiral_cells(10));
+        assert_eq!(grid.dimensions(), collected.dimensions());
+        for query_point in random_points(50, 6550) {
+            assert_eq!(
+                grid.nearest_neighbor(query_point),
+                collected.nearest_neighbor(query_point)
+            );
+        }
+    }
+
+    #[test]
+    fn rings_searched_grows_with_sparseness() {
+        let dense = UniformGridBuilder::new()
+            .target_occupancy(4.0)
+            .build(random_points(20_000, 656));
+        let rings_searched = random_points(100, 6560)
+            .into_iter()
+            .map(|query_point| {
+                dense
+                    .nearest_neighbor_traced(query_point)
+                    .unwrap()
+                    .rings_searched
+            })
+            .collect_vec();
+        assert!(rings_searched.iter().all(|&rings| rings <= 3));
+        assert!(rings_searched.iter().filter(|&&rings| rings <= 1).count() >= 90);
+
+        // A cell width suited to the dense cluster leaves the distant points many
+        // rings away from queries near the cluster.
+        let mut points = random_points(2000, 6561);
+        points.extend([[10.0; 3], [-10.0, 5.0, 0.0]]);
+        let sparse = UniformGrid::new(points, 1.0, spiral_cells::spiral_cells(10));
+        let traced = sparse.nearest_neighbor_traced([9.0; 3]).unwrap();
+        assert_eq!(*traced.point, [10.0; 3]);
+        assert!(traced.rings_searched > 0);
+    }
+
+    /// A point on a surface, with the surface's normal.
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    struct OrientedPoint {
+        position: [f32; 3],
+        normal: [f32; 3],
+    }
+
+    impl PointObject for OrientedPoint {
+        fn position(&self) -> [f32; 3] {
+            self.position
+        }
+
+        fn normal(&self) -> Option<[f32; 3]> {
+            Some(self.normal)
+        }
+    }
+
+    #[test]
+    fn nearest_neighbor_with_normal_returns_normal_of_point() {
+        let points = random_points(500, 657)
+            .into_iter()
+            .zip(random_points(500, 6570))
+            .map(|(position, normal)| OrientedPoint { position, normal })
+            .collect_vec();
+        let grid = UniformGridBuilder::new().build(points);
+        for query_point in random_points(50, 6571) {
+            let (found, distance, normal) = grid.nearest_neighbor_with_normal(query_point).unwrap();
+            assert_eq!(normal, Some(found.normal));
+            assert_eq!(
+                (found, distance),
+                grid.nearest_neighbor(query_point).unwrap()
+            );
+        }
+
+        let grid = UniformGridBuilder::new()
+            .axis_order([Axis::X, Axis::Z, Axis::Y])
+            .build(vec![OrientedPoint {
+                position: [1.0, 2.0, 3.0],
+                normal: [0.0, 0.0, 1.0],
+            }]);
+        let (_, _, normal) = grid.nearest_neighbor_with_normal([1.0, 3.0, 2.0]).unwrap();
+        assert_eq!(normal, Some([0.0, 1.0, 0.0]));
+
+        let grid = UniformGridBuilder::new().build(vec![[1.0; 3]]);
+        let (_, _, normal) = grid.nearest_neighbor_with_normal([0.0; 3]).unwrap();
+        assert_eq!(normal, None);
+    }
+
+    #[test]
+    fn same_or_adjacent_cell_compares_cell_offsets() {
+        let grid = UniformGridBuilder::new()
+            .scale(0.01)
+            .min_dimensions((4, 4, 4))
+            .boundary_epsilon(1.0)
+            .build(vec![[0.0; 3], [4.0; 3]]);
+        assert_eq!(grid.cell_widths, [1.0; 3]);
+        assert!(grid.same_or_adjacent_cell([0.5; 3], [0.6; 3]));
+        assert!(grid.same_or_adjacent_cell([0.5; 3], [1.5; 3]));
+        assert!(grid.same_or_adjacent_cell([0.9, 0.9, 0.9], [1.1, 1.1, 1.1]));
+        assert!(!grid.same_or_adjacent_cell([0.5; 3], [2.5, 0.5, 0.5]));
+        assert!(!grid.same_or_adjacent_cell([0.5; 3], [1.5, 1.5, 2.5]));
+        assert!(!grid.same_or_adjacent_cell([3.5; 3], [4.5; 3]));
+        assert!(!grid.same_or_adjacent_cell([f32::NAN; 3], [0.5; 3]));
+    }
+
+    #[test]
+    fn auto_scale_gives_one_to_two_points_per_occupied_cell() {
+        let points = random_points(5000, 661);
+        let mut spiral_width = 0;
+        let grid = UniformGrid::new_auto_scale(points.clone(), |width| {
+            spiral_width = width;
+            spiral_cells::spiral_cells(2)
+        });
+        let (x_width, y_width, z_width) = grid.dimensions();
+        assert_eq!(spiral_width, x_width.max(y_width).max(z_width));
+        let mean_occupancy = grid.mean_occupancy();
+        assert!(
+            (1.0..=2.0).contains(&mean_occupancy),
+            "mean occupancy {mean_occupancy}"
+        );
+        assert_eq!(grid.validate(), Ok(()));
+        let query_point = [0.5; 3];
+        let (found, _) = grid.nearest_neighbor(query_point).unwrap();
+        assert_eq!(
+            dist2(query_point, *found),
+            brute_force_nearest2(&points, query_point)
+        );
+    }
+
+    #[test]
+    fn two_nearest_finds_nearest_and_second_nearest() {
+        // The second-nearest point is several cells further out than the nearest.
+        let mut points = random_points(500, 662)
+            .into_iter()
+            .map(|p| p.map(|c| c * 10.0))
+            .filter(|&p| dist2(p, [5.0; 3]) > 4.0)
+            .collect_vec();
+        points.extend([[5.1, 5.0, 5.0], [5.0, 6.5, 5.0]]);
+        let grid = UniformGridBuilder::new().build(points.clone());
+        let (first, second) = grid.two_nearest([5.0; 3]);
+        let (first, first_distance) = first.unwrap();
+        let (second, second_distance) = second.unwrap();
+        assert_eq!(*first, [5.1, 5.0, 5.0]);
+        assert_eq!(*second, [5.0, 6.5, 5.0]);
+        assert!(first_distance < second_distance);
+
+        for query_point in random_points(100, 6620) {
+            let query_point = query_point.map(|c| c * 10.0);
+            let mut distances2 = points.iter().map(|&p| dist2(query_point, p)).collect_vec();
+            distances2.sort_by(f32::total_cmp);
+            let (first, second) = grid.two_nearest(query_point);
+            assert_eq!(dist2(query_point, *first.unwrap().0), distances2[0]);
+            assert_eq!(dist2(query_point, *second.unwrap().0), distances2[1]);
+        }
+
+        let grid = UniformGridBuilder::new().build(vec![[1.0; 3]]);
+        let (first, second) = grid.two_nearest([0.0; 3]);
+        assert_eq!(*first.unwrap().0, [1.0; 3]);
+        assert!(second.is_none());
+    }
+
+    #[cfg(feature = "rand")]
+    #[test]
+    fn sampled_cells_follow_occupancy() {
+        // Half of the points are packed into a small corner of the cloud.
+        let mut points = random_points(1000, 663);
+        points.extend(
+            random_points(1000, 6630)
+                .into_iter()
+                .map(|p| p.map(|c| c * 0.1)),
+        );
+        let grid = UniformGridBuilder::new().build(points);
+        let (counts, _) = grid.occupancy_volume();
+
+        let draw_count = 200_000;
+        let mut draws_per_cell = vec![0usize; counts.len()];
+        let mut rng = StdRng::seed_from_u64(6631);
+        for _ in 0..draw_count {
+            let point_index = grid.sample_point(&mut rng).unwrap();
+            draws_per_cell[grid.point_cell_index(point_index)] += 1;
+        }
+
+        let total_variation = counts
+            .iter()
+            .zip(&draws_per_cell)
+            .map(|(&count, &draws)| {
+                (count as f64 / grid.len() as f64 - draws as f64 / draw_count as f64).abs()
+            })
+            .sum::<f64>()
+            / 2.0;
+        assert!(total_variation < 0.05, "total variation {total_variation}");
+        assert!(counts
+            .iter()
+            .zip(&draws_per_cell)
+            .all(|(&count, &draws)| count > 0 || draws == 0));
+
+        let empty = UniformGridBuilder::new().build(Vec::<[f32; 3]>::new());
+        assert_eq!(empty.sample_point(&mut rng), None);
+    }
+
+    #[test]
+    fn coarsened_grid_has_fewer_cells_and_same_nearest() {
+        let points = random_points(5000, 665);
+        let grid = UniformGridBuilder::new()
+            .min_dimensions((12, 12, 12))
+            .build(points.clone());
+        let (x_width, y_width, z_width) = grid.dimensions();
+        for factor in [2, 3] {
+            let coarse = grid.coarsened(factor);
+            assert_eq!(
+                coarse.cell_widths,
+                grid.cell_widths
+                    .map(|cell_width| cell_width * factor as f32)
+            );
+            assert_eq!(
+                coarse.dimensions(),
+                (
+                    x_width.div_ceil(factor),
+                    y_width.div_ceil(factor),
+                    z_width.div_ceil(factor)
+                )
+            );
+            let cell_ratio =
+                coarse.occupancy_volume().0.len() as f32 / grid.occupancy_volume().0.len() as f32;
+            let expected_ratio = 1.0 / (factor * factor * factor) as f32;
+            assert!((cell_ratio - expected_ratio).abs() < 0.5 * expected_ratio);
+            assert_eq!(coarse.validate(), Ok(()));
+            for query_point in random_points(100, 6650) {
+                let (found, _) = coarse.nearest_neighbor(query_point).unwrap();
+                assert_eq!(
+                    dist2(query_point, *found),
+                    brute_force_nearest2(&points, query_point)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn build_phases_are_reported_in_order() {
+        let mut phases = vec![];
+        let grid = UniformGrid::new_with_progress(
+            random_points(5000, 666),
+            1.0,
+            spiral_cells::spiral_cells(10),
+            |phase, _| phases.push(phase),
+        );
+        assert_eq!(
+            phases,
+            vec![
+                BuildPhase::BoundingBox,
+                BuildPhase::Counting,
+                BuildPhase::Allocation,
+                BuildPhase::Fill,
+            ]
+        );
+        assert_eq!(grid.len(), 5000);
+        assert_eq!(grid.validate(), Ok(()));
+    }
+
+    #[test]
+    fn nearest_neighbor_delta_points_from_query_to_neighbor() {
+        let grid = UniformGridBuilder::new().build(random_points(1000, 668));
+        for query_point in random_points(100, 6680) {
+            let (found, delta, distance) = grid.nearest_neighbor_delta(query_point).unwrap();
+            for axis in 0..3 {
+                assert!((query_point[axis] + delta[axis] - found[axis]).abs() < 1.0e-6);
+            }
+            assert!((dist2([0.0; 3], delta) - distance.squared()).abs() < 1.0e-6);
+        }
+
+        let periodic = UniformGridBuilder::new()
+            .periodic([0.0; 3], [10.0; 3])
+            .build(vec![[9.9, 5.0, 5.0], [5.0, 5.0, 5.0]]);
+        let (found, delta, _) = periodic.nearest_neighbor_delta([0.1, 5.0, 5.0]).unwrap();
+        assert_eq!(*found, [9.9, 5.0, 5.0]);
+        assert!((delta[0] + 0.2).abs() < 1.0e-4);
+        assert_eq!([delta[1], delta[2]], [0.0, 0.0]);
+    }
+
+    #[test]
+    fn data_bounds_follow_inserted_and_removed_points() {
+        let mut grid = UniformGridBuilder::new().build(Vec::<[f32; 3]>::new());
+        assert!(grid.data_bounds().is_none());
+
+        grid.insert([1.0, 2.0, 3.0]);
+        let bounds = grid.data_bounds().unwrap();
+        assert_eq!(bounds.min, [1.0, 2.0, 3.0]);
+        assert_eq!(bounds.max(), [1.0, 2.0, 3.0]);
+
+        grid.insert([-1.0, 5.0, 3.5]);
+        grid.insert([0.0, 3.0, 3.2]);
+        let bounds = grid.data_bounds().unwrap();
+        assert_eq!(bounds.min, [-1.0, 2.0, 3.0]);
+        assert_eq!(bounds.max(), [1.0, 5.0, 3.5]);
+
+        grid.remove(1);
+        let bounds = grid.data_bounds().unwrap();
+        assert_eq!(bounds.min, [0.0, 2.0, 3.0]);
+        assert_eq!(bounds.max(), [1.0, 3.0, 3.2]);
+
+        let reordered = UniformGridBuilder::new()
+            .axis_order([Axis::Z, Axis::X, Axis::Y])
+            .build(vec![[1.0, 2.0, 3.0], [-1.0, 5.0, 3.5]]);
+        let bounds = reordered.data_bounds().unwrap();
+        assert_eq!(bounds.min, [3.0, -1.0, 2.0]);
+        assert_eq!(bounds.max(), [3.5, 1.0, 5.0]);
+    }
+
+    #[test]
+    fn ring_occupancy_counts_lattice_points_by_ring() {
+        let mut points = itertools::iproduct!(0..4, 0..4, 0..4)
+            .map(|(x, y, z)| [x as f32 + 0.5, y as f32 + 0.5, z as f32 + 0.5])
+            .collect_vec();
+        points.extend([[0.0; 3], [4.0; 3]]);
+        let grid = UniformGridBuilder::new()
+            .scale(0.01)
+            .min_dimensions((4, 4, 4))
+            .boundary_epsilon(1.0)
+            .build(points);
+        assert_eq!(grid.cell_widths, [1.0; 3]);
+
+        // A ring `n` cells out from a corner cell covers `(n + 1)^3 - n^3` cells.
+        assert_eq!(grid.ring_occupancy([0.5; 3], 4), vec![2, 7, 19, 38, 0]);
+        assert_eq!(grid.ring_occupancy([1.5; 3], 2), vec![1, 27, 38]);
+    }
+
+    #[test]
+    fn interleaved_buffer_builds_same_grid_as_positions() {
+        let points = random_points(1000, 672);
+        let buffer = points.iter().flatten().copied().collect_vec();
+        match UniformGrid::from_interleaved(&buffer[..10], 1.0, spiral_cells::spiral_cells(10)) {
+            Err(error) => assert_eq!(error, BufferError::Length(10)),
+            Ok(_) => panic!("a buffer of partial positions was accepted"),
+        }
+
+        let interleaved =
+            match UniformGrid::from_interleaved(&buffer, 1.0, spiral_cells::spiral_cells(10)) {
+                Ok(grid) => grid,
+                Err(error) => panic!("{error}"),
+            };
+        let grid = UniformGrid::new(points, 1.0, spiral_cells::spiral_cells(10));
+        assert_eq!(interleaved.points(), grid.points());
+        for query_point in random_points(100, 6720) {
+            assert_eq!(
+                interleaved.nearest_neighbor(query_point),
+                grid.nearest_neighbor(query_point)
+            );
+        }
+    }
+
+    #[test]
+    fn visited_cells_start_at_query_cell_and_stop_at_nearest_ring() {
+        let points = itertools::iproduct!(0..4, 0..4, 0..4)
+            .map(|(x, y, z)| [x as f32 + 0.5, y as f32 + 0.5, z as f32 + 0.5])
+            .chain([[0.0; 3], [4.0; 3]])
+            .collect_vec();
+        let grid = UniformGridBuilder::new()
+            .scale(0.01)
+            .min_dimensions((4, 4, 4))
+            .boundary_epsilon(1.0)
+            .build(points);
+        assert_eq!(grid.cell_widths, [1.0; 3]);
+
+        let query_point = [1.6, 1.5, 1.4];
+        let (nearest, visited) = grid.nearest_neighbor_visited_cells(query_point);
+        assert_eq!(*nearest.unwrap().0, [1.5; 3]);
+        assert_eq!(visited[0], grid.point_into_offset(query_point));
+        assert_eq!(visited.len(), grid.nearest_neighbor_counted(query_point).1);
+
+        // The nearest point is closer than the faces of the query cell, so
+        // the search doesn't need to look past it.
+        assert_eq!(visited, vec![grid.point_into_offset(query_point)]);
+
+        // Near a face, the first ring around the query cell can hold a
+        // closer point, but the second can't.
+        let query_point = [1.95, 1.5, 1.5];
+        let (nearest, visited) = grid.nearest_neighbor_visited_cells(query_point);
+        assert_eq!(*nearest.unwrap().0, [1.5; 3]);
+        assert_eq!(visited[0], grid.point_into_offset(query_point));
+        assert_eq!(visited.len(), grid.nearest_neighbor_counted(query_point).1);
+        let distinct = visited
+            .iter()
+            .copied()
+            .collect::<std::collections::HashSet<_>>();
+        assert_eq!(distinct.len(), 27);
+        assert!(distinct.iter().all(|offset| {
+            [offset.x, offset.y, offset.z]
+                .iter()
+                .all(|&coordinate| (0..=2).contains(&coordinate))
+        }));
+    }
+
+    #[test]
+    fn no_table_search_from_far_outside_grid_finishes() {
+        let points = random_points(1000, 620);
+        let grid = UniformGridBuilder::new().build(points.clone());
+        // Far from the grid, many points can tie in `f32`, so compare distances.
+        for query_point in [[1.0e5, 0.0, 0.0], [0.5, -1.0e8, 0.5], [1.0e30; 3]] {
+            let (_, expected) = grid.nearest_neighbor(query_point).unwrap();
+            let (_, distance) = grid.nearest_neighbor_no_table(query_point).unwrap();
+            assert_eq!(distance, expected);
+            let (_, distance) = grid
+                .nearest_neighbor_within(query_point, f32::INFINITY)
+                .unwrap();
+            assert_eq!(distance, expected);
+        }
+        let (found, _) = grid.nearest_neighbor_no_table([2.0, 0.5, 0.5]).unwrap();
+        assert_eq!(
+            dist2([2.0, 0.5, 0.5], *found),
+            brute_force_nearest2(&points, [2.0, 0.5, 0.5])
+        );
+    }
+
+    #[test]
+    fn two_nearest_from_far_outside_grid_finishes() {
+        let grid = UniformGridBuilder::new().build(random_points(1000, 662));
+        for query_point in [[1.0e5, 0.0, 0.0], [0.5, -1.0e8, 0.5], [1.0e30; 3]] {
+            let (_, expected) = grid.nearest_neighbor(query_point).unwrap();
+            let (first, second) = grid.two_nearest(query_point);
+            let (first, second) = (first.unwrap(), second.unwrap());
+            assert_eq!(first.1, expected);
+            assert!(second.1 >= first.1);
+            assert!(!std::ptr::eq(first.0, second.0));
+        }
+    }
+
+    #[test]
+    fn metric_queries_from_far_outside_grid_finish() {
+        let points = random_points(1000, 768);
+        let grid = UniformGridBuilder::new().build(points.clone());
+        for query_point in [[1.0e5, 0.5, 0.5], [0.5, -1.0e8, 0.5], [1.0e30; 3]] {
+            let expected = points
+                .iter()
+                .map(|&p| Manhattan.distance(query_point, p))
+                .fold(f32::INFINITY, f32::min);
+            let (_, distance) = grid
+                .nearest_neighbor_by_metric(query_point, &Manhattan)
+                .unwrap();
+            assert_eq!(distance, expected);
+            assert!(grid
+                .neighbors_within_radius_by_metric(query_point, 1.0, &Manhattan)
+                .is_empty());
+        }
+        let neighbors =
+            grid.neighbors_within_radius_by_metric([1.0e5, 0.5, 0.5], 2.0e5, &Manhattan);
+        assert_eq!(neighbors.len(), points.len());
+    }
+
+    #[test]
+    fn radius_queries_skip_points_with_nan_coordinates() {
+        let points = vec![[0.0, 0.0, 0.0], [f32::NAN, 0.0, 0.0], [1.0, 0.0, 0.0]];
+        let grid = UniformGridBuilder::new().build(points);
+        let query_point = [0.1, 0.0, 0.0];
+
+        let neighbors = grid.neighbors_within_radius(query_point, 10.0);
+        assert_eq!(neighbors.len(), 2);
+        assert!(neighbors.iter().all(|(p, _)| !p[0].is_nan()));
+
+        let mut visited = vec![];
+        grid.for_each_within_radius(query_point, 10.0, |p, _| {
+            visited.push(*p);
+            ControlFlow::Continue(())
+        });
+        assert_eq!(visited.len(), 2);
+        assert!(visited.iter().all(|p| !p[0].is_nan()));
+
+        let mut indices = vec![];
+        grid.neighbors_within_radius_into(query_point, 10.0, &mut indices);
+        assert!(indices.iter().all(|&(point_index, _)| point_index != 1));
+    }
+
+    #[test]
+    fn knn_graph_never_links_points_with_nan_coordinates() {
+        let points = vec![
+            [0.0, 0.0, 0.0],
+            [1.0, 0.0, 0.0],
+            [f32::NAN, 0.0, 0.0],
+            [2.0, 0.0, 0.0],
+        ];
+        let grid = UniformGridBuilder::new().build(points);
+        let graph = grid.knn_graph(1);
+        assert_eq!(
+            graph,
+            vec![
+                vec![(1, Distance::from_squared(1.0))],
+                vec![(0, Distance::from_squared(1.0))],
+                vec![],
+                vec![(1, Distance::from_squared(1.0))],
+            ]
+        );
+        assert!(grid.knn_graph(3).iter().flatten().all(|&(i, _)| i != 2));
+        #[cfg(feature = "rayon")]
+        assert_eq!(grid.knn_graph_parallel(1), graph);
+    }
+
+    #[test]
+    fn nearest_neighbors_tied_skips_points_with_nan_coordinates() {
+        let points = vec![
+            [0.2, 0.5, 0.5],
+            [0.8, 0.5, 0.5],
+            [f32::NAN, 0.5, 0.5],
+            [0.0; 3],
+            [4.0; 3],
+        ];
+        let grid = UniformGridBuilder::new()
+            .scale(0.01)
+            .min_dimensions((4, 4, 4))
+            .boundary_epsilon(1.0)
+            .build(points);
+        let query_point = [0.5; 3];
+        assert_eq!(
+            Some(grid.point_cell_index(2)),
+            grid.debug_locate(query_point).index
+        );
+
+        let tied = grid.nearest_neighbors_tied(query_point, 1.0e-4);
+        assert_eq!(tied.len(), 2);
+        assert_eq!(*tied[0].0, [0.2, 0.5, 0.5]);
+        assert_eq!(*tied[1].0, [0.8, 0.5, 0.5]);
+    }
+
+    #[test]
+    fn index_with_half_precision_option_reads_back_in_every_build() {
+        let points = random_points(2000, 6251);
+        let grid = UniformGridBuilder::new().build(points.clone());
+
+        // An index exported by a build with the `half` feature has the same shape,
+        // whether or not this build has the feature.
+        let mut index = grid.export_index();
+        index.options.half_precision_positions = true;
+        let bytes = bincode::serialize(&index).unwrap();
+        assert_eq!(
+            bytes.len(),
+            bincode::serialize(&grid.export_index()).unwrap().len()
+        );
+        let index: GridIndex = bincode::deserialize(&bytes).unwrap();
+        let rebuilt = UniformGrid::from_index(index, points).unwrap();
+        assert_eq!(rebuilt.validate(), Ok(()));
+        for query_point in random_points(200, 62510) {
+            let (_, distance) = grid.nearest_neighbor(query_point).unwrap();
+            let (_, rebuilt_distance) = rebuilt.nearest_neighbor(query_point).unwrap();
+            assert!(rebuilt_distance >= distance);
+            assert!(rebuilt_distance.linear() - distance.linear() <= grid.cell_widths[0] / 1000.0);
+        }
+    }
+
+    #[test]
+    fn queries_on_per_axis_cells_match_brute_force() {
+        let mut rng = StdRng::seed_from_u64(7580);
+        let points = (0..3000)
+            .map(|_| [rng.gen(), rng.gen(), rng.gen::<f32>() * 100.0])
+            .collect_vec();
+        let grid = UniformGridBuilder::new()
+            .cell_size(CellSize::PerAxis([0.1, 0.1, 10.0]))
+            .build(points.clone());
+        assert!(!grid.has_cubic_cells());
+        assert!(grid.spiral_covers_grid());
+
+        let mut query_points = (0..200)
+            .map(|_| [rng.gen(), rng.gen(), rng.gen::<f32>() * 100.0])
+            .collect_vec();
+        query_points.extend([[0.5, 0.5, -50.0], [3.0, -2.0, 150.0], [1.0e5, 0.5, 0.5]]);
+        for query_point in query_points {
+            let expected = brute_force_nearest2(&points, query_point);
+            let traced = grid.nearest_neighbor_traced(query_point).unwrap();
+            assert_eq!(dist2(query_point, *traced.point), expected);
+            assert!(traced.exact);
+            let (found, _) = grid.nearest_neighbor_no_table(query_point).unwrap();
+            assert_eq!(dist2(query_point, *found), expected);
+            let (first, second) = grid.two_nearest(query_point);
+            assert_eq!(dist2(query_point, *first.unwrap().0), expected);
+            assert!(second.unwrap().1 >= first.unwrap().1);
+
+            let radius = 2.0;
+            let within = grid.neighbors_within_radius(query_point, radius);
+            let expected_within = points
+                .iter()
+                .filter(|&&p| dist2(query_point, p) <= radius * radius)
+                .count();
+            assert_eq!(within.len(), expected_within);
+        }
+
+        for (point_index, neighbors) in grid.knn_graph(3).into_iter().enumerate().step_by(10) {
+            let mut expected = (0..points.len())
+                .filter(|&other| other != point_index)
+                .map(|other| dist2(points[point_index], points[other]))
+                .collect_vec();
+            expected.sort_by(f32::total_cmp);
+            let found = neighbors
+                .iter()
+                .map(|&(other, _)| dist2(points[point_index], points[other]))
+                .collect_vec();
+            assert_eq!(found, expected[..3]);
+        }
+
+        let mut visit_counts = vec![0; points.len()];
+        grid.spiral_visit(Offset3::new(3, 4, 5), |group| {
+            for &(_, point_index) in group {
+                visit_counts[point_index] += 1;
+            }
+            ControlFlow::Continue(())
+        });
+        assert!(visit_counts.iter().all(|&count| count == 1));
+
+        let voxel = (3, 7, 2);
+        assert_eq!(
+            grid.world_to_voxel(grid.voxel_to_world_center(voxel)),
+            Some(voxel)
+        );
+        let rebuilt = UniformGrid::from_index(grid.export_index(), points).unwrap();
+        assert_eq!(rebuilt.cell_widths(), grid.cell_widths());
+        assert_eq!(rebuilt.validate(), Ok(()));
+    }
+
+    #[test]
+    fn per_axis_cells_fill_layered_points_evenly() {
+        // Layers of points that are far apart along z, but dense within each layer.
+        let mut rng = StdRng::seed_from_u64(7581);
+        let points = (0..4000)
+            .map(|i| [rng.gen(), rng.gen(), (i % 4) as f32 * 100.0])
+            .collect_vec();
+        let cubic = UniformGridBuilder::new()
+            .spiral_extent(2)
+            .build(points.clone());
+        let boxes = UniformGridBuilder::new()
+            .cell_size(CellSize::PerAxis([0.05, 0.05, 100.0]))
+            .build(points);
+        assert!(boxes.mean_occupancy() < cubic.mean_occupancy() / 10.0);
+        assert!(boxes.mean_occupancy() < 4.0);
+        assert!(boxes.spiral_cells.is_empty());
+    }
 }
@@ -0,0 +1,68 @@
+use std::fmt;
+
+/// Error returned by `UniformGrid::validate` when one of the grid's internal
+/// invariants doesn't hold.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValidationError {
+    /// The number of cells doesn't match the grid's dimensions.
+    CellCount,
+
+    /// The count of points in a cell doesn't match the number of points that
+    /// are stored in the cell.
+    PointCount {
+        cell_index: usize,
+        counted: usize,
+        stored: usize,
+    },
+
+    /// A cell refers to a point index that's out of range, or that's already
+    /// stored in another cell or earlier in the same cell.
+    InvalidPointIndex(usize),
+
+    /// A point isn't stored in any cell.
+    MissingPoint(usize),
+
+    /// A point is stored in a cell that it doesn't bucket into.
+    WrongCell {
+        point_index: usize,
+        cell_index: usize,
+    },
+
+    /// The position that's stored for a point doesn't match the point's
+    /// position.
+    PositionMismatch(usize),
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValidationError::CellCount => write!(f, "cell count doesn't match the dimensions"),
+            ValidationError::PointCount {
+                cell_index,
+                counted,
+                stored,
+            } => write!(
+                f,
+                "cell {} is counted as having {} points but stores {}",
+                cell_index, counted, stored
+            ),
+            ValidationError::InvalidPointIndex(i) => {
+                write!(f, "point {} is out of range or stored more than once", i)
+            }
+            ValidationError::MissingPoint(i) => write!(f, "point {} isn't stored in any cell", i),
+            ValidationError::WrongCell {
+                point_index,
+                cell_index,
+            } => write!(
+                f,
+                "point {} is stored in cell {} but doesn't bucket into it",
+                point_index, cell_index
+            ),
+            ValidationError::PositionMismatch(i) => {
+                write!(f, "stored position of point {} doesn't match it", i)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ValidationError {}